@@ -8,6 +8,33 @@ use shlex::Shlex; // For splitting a string into command line arguments.
 /// A result from a command.
 pub type CommandResult = anyhow::Result<ControlFlow<(), ()>>;
 
+/// A hook that is called before a command.
+pub type BeforeHook<'h, T> = Box<dyn FnMut(&mut T) + 'h>;
+
+/// A hook that is called after a command, with the result of the command.
+pub type AfterHook<'h, T> = Box<dyn FnMut(&mut T, &CommandResult) + 'h>;
+
+/// Optional hooks which are invoked around every command in the repl.
+///
+/// Lines that are blank, comments or fail to parse are not considered commands
+/// and will not trigger any hooks.
+pub struct Hooks<'h, T> {
+    /// Called right before a command is run.
+    pub before: Option<BeforeHook<'h, T>>,
+    /// Called right after a command has been run, with the result of the
+    /// command.
+    pub after: Option<AfterHook<'h, T>>,
+}
+
+impl<T> Default for Hooks<'_, T> {
+    fn default() -> Self {
+        Self {
+            before: None,
+            after: None,
+        }
+    }
+}
+
 /// Run a repl from a `clap::Subcommand`.
 ///
 /// # Arguments
@@ -15,29 +42,39 @@ pub type CommandResult = anyhow::Result<ControlFlow<(), ()>>;
 /// Takes a function generating a prompt, a run function for something
 /// implementing `clap::Subcommand`, and a mutable reference to some data that
 /// will be passed to the run-function of the command.
+///
+/// Blank lines and lines whose first non-whitespace character is '#' are
+/// ignored, so that command files piped to the repl can be annotated with
+/// comments.
 pub async fn run_repl<Cmds, T>(
+    prompt: impl FnMut(&mut T) -> String,
+    run_func: impl for<'a> FnMut(Cmds, &'a mut T) -> Pin<Box<dyn Future<Output = CommandResult> + 'a>>,
+    data: &mut T,
+) -> anyhow::Result<()>
+where
+    Cmds: clap::Subcommand + clap::FromArgMatches,
+{
+    run_repl_with_hooks(prompt, run_func, Hooks::default(), data).await
+}
+
+/// Run a repl from a `clap::Subcommand` with hooks around every command.
+///
+/// This is like `run_repl` but the `before` and `after` hooks in `hooks` will
+/// be called before and after every command respectively. This can for
+/// instance be used to save some state after each command.
+pub async fn run_repl_with_hooks<Cmds, T>(
     mut prompt: impl FnMut(&mut T) -> String,
     mut run_func: impl for<'a> FnMut(
         Cmds,
         &'a mut T,
     ) -> Pin<Box<dyn Future<Output = CommandResult> + 'a>>,
+    mut hooks: Hooks<'_, T>,
     data: &mut T,
 ) -> anyhow::Result<()>
 where
     Cmds: clap::Subcommand + clap::FromArgMatches,
 {
-    // Create a super command which has all commands as subcommands. This is a so
-    // called "multicall" command, (see `clap::Command::multicall` for more
-    // information). The idea is that the argument list is sent to this command
-    // and the first argument should be recognized as a subcommand.
-    let mut super_command = clap::Command::new("")
-        .multicall(true)
-        .subcommand_required(true)
-        .subcommand_value_name("COMMAND")
-        .subcommand_help_heading("COMMANDS")
-        .help_template("\n{all-args}")
-        .allow_external_subcommands(true); // Needed to be able to figure out when the user has entered an invalid command.
-    super_command = Cmds::augment_subcommands(super_command);
+    let mut super_command = mk_super_command::<Cmds>();
 
     // Initiate the Read Eval Print LOOP!
     let mut rl = rustyline::Editor::<(), rustyline::history::MemHistory>::with_history(
@@ -47,47 +84,7 @@ where
     loop {
         match rl.readline(&prompt(data)) {
             Ok(line) => {
-                let line = line.trim();
-                if line.is_empty() {
-                    continue;
-                }
-                let mut arg_splitter = Shlex::new(line);
-                // Try to parse the arguments but don't handle the result yet. Since the
-                // shlex-stuff happens implace, we need to check whether that has failed first.
-                let arg_matches_res = super_command.try_get_matches_from_mut(arg_splitter.by_ref());
-                if arg_splitter.had_error {
-                    eprintln!(
-                        "Error while splitting argument list. Perhaps an unclosed quotation or \
-                         unended escape."
-                    );
-                    continue;
-                }
-
-                let arg_matches = match arg_matches_res {
-                    Ok(x) => x,
-                    Err(e) => {
-                        // Command line parsing failed.
-                        e.print().unwrap_or_else(|f| {
-                            panic!("Error: {}, Failed to print CLI parsing error: {}", f, e)
-                        });
-                        continue;
-                    }
-                };
-                let args = match Cmds::from_arg_matches(&arg_matches) {
-                    Ok(x) => x,
-                    Err(e) if e.kind() == clap::error::ErrorKind::InvalidSubcommand => {
-                        eprintln!(
-                            r#"{}: command not found, try "help" for a list of all commands."#,
-                            arg_matches.subcommand().unwrap().0
-                        );
-                        continue;
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to deserialize the command: {e}");
-                        continue;
-                    }
-                };
-                match run_func(args, data).await {
+                match run_line(&mut super_command, &line, &mut run_func, &mut hooks, data).await {
                     Ok(ControlFlow::Continue(())) => (),
                     Ok(ControlFlow::Break(())) => break,
                     Err(e) => eprintln!("Error: {}", e),
@@ -99,3 +96,173 @@ where
     }
     Ok(())
 }
+
+/// Create a super command which has all commands as subcommands.
+///
+/// This is a so called "multicall" command, (see `clap::Command::multicall`
+/// for more information). The idea is that the argument list is sent to this
+/// command and the first argument should be recognized as a subcommand.
+fn mk_super_command<Cmds: clap::Subcommand>() -> clap::Command {
+    let super_command = clap::Command::new("")
+        .multicall(true)
+        .subcommand_required(true)
+        .subcommand_value_name("COMMAND")
+        .subcommand_help_heading("COMMANDS")
+        .help_template("\n{all-args}")
+        .allow_external_subcommands(true); // Needed to be able to figure out when the user has entered an invalid command.
+    Cmds::augment_subcommands(super_command)
+}
+
+/// Check whether a line should be ignored, that is if it is blank or a
+/// shell-style comment whose first non-whitespace character is '#'.
+fn is_blank_or_comment(line: &str) -> bool {
+    let line = line.trim_start();
+    line.is_empty() || line.starts_with('#')
+}
+
+/// Parse and run a single line of input.
+///
+/// Errors while parsing the line are printed directly and the line is
+/// skipped, while errors from the command itself are returned.
+async fn run_line<Cmds, T>(
+    super_command: &mut clap::Command,
+    line: &str,
+    run_func: &mut impl for<'a> FnMut(
+        Cmds,
+        &'a mut T,
+    ) -> Pin<Box<dyn Future<Output = CommandResult> + 'a>>,
+    hooks: &mut Hooks<'_, T>,
+    data: &mut T,
+) -> CommandResult
+where
+    Cmds: clap::Subcommand + clap::FromArgMatches,
+{
+    if is_blank_or_comment(line) {
+        return Ok(ControlFlow::Continue(()));
+    }
+    let mut arg_splitter = Shlex::new(line.trim());
+    // Try to parse the arguments but don't handle the result yet. Since the
+    // shlex-stuff happens implace, we need to check whether that has failed first.
+    let arg_matches_res = super_command.try_get_matches_from_mut(arg_splitter.by_ref());
+    if arg_splitter.had_error {
+        eprintln!(
+            "Error while splitting argument list. Perhaps an unclosed quotation or unended escape."
+        );
+        return Ok(ControlFlow::Continue(()));
+    }
+
+    let arg_matches = match arg_matches_res {
+        Ok(x) => x,
+        Err(e) => {
+            // Command line parsing failed.
+            e.print().unwrap_or_else(|f| {
+                panic!("Error: {}, Failed to print CLI parsing error: {}", f, e)
+            });
+            return Ok(ControlFlow::Continue(()));
+        }
+    };
+    let args = match Cmds::from_arg_matches(&arg_matches) {
+        Ok(x) => x,
+        Err(e) if e.kind() == clap::error::ErrorKind::InvalidSubcommand => {
+            eprintln!(
+                r#"{}: command not found, try "help" for a list of all commands."#,
+                arg_matches.subcommand().unwrap().0
+            );
+            return Ok(ControlFlow::Continue(()));
+        }
+        Err(e) => {
+            eprintln!("Failed to deserialize the command: {e}");
+            return Ok(ControlFlow::Continue(()));
+        }
+    };
+    if let Some(before) = hooks.before.as_mut() {
+        before(data);
+    }
+    let res = run_func(args, data).await;
+    if let Some(after) = hooks.after.as_mut() {
+        after(data, &res);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[derive(clap::Subcommand)]
+    enum TestCommand {
+        /// Increment the counter.
+        Incr,
+    }
+
+    fn run_test_command(
+        cmd: TestCommand,
+        counter: &mut u32,
+    ) -> Pin<Box<dyn Future<Output = CommandResult> + '_>> {
+        Box::pin(async move {
+            match cmd {
+                TestCommand::Incr => *counter += 1,
+            }
+            Ok(ControlFlow::Continue(()))
+        })
+    }
+
+    #[tokio::test]
+    async fn test_comments_are_skipped() {
+        let mut super_command = mk_super_command::<TestCommand>();
+        let mut run_func = run_test_command;
+        let mut counter = 0;
+        for line in [
+            "# incr",
+            "   # A comment with leading whitespace",
+            "",
+            "incr",
+        ] {
+            let res = run_line(
+                &mut super_command,
+                line,
+                &mut run_func,
+                &mut Hooks::default(),
+                &mut counter,
+            )
+            .await;
+            assert!(matches!(res, Ok(ControlFlow::Continue(()))));
+        }
+        assert_eq!(counter, 1);
+    }
+
+    #[tokio::test]
+    async fn test_hooks() {
+        let mut super_command = mk_super_command::<TestCommand>();
+        let mut run_func = run_test_command;
+        let (before_calls, after_calls) = (Cell::new(0), Cell::new(0));
+        let mut hooks = Hooks {
+            before: Some(Box::new(|_: &mut u32| {
+                before_calls.set(before_calls.get() + 1)
+            })),
+            after: Some(Box::new(|counter: &mut u32, res: &CommandResult| {
+                assert!(res.is_ok());
+                // The command should have run before the after hook.
+                assert_eq!(*counter, before_calls.get());
+                after_calls.set(after_calls.get() + 1);
+            })),
+        };
+        let mut counter = 0;
+        for line in ["incr", "# A comment", "incr", "not-a-command", "incr"] {
+            let res = run_line(
+                &mut super_command,
+                line,
+                &mut run_func,
+                &mut hooks,
+                &mut counter,
+            )
+            .await;
+            assert_eq!(res.unwrap(), ControlFlow::Continue(()));
+        }
+        assert_eq!(counter, 3);
+        assert_eq!(before_calls.get(), 3);
+        assert_eq!(after_calls.get(), 3);
+    }
+}
@@ -0,0 +1,31 @@
+//! Benchmarks for looking up entries in the cache.
+use common_cache::CommonCache;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand::prelude::*;
+
+/// Create a cache with `n` items and a seeded RNG.
+fn mk_cache(n: u64) -> CommonCache<u64, u64, StdRng> {
+    let mut cache = CommonCache::new_with_rng(2, None, StdRng::seed_from_u64(0));
+    for i in 0..n {
+        cache.insert(i, i);
+    }
+    cache
+}
+
+fn entry_benchmark(c: &mut Criterion) {
+    let mut cache = mk_cache(10_000);
+    let top_key = *cache.iter().next().unwrap().0;
+    let bottom_key = *cache.iter().next_back().unwrap().0;
+    c.bench_function("entry top level hit", |b| {
+        b.iter(|| cache.entry(black_box(&top_key)).is_some())
+    });
+    c.bench_function("entry bottom level hit", |b| {
+        b.iter(|| cache.entry(black_box(&bottom_key)).is_some())
+    });
+    c.bench_function("entry miss", |b| {
+        b.iter(|| cache.entry(black_box(&u64::MAX)).is_some())
+    });
+}
+
+criterion_group!(benches, entry_benchmark);
+criterion_main!(benches);
@@ -9,6 +9,7 @@
 //! - Items that haven't be used for a while might be discarded from the cache.
 //! - If many elements are used, the cache will grow in size. And if only a few elements are used,
 //! the cache will slowly discard the unused items and shrink in size.
+//! - A min size can be set to stop the cache from shrinking below a certain number of items.
 //! - The cache uses some randomness under the hood to select what items to move down and discard.
 //! But don't worry, you can provide your own RNG if you want reproducability.
 //! - Most operations, like insert, remove and lookup, runs in logarithmic time.
@@ -44,9 +45,31 @@
 //! - When iterating over the cache, all levels are visited in order. So no element on any level
 //!   will
 //! come after any element on a level below.
+//!
+//! ## Peeking and promoting
+//!
+//! Reading an item through an `Entry` or `Index` either *peeks* at it, which
+//! leaves the cache untouched, or *promotes* it, which moves it up a level and
+//! counts as a use of the item. The short names `peek` and `get_promote` make
+//! the distinction explicit, the older names are still available:
+//!
+//! | Old name | New name | Promotes |
+//! |---|---|---|
+//! | `Entry::peek_key_value` | `Entry::peek` | No |
+//! | `Entry::get_key_value` | `Entry::get_promote` | Yes |
+//! | `Index::peek_key_value` | `Index::peek` | No |
+//! | `Index::get_key_value` | `Index::get_promote` | Yes |
+//!
+//! Note that `CommonCache::entry` and `CommonCache::find_first` only look up
+//! an item, they never promote it by themselves.
+//!
+//! To move an item without reading it, use `Entry::promote` or
+//! `Entry::demote`.
 use core::borrow::Borrow;
 use core::hash::Hash;
 use core::marker::PhantomData;
+use core::mem::size_of;
+use std::collections::HashSet;
 
 use indexmap::IndexMap;
 use rand::prelude::*;
@@ -59,7 +82,16 @@ use serde::{Deserialize, Serialize};
 ///
 /// See the module level documentation for details.
 #[derive(Debug, Clone)]
-#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        from = "DeserializedCommonCache<K, V, R>",
+        bound(
+            deserialize = "K: Deserialize<'de> + Eq + Hash, V: Deserialize<'de>, R: SeedableRng"
+        )
+    )
+)]
 pub struct CommonCache<K, V, R: Rng = StdRng> {
     /// The base for the exponentially growing size of levels.
     base: usize,
@@ -75,14 +107,40 @@ pub struct CommonCache<K, V, R: Rng = StdRng> {
         ))
     )]
     levels: Vec<Level<K, V>>,
+    /// The total number of items in all levels, kept up to date so that
+    /// `Self::size` runs in constant time.
+    ///
+    /// It is not serialized but recomputed from the levels on
+    /// deserialization.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    len: usize,
+    /// Preallocated maps for the items of levels which have not been created
+    /// yet, indexed by level. They are used instead of allocating new maps
+    /// when the cache grows, see `Self::with_capacity`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    reserved_levels: Vec<IndexMap<K, V>>,
     /// A random number generator.
-    #[serde(skip, default = "SeedableRng::from_entropy", bound = "R: SeedableRng")]
+    #[cfg_attr(
+        feature = "serde",
+        serde(skip, default = "SeedableRng::from_entropy", bound = "R: SeedableRng")
+    )]
     rng: R,
 
     /// An upper bound of the number of elements in the cache. Might be set to
     /// `usize::MAX`.
     max_size: usize,
 
+    /// A lower bound of the number of elements in the cache, under which no
+    /// items are discarded when other items are promoted. Is at most
+    /// `max_size`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    min_size: usize,
+
+    /// How aggressively commonly used items are promoted, 1.0 by default. See
+    /// `Self::set_aggressiveness`.
+    #[cfg_attr(feature = "serde", serde(default = "default_aggressiveness"))]
+    aggressiveness: f64,
+
     /// A counter that increments every time elements are moved between levels
     /// in the cache.
     ///
@@ -93,6 +151,59 @@ pub struct CommonCache<K, V, R: Rng = StdRng> {
     generation: u64,
 }
 
+/// The serialized fields of a `CommonCache`, from which the cache is created on
+/// deserialization.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(bound(
+    deserialize = "K: Deserialize<'de> + Eq + Hash, V: Deserialize<'de>, R: SeedableRng"
+))]
+struct DeserializedCommonCache<K, V, R> {
+    base: usize,
+    levels: Vec<Level<K, V>>,
+    #[serde(skip, default = "SeedableRng::from_entropy")]
+    rng: R,
+    max_size: usize,
+    #[serde(default)]
+    min_size: usize,
+    #[serde(default = "default_aggressiveness")]
+    aggressiveness: f64,
+    generation: u64,
+}
+
+/// The aggressiveness of a cache which was serialized before it was
+/// configurable.
+#[cfg(feature = "serde")]
+fn default_aggressiveness() -> f64 {
+    1.0
+}
+
+#[cfg(feature = "serde")]
+impl<K, V, R: Rng> From<DeserializedCommonCache<K, V, R>> for CommonCache<K, V, R> {
+    fn from(cache: DeserializedCommonCache<K, V, R>) -> Self {
+        let DeserializedCommonCache {
+            base,
+            levels,
+            rng,
+            max_size,
+            min_size,
+            aggressiveness,
+            generation,
+        } = cache;
+        Self {
+            base,
+            len: levels.iter().map(|x| x.items.len()).sum(),
+            levels,
+            reserved_levels: Vec::new(),
+            rng,
+            max_size,
+            min_size,
+            aggressiveness,
+            generation,
+        }
+    }
+}
+
 /// A level in the cache.
 #[derive(Debug, Clone)]
 #[cfg_attr(
@@ -120,6 +231,17 @@ impl<K, V> CommonCache<K, V> {
         Self::new_with_rng(base, max_size, StdRng::from_entropy())
     }
 
+    /// Create a new `CommonCache` like `Self::new`, but with enough memory
+    /// reserved to hold `expected_items` items without reallocation.
+    ///
+    /// The maps for the levels needed for `expected_items` items are allocated
+    /// up front, and are used as the levels are created.
+    pub fn with_capacity(base: usize, max_size: Option<usize>, expected_items: usize) -> Self {
+        let mut cache = Self::new(base, max_size);
+        cache.reserve_levels(expected_items.min(cache.max_size));
+        cache
+    }
+
     /// Get the currently configured max size for the cache.
     pub fn max_size(&self) -> usize {
         self.max_size
@@ -128,7 +250,7 @@ impl<K, V> CommonCache<K, V> {
     /// Set the max size. Note that if this might cause many elements to be
     /// removed.
     ///
-    /// PRE: max_size >= 2
+    /// PRE: max_size >= 2 and max_size >= self.min_size()
     ///
     /// Runs in linear time if max_size < self.size(), constant time otherwise.
     ///
@@ -141,6 +263,10 @@ impl<K, V> CommonCache<K, V> {
             max_size >= 2,
             "max_size must be >=2 in CommonCache::set_max_size()"
         );
+        assert!(
+            max_size >= self.min_size,
+            "max_size must be >= min_size in CommonCache::set_max_size()"
+        );
         if max_size >= self.max_size {
             self.max_size = max_size;
             return;
@@ -161,6 +287,8 @@ impl<K, V> CommonCache<K, V> {
                 break;
             }
         }
+        self.max_size = max_size;
+        self.len = self.len.min(max_size);
 
         // Some random elements might have been removed so let's increase the generation
         // to invalidate any indexes to the cache.
@@ -170,6 +298,7 @@ impl<K, V> CommonCache<K, V> {
     /// Clear the cache.
     pub fn clear(&mut self) {
         self.levels.clear();
+        self.len = 0;
         self.generation += 1;
     }
 }
@@ -189,17 +318,260 @@ impl<K, V, R: Rng> CommonCache<K, V, R> {
             base,
             rng,
             levels: Vec::new(),
+            len: 0,
+            reserved_levels: Vec::new(),
             max_size,
+            min_size: 0,
+            aggressiveness: 1.0,
             generation: 0,
         }
     }
 
+    /// Replace the random number generator of this cache, returning the cache
+    /// with the new RNG together with the old RNG.
+    ///
+    /// Note that the type of the RNG is a type parameter of the cache, so the
+    /// returned cache might have a different type than `self`. This can be
+    /// useful for swapping to a seeded RNG after construction to get
+    /// reproduceable behaviour.
+    ///
+    /// The items in the cache are kept intact, and since no items are moved,
+    /// no `Index` to the cache is invalidated by this operation. (Although they
+    /// can not be used with the new cache if the type of the RNG changes.)
+    pub fn replace_rng<R2: Rng>(self, rng: R2) -> (CommonCache<K, V, R2>, R) {
+        let Self {
+            base,
+            levels,
+            len,
+            reserved_levels,
+            rng: old_rng,
+            max_size,
+            min_size,
+            aggressiveness,
+            generation,
+        } = self;
+        (
+            CommonCache {
+                base,
+                levels,
+                len,
+                reserved_levels,
+                rng,
+                max_size,
+                min_size,
+                aggressiveness,
+                generation,
+            },
+            old_rng,
+        )
+    }
+
+    /// Clone the cache but use another random generator in the clone.
+    ///
+    /// This is useful since the derived `Clone` implementation requires the
+    /// random generator to implement `Clone` which not all generators do.
+    /// It is also useful to fork a cache, since a clone of the random generator
+    /// would make the same random choices in both caches.
+    pub fn clone_with_rng<R2: Rng>(&self, rng: R2) -> CommonCache<K, V, R2>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        CommonCache {
+            base: self.base,
+            levels: self.levels.clone(),
+            len: self.len,
+            reserved_levels: Vec::new(),
+            rng,
+            max_size: self.max_size,
+            min_size: self.min_size,
+            aggressiveness: self.aggressiveness,
+            generation: self.generation,
+        }
+    }
+
+    /// Get the currently configured min size for the cache, 0 by default.
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    /// Set the min size of the cache.
+    ///
+    /// Items at the lowest level are usually discarded at random when other
+    /// items are promoted, so that unused items eventually disappear. As long
+    /// as the cache has no more than `min_size` items though, no items are
+    /// discarded in this way, so the cache keeps a warm working set of at
+    /// least `min_size` items once it has grown that large. Removing items
+    /// explicitly, or inserting items when the cache is full, is not affected.
+    ///
+    /// PRE: min_size <= self.max_size()
+    pub fn set_min_size(&mut self, min_size: usize) {
+        assert!(
+            min_size <= self.max_size,
+            "min_size must be <= max_size in CommonCache::set_min_size()"
+        );
+        self.min_size = min_size;
+    }
+
+    /// Clear the cache like `CommonCache::clear`, but keep the memory
+    /// allocated for the levels, so that the cache can be filled again without
+    /// reallocation.
+    ///
+    /// The emptied maps are kept like the ones preallocated by
+    /// `CommonCache::with_capacity`, and are reused as the levels are created
+    /// again.
+    pub fn clear_keep_capacity(&mut self) {
+        for (i, level) in self.levels.drain(..).enumerate() {
+            let mut items = level.items;
+            items.clear();
+            match self.reserved_levels.get_mut(i) {
+                Some(reserved) if reserved.capacity() >= items.capacity() => (),
+                Some(reserved) => *reserved = items,
+                None => self.reserved_levels.push(items),
+            }
+        }
+        self.len = 0;
+        self.generation += 1;
+    }
+
+    /// Get the currently configured aggressiveness of the cache, 1.0 by
+    /// default.
+    pub fn aggressiveness(&self) -> f64 {
+        self.aggressiveness
+    }
+
+    /// Set how aggressively commonly used items are promoted to the top of the
+    /// cache, without changing the base.
+    ///
+    /// When an item is inserted or promoted, an item is moved down from each
+    /// level below it with probability k/n, where k is the number of items at
+    /// the level and n its capacity. With an aggressiveness of a, the
+    /// probability is instead k/(a*n), capped at 1. A value above 1.0 moves
+    /// fewer items down, so the levels fill up and the cache gets shallower,
+    /// and a commonly used item reaches the top in fewer promotions. A value
+    /// below 1.0 moves more items down, so the cache gets deeper and more
+    /// items keep their positions for longer. A value of 1.0 keeps the default
+    /// behaviour.
+    ///
+    /// PRE: aggressiveness > 0.0 and is finite
+    pub fn set_aggressiveness(&mut self, aggressiveness: f64) {
+        assert!(
+            aggressiveness > 0.0 && aggressiveness.is_finite(),
+            "aggressiveness must be positive and finite in CommonCache::set_aggressiveness()"
+        );
+        self.aggressiveness = aggressiveness;
+    }
+
     /// Get the number of elements in the cache.
     ///
-    /// Runs in O(log\[base](n)) time, since the len of all levels must be
-    /// summed up.
+    /// Runs in O(1) time.
     pub fn size(&self) -> usize {
-        self.levels.iter().map(|x| x.items.len()).sum()
+        self.len
+    }
+
+    /// Get the number of items at each level, from the highest (most used)
+    /// level to the lowest.
+    pub fn level_occupancy(&self) -> Vec<usize> {
+        self.levels.iter().map(|x| x.items.len()).collect()
+    }
+
+    /// Estimate the number of bytes of memory held by the cache.
+    ///
+    /// This counts the cache itself, the list of levels and every item, where
+    /// each item takes `size_of::<K>() + size_of::<V>()` plus the hash and
+    /// index which the map of its level stores with it. The maps preallocated
+    /// by `Self::with_capacity` for levels which have not been created yet are
+    /// counted by their capacity.
+    ///
+    /// It is only an estimate. Memory owned by the keys and values, like the
+    /// contents of a `String`, is not counted, and neither is unused capacity
+    /// in the maps of the levels, padding or the overhead of the allocator.
+    pub fn memory_estimate(&self) -> usize {
+        // An `IndexMap` stores every item together with its hash, and the index
+        // of every item in a hash table with one control byte per slot.
+        let item_size = size_of::<K>() + size_of::<V>() + 2 * size_of::<usize>() + 1;
+        let reserved: usize = self.reserved_levels.iter().map(|x| x.capacity()).sum();
+        size_of::<Self>()
+            + self.levels.capacity() * size_of::<Level<K, V>>()
+            + self.reserved_levels.capacity() * size_of::<IndexMap<K, V>>()
+            + (self.len + reserved) * item_size
+    }
+
+    /// Render the keys at each level of the cache, one level per line, like:
+    ///
+    /// ```text
+    /// level 0 (1/1): [3]
+    /// level 1 (2/2): [7, 1]
+    /// level 2 (1/4): [5]
+    /// ```
+    ///
+    /// where "1/4" means that the level has 1 item and a capacity of 4. This
+    /// shows the shape of the cache more clearly than the derived `Debug`
+    /// implementation and is useful when debugging promotions and evictions.
+    pub fn debug_pyramid(&self) -> String
+    where
+        K: std::fmt::Debug,
+    {
+        let mut pyramid = String::new();
+        for (i, level) in self.levels.iter().enumerate() {
+            let keys: Vec<_> = level.items.keys().collect();
+            pyramid += &format!(
+                "level {i} ({}/{}): {keys:?}\n",
+                keys.len(),
+                self.level_capacity(i)
+            );
+        }
+        pyramid
+    }
+
+    /// Get the maximum number of items at a level, that is base^level.
+    fn level_capacity(&self, level: usize) -> usize {
+        self.base
+            .checked_pow(level.try_into().unwrap_or(u32::MAX))
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Allocate the maps for as many levels as are needed to hold `items` items,
+    /// with the capacity of each level.
+    fn reserve_levels(&mut self, items: usize) {
+        let mut remaining = items;
+        while remaining > 0 {
+            let capacity = self
+                .level_capacity(self.reserved_levels.len())
+                .min(remaining);
+            self.reserved_levels.push(IndexMap::with_capacity(capacity));
+            remaining -= capacity;
+        }
+        self.levels.reserve(self.reserved_levels.len());
+    }
+
+    /// Create an empty level at the bottom of the cache, using the reserved map
+    /// for the level if there is one.
+    fn push_level(&mut self) -> &mut Level<K, V> {
+        let level = self.levels.len();
+        let items = match self.reserved_levels.get_mut(level) {
+            Some(items) if items.capacity() > 0 => std::mem::take(items),
+            _ => IndexMap::with_capacity(1),
+        };
+        let capacity = self.level_capacity(level);
+        self.levels.push(Level {
+            items,
+            rand_range: (0..capacity).into(),
+        });
+        self.levels.last_mut().unwrap()
+    }
+
+    /// Remove all empty levels at the bottom of the cache, so that the last
+    /// level is never empty.
+    fn pop_empty_levels(&mut self) {
+        while self.levels.last().is_some_and(|x| x.items.is_empty()) {
+            self.levels.pop();
+        }
+    }
+
+    /// Get the heat of all items at a level, see `Entry::heat`.
+    fn heat_of_level(&self, level: usize) -> f64 {
+        1.0 - level as f64 / self.levels.len() as f64
     }
 }
 
@@ -238,10 +610,12 @@ where
     /// assert_matches!(*entry.get_value(), "Hello");
     /// ```
     pub fn insert(&mut self, key: K, value: V) -> Entry<'_, K, V, R> {
-        // Check if the item is already in the cache.
-        let insert_level = if let Some(entry) = self.entry(&key) {
-            let level = entry.level;
-            let _old_item = entry.remove();
+        // Check if the item is already in the cache, and if so remove the old
+        // item by its index. Every level is a separate map with its own hasher,
+        // so the key is still hashed once for every level it is looked up at
+        // and once more when it is inserted.
+        let insert_level = if let Some((level, idx)) = self.find_key(&key) {
+            let _old_item = self.remove_at(level, idx);
             // Insert the item at the level above.
             level.saturating_sub(1)
         } else {
@@ -251,15 +625,45 @@ where
         self.insert_at_level::<true>(key, value, insert_level)
     }
 
+    /// Insert many items into the cache, like calling `Self::insert` for each
+    /// of them in order.
+    ///
+    /// The generation is increased only once rather than once per item. All
+    /// `Index`es taken before the call are still invalidated.
+    pub fn insert_many(&mut self, items: impl IntoIterator<Item = (K, V)>) {
+        let generation = self.generation;
+        for (key, value) in items {
+            self.insert(key, value);
+        }
+        // No index can have been created during the insertions, so it is safe
+        // to reset the generation.
+        self.generation = generation + 1;
+    }
+
+    /// Remove the item at a given level and index within the level.
+    ///
+    /// Another item at the level might be moved to `idx`, but the generation
+    /// is not increased.
+    fn remove_at(&mut self, level: usize, idx: usize) -> (K, V) {
+        let (key, value) = self.levels[level].items.swap_remove_index(idx).unwrap();
+        self.len -= 1;
+        // If the last level became empty, we shall remove it, together with
+        // any empty levels above it.
+        self.pop_empty_levels();
+        (key, value)
+    }
+
     /// Insert an item at a specific level in the cache and possibly push an
     /// item to lower levels.
     ///
     /// This is the core function of the algorithm. It will, with probability
     /// k/n, (where n is the maximum number of items at the level and k is
-    /// the actual number of items), remove an item from the level and
-    /// insert it on the level below. This will be repeated for all lower
+    /// the actual number of items), divided by `self.aggressiveness`, remove
+    /// an item from the level and insert it on the level below. This will be repeated for all lower
     /// levels. If an item is selected at the lowest level, a new lowest level
-    /// will be created.
+    /// will be created if `CREATE_NEW_LEVEL_IF_NEEDED` is true or the cache
+    /// has no more than `self.min_size` items, otherwise the item is
+    /// discarded.
     ///
     /// The function will of course also insert the given item at the given
     /// level.
@@ -280,19 +684,21 @@ where
             let last_level_items = &mut self.levels.last_mut().unwrap().items;
             let to_remove = self.rng.gen_range(0..last_level_items.len());
             last_level_items.swap_remove_index(to_remove);
-            if last_level_items.is_empty() {
-                self.levels.pop();
-            }
+            self.len -= 1;
+            self.pop_empty_levels();
         }
 
-        if self.levels.is_empty() {
-            // If there are no levels, add one.
-            self.levels.push(Level {
-                items: IndexMap::with_capacity(1),
-                rand_range: (0..1).into(),
-            });
+        // Add levels down to the desired level if they don't exist. This
+        // happens if there are no levels at all, or if the levels below an item
+        // were removed together with it.
+        while self.levels.len() <= level {
+            self.push_level();
         }
 
+        // Don't discard any item if that would bring the size below the min
+        // size. Note that the item to insert is not counted by `self.size()`.
+        let create_new_level = CREATE_NEW_LEVEL_IF_NEEDED || self.size() < self.min_size;
+
         // Loop through all levels from the lowest to the current (`level`).c For each
         // level, randomly decide whether to move one item down to the level
         // below. The fuller a level is, the higher probability it is that an
@@ -301,22 +707,33 @@ where
             let current_level = &mut self.levels[level];
             // Generate an integer in the range of the total capacity of the level.
             let i = current_level.rand_range.sample(&mut self.rng);
-            if let Some(move_down_item) = current_level.items.swap_remove_index(i) {
+            // An item is moved down if `i`, scaled by the aggressiveness, is
+            // below the number of items. If `i` is not the index of an item, a
+            // random item is picked instead.
+            let items = current_level.items.len();
+            let move_down_idx = if i as f64 * self.aggressiveness >= items as f64 {
+                None
+            } else if i < items {
+                Some(i)
+            } else {
+                Some(self.rng.gen_range(0..items))
+            };
+            if let Some(move_down_item) =
+                move_down_idx.and_then(|i| current_level.items.swap_remove_index(i))
+            {
                 if level != self.levels.len() - 1 {
                     // Insert the item on the level below.
                     self.levels[level + 1]
                         .items
                         .insert(move_down_item.0, move_down_item.1);
-                } else if CREATE_NEW_LEVEL_IF_NEEDED {
+                } else if create_new_level {
                     // This was the lowest level. So let's create a new one.
-                    let new_level_size = self
-                        .base
-                        .checked_pow((level + 1).try_into().unwrap_or(u32::MAX))
-                        .unwrap_or(usize::MAX);
-                    self.levels.push(Level {
-                        items: IndexMap::from([move_down_item]),
-                        rand_range: (0..new_level_size).into(),
-                    });
+                    self.push_level()
+                        .items
+                        .insert(move_down_item.0, move_down_item.1);
+                } else {
+                    // The item was discarded.
+                    self.len -= 1;
                 }
             }
         }
@@ -324,6 +741,10 @@ where
         let (idx, None) = self.levels[level].items.insert_full(key, value) else {
             unreachable!()
         };
+        self.len += 1;
+        // If an item was discarded from the lowest level, it might have become
+        // empty.
+        self.pop_empty_levels();
         Entry {
             cache: self,
             level,
@@ -331,29 +752,145 @@ where
         }
     }
 
+    /// Get an entry for a key which might or might not be in the cache, like
+    /// `HashMap::entry`.
+    ///
+    /// Looking up the entry does not promote the item. Runs in
+    /// `O(log[base](n))` time.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use common_cache::CommonCache;
+    ///
+    /// let mut cache = CommonCache::new(2, None);
+    /// *cache.entry_api("apples").or_insert(0).get_value() += 1;
+    /// *cache.entry_api("apples").or_insert(0).get_value() += 1;
+    /// assert_eq!(*cache.entry(&"apples").unwrap().peek_value(), 2);
+    /// ```
+    pub fn entry_api(&mut self, key: K) -> CacheEntry<'_, K, V, R> {
+        match self.find_key(&key) {
+            Some((level, idx)) => CacheEntry::Occupied(Entry {
+                cache: self,
+                level,
+                idx,
+            }),
+            None => CacheEntry::Vacant(VacantEntry { cache: self, key }),
+        }
+    }
+
     /// Get a handle to an entry in the cache.
     ///
     /// Runs in `O(log[base](n))` time.
+    ///
+    /// In debug builds, this panics if the key is found at several levels,
+    /// which would mean that the cache is corrupt. See `Self::entry_checked`
+    /// for a version which returns an error instead.
     pub fn entry<Q>(&mut self, key: &Q) -> Option<Entry<'_, K, V, R>>
     where
         K: Borrow<Q>,
         Q: Eq + Hash + ?Sized,
     {
-        if let Some((level, idx)) = self
+        let (level, idx) = self.find_key(key)?;
+        debug_assert!(
+            self.find_key_from_level(key, level + 1).is_none(),
+            "A key was found at several levels in CommonCache::entry()"
+        );
+        Some(Entry {
+            cache: self,
+            level,
+            idx,
+        })
+    }
+
+    /// Get an `Index` to an item in the cache without borrowing the cache
+    /// mutably, for instance to promote the item later.
+    ///
+    /// The index is valid until the cache is altered. Runs in
+    /// `O(log[base](n))` time.
+    pub fn index_of<Q>(&self, key: &Q) -> Option<Index<K, V, R>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let (level, idx) = self.find_key(key)?;
+        Some(Index::new(level, idx, self))
+    }
+
+    /// Get a handle to an entry in the cache, after checking that the key is
+    /// found at no more than one level.
+    ///
+    /// A key should never be at several levels, so an error means that the
+    /// cache is corrupt because of a bug. Unlike `Self::entry`, this always
+    /// searches all levels, so it runs in `O(log[base](n))` time even when the
+    /// key is found at one of the top levels.
+    pub fn entry_checked<Q>(
+        &mut self,
+        key: &Q,
+    ) -> Result<Option<Entry<'_, K, V, R>>, DuplicateKeyError>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let levels: Vec<usize> = self
             .levels
-            .iter_mut()
+            .iter()
             .enumerate()
-            .filter_map(|(i, x)| x.items.get_index_of(key).map(|x| (i, x)))
-            .next()
-        {
-            Some(Entry {
-                cache: self,
-                level,
-                idx,
-            })
-        } else {
-            None
-        }
+            .filter(|(_, x)| x.items.contains_key(key))
+            .map(|(i, _)| i)
+            .collect();
+        let level = match levels[..] {
+            [] => return Ok(None),
+            [level] => level,
+            _ => return Err(DuplicateKeyError { levels }),
+        };
+        let idx = self.levels[level].items.get_index_of(key).unwrap();
+        Ok(Some(Entry {
+            cache: self,
+            level,
+            idx,
+        }))
+    }
+
+    /// Remove an item by its key and return only the value.
+    ///
+    /// This is a shorthand for `self.entry(key).map(|x| x.remove().1)`, useful
+    /// when the key is already known. Returns `None` if the key is not in the
+    /// cache.
+    ///
+    /// Since the removal might move another item within its level, all indexes
+    /// to this cache are invalidated if an item is removed.
+    pub fn take<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let (_key, value) = self.entry(key)?.remove();
+        self.generation += 1;
+        Some(value)
+    }
+
+    /// Find the level and the index within the level of a key.
+    fn find_key<Q>(&self, key: &Q) -> Option<(usize, usize)>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.find_key_from_level(key, 0)
+    }
+
+    /// Find the level and the index within the level of a key by scanning all
+    /// levels from `start_level` and downwards.
+    fn find_key_from_level<Q>(&self, key: &Q, start_level: usize) -> Option<(usize, usize)>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.levels
+            .iter()
+            .enumerate()
+            .skip(start_level)
+            .find_map(|(i, x)| x.items.get_index_of(key).map(|x| (i, x)))
     }
 
     /// Iterate over the elements in the cache so that all items on any level
@@ -365,6 +902,58 @@ where
         self.levels.iter().flat_map(|x| x.items.iter())
     }
 
+    /// Iterate over the levels of the cache, from the highest (most used) level
+    /// to the lowest, together with the index of each level.
+    ///
+    /// The items are yielded in the same order as by `Self::iter`. Like
+    /// `Self::iter`, this does not promote any items.
+    pub fn iter_levels(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = (usize, impl ExactSizeIterator<Item = (&'_ K, &'_ V)>)> + '_
+    {
+        self.levels.iter().map(|x| x.items.iter()).enumerate()
+    }
+
+    /// Get an owned copy of all keys in the cache, in the same order as
+    /// `Self::iter`.
+    ///
+    /// Unlike `Self::iter`, the borrow of the cache ends immediately, so the
+    /// keys can be used to access the cache mutably while going through them.
+    /// This comes at the cost of cloning all keys into a new vector, which
+    /// takes linear time and memory.
+    pub fn snapshot_keys(&self) -> Vec<K>
+    where
+        K: Clone,
+    {
+        self.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    /// Get an owned copy of all items in the cache, in the same order as
+    /// `Self::iter`.
+    ///
+    /// Like `Self::snapshot_keys`, but the values are cloned as well.
+    pub fn snapshot(&self) -> Vec<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Iterate over the `n` most commonly used items in the cache, or all items
+    /// if there are fewer than `n` items.
+    ///
+    /// This is like `self.iter().take(n)` but the returned iterator knows its
+    /// exact length. No items are promoted.
+    pub fn top_n(&self, n: usize) -> impl ExactSizeIterator<Item = (&'_ K, &'_ V)> + '_ {
+        ExactLen {
+            iter: self.iter(),
+            len: n.min(self.size()),
+        }
+    }
+
     /// Iterate over mutable references to the elements in the cache. All items
     /// on any level will come before any item on any lower level.
     ///
@@ -426,6 +1015,13 @@ pub struct Entry<'a, K, V, R: Rng = StdRng> {
     idx: usize,
 }
 
+impl<K: Eq + Hash, V, R: Rng> Extend<(K, V)> for CommonCache<K, V, R> {
+    /// Insert all items with `CommonCache::insert_many`.
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        self.insert_many(iter)
+    }
+}
+
 impl<'a, K: Eq + Hash, V, R: Rng> Entry<'a, K, V, R> {
     /// Read the key and value at the entry without touching the rest of the
     /// cache. This operation will hence not be taken into account when
@@ -437,6 +1033,56 @@ impl<'a, K: Eq + Hash, V, R: Rng> Entry<'a, K, V, R> {
             .unwrap()
     }
 
+    /// Read the key and value at the entry without promoting it. This is the
+    /// same as `Self::peek_key_value`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use common_cache::CommonCache;
+    /// use rand::SeedableRng;
+    /// use rand_chacha::ChaCha8Rng;
+    ///
+    /// let mut cache = CommonCache::new_with_rng(2, None, ChaCha8Rng::seed_from_u64(0));
+    /// for i in 0..20 {
+    ///     cache.insert(i, i);
+    /// }
+    /// let (&key, _) = cache.iter().next_back().unwrap();
+    /// let mut entry = cache.entry(&key).unwrap();
+    /// let heat = entry.heat();
+    /// assert_eq!(entry.peek(), (&key, &key));
+    /// // The entry is still on the same level.
+    /// assert_eq!(entry.heat(), heat);
+    /// ```
+    pub fn peek(&self) -> (&K, &V) {
+        self.peek_key_value()
+    }
+
+    /// Get the key and value at the entry and promote it to a higher level in
+    /// the cache. This is the same as `Self::get_key_value`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use common_cache::CommonCache;
+    /// use rand::SeedableRng;
+    /// use rand_chacha::ChaCha8Rng;
+    ///
+    /// let mut cache = CommonCache::new_with_rng(2, None, ChaCha8Rng::seed_from_u64(0));
+    /// for i in 0..20 {
+    ///     cache.insert(i, i);
+    /// }
+    /// let (&key, _) = cache.iter().next_back().unwrap();
+    /// let mut entry = cache.entry(&key).unwrap();
+    /// let heat = entry.heat();
+    /// assert_eq!(entry.get_promote(), (&key, &mut key.clone()));
+    /// // The entry has moved up a level.
+    /// assert!(entry.heat() > heat);
+    /// ```
+    pub fn get_promote(&mut self) -> (&K, &mut V) {
+        self.get_key_value()
+    }
+
     /// Silently read the key at this entry.
     pub fn peek_key(&self) -> &K {
         self.peek_key_value().0
@@ -467,6 +1113,27 @@ impl<'a, K: Eq + Hash, V, R: Rng> Entry<'a, K, V, R> {
         self.peek_key_value_mut().1
     }
 
+    /// Replace the value at this entry, returning the old value.
+    ///
+    /// The entry is neither promoted nor moved in any other way, so unlike
+    /// most other modifications of the cache this does not increase the
+    /// generation. All `Index`es to the cache, including one to this entry,
+    /// remain valid.
+    pub fn reinsert_preserving_generation(&mut self, value: V) -> V {
+        std::mem::replace(self.peek_value_mut(), value)
+    }
+
+    /// Get the "heat" of this entry, a number in (0, 1] describing how commonly
+    /// used it is compared to the rest of the cache.
+    ///
+    /// The heat is computed as `1 - level / num_levels`, where `level` is the
+    /// level of the entry, 0 being the top level, and `num_levels` is the
+    /// number of levels in the cache. So items at the top level have heat 1.0
+    /// and items at the bottom level have heat `1 / num_levels`.
+    pub fn heat(&self) -> f64 {
+        self.cache.heat_of_level(self.level)
+    }
+
     /// Read the item at this entry and destroy the `Entry` struct. The item
     /// will still be in the cache but this allows us to get a reference
     /// with the full lifetime of this entry.
@@ -484,15 +1151,58 @@ impl<'a, K: Eq + Hash, V, R: Rng> Entry<'a, K, V, R> {
     /// This function will promote this entry to a higher level in the cache and
     /// based on some probability move other items down in the cache.
     pub fn get_key_value(&mut self) -> (&K, &mut V) {
-        replace_with_or_abort(self, |self_| {
-            let curr_level = self_.level;
-            let (index, cache) = self_.index_and_cache();
-            let (key, value) = index.remove_from(cache);
-            cache.insert_at_level::<false>(key, value, curr_level.saturating_sub(1))
-        });
+        replace_with_or_abort(self, Self::promote);
         self.peek_key_value_mut()
     }
 
+    /// Promote this entry to the level above without reading it, and based on
+    /// some probability move other items down in the cache, like
+    /// `Self::get_key_value` does.
+    ///
+    /// Returns the entry at its new position.
+    pub fn promote(self) -> Self {
+        let curr_level = self.level;
+        let (index, cache) = self.index_and_cache();
+        let (key, value) = index.remove_from(cache);
+        cache.insert_at_level::<false>(key, value, curr_level.saturating_sub(1))
+    }
+
+    /// Move this entry to the level below without reading it, or discard it if
+    /// it is already at the lowest level.
+    ///
+    /// If the level below is full, a random item there takes the place of
+    /// this entry, otherwise no other items are moved. Returns the entry at
+    /// its new position, or `None` if it was discarded. The generation is
+    /// increased, so all `Index`es to this cache are invalidated.
+    pub fn demote(self) -> Option<Self> {
+        let curr_level = self.level;
+        let (index, cache) = self.index_and_cache();
+        let is_lowest = curr_level + 1 == cache.levels.len();
+        let (key, value) = index.remove_from(cache);
+        cache.generation += 1;
+        if is_lowest {
+            return None;
+        }
+        // The level below is not removed since it is not above the lowest level.
+        let below = curr_level + 1;
+        let below_items = cache.levels[below].items.len();
+        if below_items >= cache.level_capacity(below) {
+            // The level below is full, so swap places with a random item there.
+            let i = cache.rng.gen_range(0..below_items);
+            let (up_key, up_value) = cache.levels[below].items.swap_remove_index(i).unwrap();
+            cache.levels[curr_level].items.insert(up_key, up_value);
+        }
+        let (idx, None) = cache.levels[below].items.insert_full(key, value) else {
+            unreachable!()
+        };
+        cache.len += 1;
+        Some(Entry {
+            cache,
+            level: below,
+            idx,
+        })
+    }
+
     /// Get the value at this entry and promote this entry to a higher level in
     /// the cache.
     ///
@@ -546,6 +1256,203 @@ impl<'a, K: Eq + Hash, V, R: Rng> Entry<'a, K, V, R> {
     }
 }
 
+/// An error from `CommonCache::entry_checked` when a key is found at several
+/// levels in the cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateKeyError {
+    /// The levels where the key was found.
+    pub levels: Vec<usize>,
+}
+
+impl std::fmt::Display for DuplicateKeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The key was found at several levels in the cache: {:?}",
+            self.levels
+        )
+    }
+}
+
+impl std::error::Error for DuplicateKeyError {}
+
+/// An entry for a key which might or might not be in the cache.
+///
+/// Created with `CommonCache::entry_api`.
+#[derive(Debug)]
+pub enum CacheEntry<'a, K, V, R: Rng = StdRng> {
+    /// The key is in the cache.
+    Occupied(Entry<'a, K, V, R>),
+    /// The key is not in the cache.
+    Vacant(VacantEntry<'a, K, V, R>),
+}
+
+impl<'a, K: Eq + Hash, V, R: Rng> CacheEntry<'a, K, V, R> {
+    /// Get the key of the entry.
+    pub fn key(&self) -> &K {
+        match self {
+            Self::Occupied(entry) => entry.peek_key(),
+            Self::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Insert `default` if the key is not in the cache, and return the
+    /// (possibly newly inserted) entry.
+    ///
+    /// An existing item is not promoted.
+    pub fn or_insert(self, default: V) -> Entry<'a, K, V, R> {
+        self.or_insert_with(|| default)
+    }
+
+    /// Insert the result of `default` if the key is not in the cache, and
+    /// return the (possibly newly inserted) entry.
+    ///
+    /// An existing item is not promoted.
+    pub fn or_insert_with(self, default: impl FnOnce() -> V) -> Entry<'a, K, V, R> {
+        match self {
+            Self::Occupied(entry) => entry,
+            Self::Vacant(entry) => entry.insert(default()),
+        }
+    }
+}
+
+/// An entry for a key which is not in the cache.
+///
+/// Part of `CacheEntry`.
+#[derive(Debug)]
+pub struct VacantEntry<'a, K, V, R: Rng = StdRng> {
+    /// A reference to the entire cache.
+    cache: &'a mut CommonCache<K, V, R>,
+    /// The key which is not in the cache.
+    key: K,
+}
+
+impl<'a, K: Eq + Hash, V, R: Rng> VacantEntry<'a, K, V, R> {
+    /// Get the key of the entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Take the key back without inserting anything.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Insert a value for the key, at the level where new items are inserted
+    /// by `CommonCache::insert`.
+    ///
+    /// **All `Index` to elements in the cache will be invalidated.**
+    pub fn insert(self, value: V) -> Entry<'a, K, V, R> {
+        let level = self.cache.levels.len().saturating_sub(2);
+        self.cache.insert_at_level::<true>(self.key, value, level)
+    }
+}
+
+/// An iterator adapter which yields at most `len` items and knows that the
+/// inner iterator has at least that many items.
+struct ExactLen<I> {
+    iter: I,
+    len: usize,
+}
+
+impl<I: Iterator> Iterator for ExactLen<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<I: Iterator> ExactSizeIterator for ExactLen<I> {}
+
+/// A cursor for traversing a `CommonCache` in rank order, from the most to the
+/// least commonly used item, across multiple calls.
+///
+/// Unlike `CommonCache::iter`, the cursor does not borrow the cache between
+/// calls to `Cursor::advance`, and it might optionally promote every visited
+/// item. Since promotion moves items between levels, the cursor keeps track of
+/// the keys of all visited items to make sure that no item is visited twice.
+/// Note however that promotion might discard items from the lowest level, so
+/// not all items in the cache will necessarily be visited if promotion is
+/// enabled.
+#[derive(Debug, Clone)]
+pub struct Cursor<K> {
+    /// The keys of all visited items.
+    visited: HashSet<K>,
+    /// Whether to promote the visited items.
+    promote: bool,
+    /// The rank of the last visited item together with the generation of the
+    /// cache at that time.
+    ///
+    /// As long as the generation of the cache is the same, the next item can be
+    /// searched for after this rank, otherwise the cache must be scanned from
+    /// the top.
+    last: Option<(usize, u64)>,
+}
+
+impl<K: Eq + Hash + Clone> Cursor<K> {
+    /// Create a new cursor positioned at the top of the cache.
+    ///
+    /// If `promote` is `true`, every item visited with `Cursor::advance` will be
+    /// promoted to a higher level in the cache, just like with
+    /// `Entry::get_key_value`.
+    pub fn new(promote: bool) -> Self {
+        Self {
+            visited: HashSet::new(),
+            promote,
+            last: None,
+        }
+    }
+
+    /// Move to the next item in the cache which has not yet been visited by
+    /// this cursor and return its key and value.
+    ///
+    /// Returns `None` when all items in the cache have been visited.
+    ///
+    /// If the cache has not been altered since the last call, the search
+    /// continues right after the previously visited item. Otherwise, (E.G if
+    /// promotion is enabled), the cache must be scanned from the top which
+    /// runs in linear time.
+    pub fn advance<'a, V, R: Rng>(
+        &mut self,
+        cache: &'a mut CommonCache<K, V, R>,
+    ) -> Option<(&'a K, &'a V)> {
+        let start = match self.last {
+            Some((rank, generation)) if generation == cache.generation => rank + 1,
+            _ => 0,
+        };
+        let (rank, (level, idx)) = cache
+            .levels
+            .iter()
+            .enumerate()
+            .flat_map(|(i, level)| (0..level.items.len()).map(move |j| (i, j)))
+            .enumerate()
+            .skip(start)
+            .find(|(_, (level, idx))| {
+                let (key, _) = cache.levels[*level].items.get_index(*idx).unwrap();
+                !self.visited.contains(key)
+            })?;
+        let entry = Entry { cache, level, idx };
+        self.visited.insert(entry.peek_key().clone());
+        let (key, value) = if self.promote {
+            self.last = None;
+            entry.get_long()
+        } else {
+            self.last = Some((rank, entry.cache.generation));
+            entry.peek_long()
+        };
+        Some((key, &*value))
+    }
+}
+
 /// An index into a `CommonCache`.
 ///
 /// This should be used when an `Entry` is not sufficient due to life time
@@ -625,6 +1532,18 @@ impl<K: Eq + Hash, V, R: Rng> Index<K, V, R> {
         cache.levels[self.level].items.get_index(self.idx).unwrap()
     }
 
+    /// Get the "heat" of the item at this index, see `Entry::heat`.
+    pub fn heat(&self, cache: &CommonCache<K, V, R>) -> f64 {
+        self.assert_generation(cache);
+        cache.heat_of_level(self.level)
+    }
+
+    /// Read the key and value at the index without promoting the item. This is
+    /// the same as `Self::peek_key_value`.
+    pub fn peek<'a>(&'a self, cache: &'a CommonCache<K, V, R>) -> (&'a K, &'a V) {
+        self.peek_key_value(cache)
+    }
+
     /// Silently read the key at this index.
     pub fn peek_key<'a>(&'a self, cache: &'a CommonCache<K, V, R>) -> &'a K {
         self.peek_key_value(cache).0
@@ -680,6 +1599,14 @@ impl<K: Eq + Hash, V, R: Rng> Index<K, V, R> {
             .peek_long()
     }
 
+    /// Get the key and value at this index and promote the item to a higher
+    /// level in the cache. This is the same as `Self::get_key_value`.
+    ///
+    /// **The index will be invalidated after this operation.**
+    pub fn get_promote(self, cache: &mut CommonCache<K, V, R>) -> (&K, &mut V) {
+        self.get_key_value(cache)
+    }
+
     /// Get the value at this index and promote this index to a higher level in
     /// the cache.
     ///
@@ -689,15 +1616,542 @@ impl<K: Eq + Hash, V, R: Rng> Index<K, V, R> {
         self.get_key_value(cache).1
     }
 
+    /// Promote the item at this index like `Self::get_value` and return a
+    /// clone of its value.
+    ///
+    /// Unlike the other methods, this does not panic if the index has been
+    /// invalidated, but returns `None`.
+    ///
+    /// **The index will be invalidated after this operation.**
+    pub fn take_value(self, cache: &mut CommonCache<K, V, R>) -> Option<V>
+    where
+        V: Clone,
+    {
+        let is_valid = self.generation == cache.generation
+            && cache
+                .levels
+                .get(self.level)
+                .is_some_and(|x| self.idx < x.items.len());
+        is_valid.then(|| self.get_value(cache).clone())
+    }
+
     /// Remove the item at this index from the cache.
     fn remove_from(self, cache: &mut CommonCache<K, V, R>) -> (K, V) {
         self.assert_generation(cache);
-        let level_items = &mut cache.levels[self.level].items;
-        let (key, value) = level_items.swap_remove_index(self.idx).unwrap();
-        if level_items.is_empty() && self.level == cache.levels.len() - 1 {
-            // If the last level became empty, we shall remove it.
-            cache.levels.pop();
+        cache.remove_at(self.level, self.idx)
+    }
+}
+
+#[cfg(test)]
+mod proptests;
+
+#[cfg(test)]
+mod tests {
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+
+    /// Create a cache with a seeded RNG and the numbers 0..n inserted.
+    fn test_cache(n: u32) -> CommonCache<u32, u32, ChaCha8Rng> {
+        let mut cache = CommonCache::new_with_rng(2, None, ChaCha8Rng::seed_from_u64(0));
+        for i in 0..n {
+            cache.insert(i, i);
         }
-        (key, value)
+        cache
+    }
+
+    /// Create a cache with the given items at each level, without checking
+    /// any invariants.
+    fn cache_from_levels(levels: Vec<Vec<(u32, u32)>>) -> CommonCache<u32, u32, ChaCha8Rng> {
+        let mut cache = CommonCache::new_with_rng(2, None, ChaCha8Rng::seed_from_u64(0));
+        for (i, items) in levels.into_iter().enumerate() {
+            cache.levels.push(Level {
+                items: items.into_iter().collect(),
+                rand_range: (0..cache.level_capacity(i)).into(),
+            });
+        }
+        cache.len = cache.levels.iter().map(|x| x.items.len()).sum();
+        cache
+    }
+
+    #[test]
+    fn test_entry_checked() {
+        let mut cache = test_cache(50);
+        for key in 0..60 {
+            let checked = cache.entry_checked(&key).unwrap().map(|x| x.index());
+            let unchecked = cache.entry(&key).map(|x| x.index());
+            assert_eq!(checked, unchecked);
+        }
+
+        let mut cache = cache_from_levels(vec![vec![(1, 1)], vec![(2, 2), (3, 3)], vec![(3, 30)]]);
+        assert_eq!(*cache.entry_checked(&2).unwrap().unwrap().peek_value(), 2);
+        let err = cache.entry_checked(&3).unwrap_err();
+        assert_eq!(err.levels, [1, 2]);
+        assert_eq!(
+            err.to_string(),
+            "The key was found at several levels in the cache: [1, 2]"
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "A key was found at several levels")]
+    fn test_entry_with_duplicate_key() {
+        let mut cache = cache_from_levels(vec![vec![(1, 1)], vec![(2, 2), (3, 3)], vec![(3, 30)]]);
+        cache.entry(&3);
+    }
+
+    #[test]
+    fn test_set_max_size() {
+        let mut cache = CommonCache::new(2, None);
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        cache.set_max_size(10);
+        assert_eq!(cache.max_size(), 10);
+        assert_eq!(cache.size(), 10);
+        cache.set_max_size(20);
+        assert_eq!(cache.max_size(), 20);
+    }
+
+    #[test]
+    fn test_iter_levels() {
+        let cache = test_cache(100);
+        let occupancy = cache.level_occupancy();
+        assert!(occupancy.len() > 2);
+        assert_eq!(occupancy.iter().sum::<usize>(), 100);
+        let mut flattened = Vec::new();
+        for (i, items) in cache.iter_levels() {
+            assert_eq!(items.len(), occupancy[i]);
+            assert!(items.len() <= cache.level_capacity(i));
+            flattened.extend(items.map(|(&key, _)| key));
+        }
+        assert!(cache.iter().map(|(&key, _)| key).eq(flattened));
+        assert_eq!(cache.iter_levels().count(), occupancy.len());
+    }
+
+    #[test]
+    fn test_debug_pyramid() {
+        let cache = cache_from_levels(vec![vec![(3, 0)], vec![(7, 0), (1, 0)], vec![(5, 0)]]);
+        assert_eq!(
+            cache.debug_pyramid(),
+            "level 0 (1/1): [3]\nlevel 1 (2/2): [7, 1]\nlevel 2 (1/4): [5]\n"
+        );
+
+        let cache = test_cache(100);
+        let pyramid = cache.debug_pyramid();
+        assert_eq!(pyramid.lines().count(), cache.level_occupancy().len());
+        for (line, occupancy) in pyramid.lines().zip(cache.level_occupancy()) {
+            let items = line.split_once(": ").unwrap().1;
+            assert_eq!(items.split(", ").count(), occupancy, "{line}");
+        }
+        assert_eq!(CommonCache::<u32, u32>::new(2, None).debug_pyramid(), "");
+    }
+
+    #[test]
+    fn test_promote_and_demote() {
+        let mut cache = cache_from_levels(vec![vec![(1, 0)], vec![(2, 0), (3, 0)], vec![(4, 0)]]);
+        let entry = cache.entry(&1).unwrap().demote().unwrap();
+        assert_eq!((entry.level, entry.peek()), (1, (&1, &0)));
+        assert_eq!(cache.find_key(&1).unwrap().0, 1);
+        // The level below was full, so one of its items took the place of 1.
+        assert_eq!(cache.level_occupancy(), [1, 2, 1]);
+        let (&up, _) = cache.iter().next().unwrap();
+        assert!(up == 2 || up == 3);
+        // Demoting an item at the lowest level discards it.
+        assert!(cache.entry(&4).unwrap().demote().is_none());
+        assert!(cache.entry(&4).is_none());
+        assert_eq!(cache.level_occupancy(), [1, 2]);
+        assert_eq!(cache.size(), 3);
+
+        let mut cache = test_cache(100);
+        let (&key, _) = cache.iter().next_back().unwrap();
+        let level = cache.find_key(&key).unwrap().0;
+        assert!(level > 0);
+        let entry = cache.entry(&key).unwrap().promote();
+        assert_eq!((entry.level, entry.peek()), (level - 1, (&key, &key)));
+        assert_eq!(cache.find_key(&key).unwrap().0, level - 1);
+        assert_eq!(cache.size(), 100);
+        let entry = cache.entry(&key).unwrap().demote().unwrap();
+        assert_eq!(entry.level, level);
+    }
+
+    #[test]
+    fn test_index_of() {
+        let mut cache = test_cache(50);
+        assert!(cache.index_of(&50).is_none());
+        for key in [0, 7, 49] {
+            let index = cache.index_of(&key).unwrap();
+            assert_eq!(index.peek(&cache), (&key, &key));
+            let entry = index.entry(&mut cache);
+            assert_eq!(entry.peek(), (&key, &key));
+        }
+        let level = cache.find_key(&7).unwrap().0;
+        let index = cache.index_of(&7).unwrap();
+        assert_eq!(index.get_promote(&mut cache), (&7, &mut 7));
+        assert_eq!(cache.find_key(&7).unwrap().0, level.saturating_sub(1));
+    }
+
+    #[test]
+    fn test_insert_many() {
+        let mut cache = test_cache(10);
+        let index = cache.entry(&3).unwrap().index();
+        let generation = cache.generation;
+        cache.insert_many((10..1000).map(|i| (i, i)));
+        assert_eq!(cache.generation, generation + 1);
+        assert_eq!(cache.size(), 1000);
+        assert_ne!(index.generation, cache.generation);
+
+        cache.insert_many([]);
+        assert_eq!(cache.generation, generation + 2);
+        cache.extend([(1000, 1000), (1001, 1001)]);
+        assert_eq!(cache.generation, generation + 3);
+        assert_eq!(*cache.entry(&1001).unwrap().peek_value(), 1001);
+    }
+
+    #[test]
+    fn test_memory_estimate() {
+        let mut cache = test_cache(0);
+        let empty = cache.memory_estimate();
+        let mut previous = empty;
+        for i in 0..1000 {
+            cache.insert(i, i);
+            let estimate = cache.memory_estimate();
+            assert!(estimate >= previous, "{estimate} < {previous} after {i}");
+            previous = estimate;
+        }
+        // At least the keys and values of all items are counted.
+        assert!(previous >= empty + 1000 * 2 * size_of::<u32>());
+    }
+
+    #[test]
+    fn test_clear_keep_capacity() {
+        fn total_capacity(cache: &CommonCache<u32, u32, ChaCha8Rng>) -> usize {
+            cache
+                .levels
+                .iter()
+                .map(|x| &x.items)
+                .chain(&cache.reserved_levels)
+                .map(IndexMap::capacity)
+                .sum()
+        }
+        let mut cache = test_cache(100);
+        let capacities: Vec<usize> = cache.levels.iter().map(|x| x.items.capacity()).collect();
+        let capacity = total_capacity(&cache);
+        let index = cache.index_of(&5).unwrap();
+        cache.clear_keep_capacity();
+        assert_eq!(cache.size(), 0);
+        assert!(cache.levels.is_empty());
+        assert_eq!(cache.iter().count(), 0);
+        assert!(cache.entry(&5).is_none());
+        assert_ne!(index.generation, cache.generation);
+        // No memory is released, but the reported capacity of a map might
+        // grow when it is cleared, since removed items leave tombstones in its
+        // hash table which count against the capacity until then.
+        assert!(total_capacity(&cache) >= capacity);
+        // The levels get their old maps back when they are created again.
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        for (level, capacity) in cache.levels.iter().zip(&capacities) {
+            assert!(level.items.capacity() >= *capacity);
+        }
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let mut cache = CommonCache::with_capacity(2, None, 100);
+        let reserved: Vec<usize> = cache
+            .reserved_levels
+            .iter()
+            .map(IndexMap::capacity)
+            .collect();
+        // 1 + 2 + 4 + 8 + 16 + 32 + 37 = 100
+        assert_eq!(reserved.len(), 7);
+        assert!(reserved[6] >= 37);
+        cache.insert(0, 0);
+        // The first level is taken from the reserved ones rather than allocated.
+        assert_eq!(cache.reserved_levels[0].capacity(), 0);
+        assert_eq!(cache.levels[0].items.capacity(), reserved[0]);
+        for i in 1..20 {
+            cache.insert(i, i);
+        }
+        for (level, capacity) in cache.levels.iter().zip(&reserved) {
+            assert!(level.items.capacity() >= *capacity);
+        }
+
+        let cache = CommonCache::<u32, u32>::with_capacity(2, Some(3), 100);
+        assert_eq!(cache.reserved_levels.len(), 2);
+        assert!(CommonCache::<u32, u32>::with_capacity(2, None, 0)
+            .reserved_levels
+            .is_empty());
+    }
+
+    #[test]
+    fn test_min_size() {
+        // Repeatedly promote a few items so that the others drift down and are
+        // discarded, and return the smallest size of the cache during that.
+        fn min_size_during_promotions(min_size: usize) -> usize {
+            let mut cache = test_cache(40);
+            cache.set_min_size(min_size);
+            let mut smallest = cache.size();
+            for i in 0..2000 {
+                let key = i % 3;
+                match cache.entry(&key) {
+                    Some(mut entry) => {
+                        entry.get_value();
+                    }
+                    None => {
+                        cache.insert(key, key);
+                    }
+                }
+                smallest = smallest.min(cache.size());
+            }
+            smallest
+        }
+        assert!(min_size_during_promotions(0) < 10);
+        assert_eq!(min_size_during_promotions(10), 10);
+        assert_eq!(min_size_during_promotions(40), 40);
+    }
+
+    #[test]
+    fn test_aggressiveness() {
+        // Access a hot key between accesses of random cold keys until it
+        // reaches the top level, and return the average number of accesses of
+        // the hot key needed over many runs.
+        fn hot_accesses_to_top(aggressiveness: f64) -> f64 {
+            const RUNS: u64 = 200;
+            let mut total = 0;
+            for seed in 0..RUNS {
+                let mut cache = test_cache(100)
+                    .replace_rng(ChaCha8Rng::seed_from_u64(seed))
+                    .0;
+                cache.set_aggressiveness(aggressiveness);
+                let mut rng = ChaCha8Rng::seed_from_u64(RUNS + seed);
+                let hot = 1000;
+                cache.insert(hot, hot);
+                for accesses in 1.. {
+                    let key = rng.gen_range(0..100);
+                    cache.insert(key, key);
+                    cache.insert(hot, hot);
+                    if cache.find_key(&hot).unwrap().0 == 0 {
+                        total += accesses;
+                        break;
+                    }
+                }
+            }
+            total as f64 / RUNS as f64
+        }
+        let default = hot_accesses_to_top(1.0);
+        assert!(hot_accesses_to_top(4.0) < default);
+        assert!(hot_accesses_to_top(0.25) > default);
+    }
+
+    #[test]
+    #[should_panic(expected = "aggressiveness must be positive")]
+    fn test_zero_aggressiveness() {
+        CommonCache::<u32, u32>::new(2, None).set_aggressiveness(0.0);
+    }
+
+    #[test]
+    fn test_take_value() {
+        let mut cache = cache_from_levels(vec![vec![(0, 0)], vec![(1, 1), (2, 2)]]);
+        let index = cache.index_of(&2).unwrap();
+        assert_eq!(index.take_value(&mut cache), Some(2));
+        assert_eq!(cache.find_key(&2).unwrap().0, 0);
+        assert_eq!(cache.size(), 3);
+        // The promotion invalidated the index.
+        let stale = cache.index_of(&1).unwrap();
+        cache.insert(3, 3);
+        assert_eq!(stale.take_value(&mut cache), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "min_size must be <= max_size")]
+    fn test_min_size_above_max_size() {
+        let mut cache = CommonCache::<u32, u32>::new(2, Some(10));
+        cache.set_min_size(11);
+    }
+
+    #[test]
+    fn test_entry_api_occupied() {
+        let mut cache = test_cache(20);
+        let level = cache.find_key(&7).unwrap().0;
+        let generation = cache.generation;
+        let CacheEntry::Occupied(entry) = cache.entry_api(7) else {
+            panic!("Expected an occupied entry");
+        };
+        assert_eq!(entry.peek(), (&7, &7));
+        assert_eq!(cache.generation, generation);
+        let entry = match cache.entry_api(7) {
+            CacheEntry::Occupied(entry) => entry,
+            CacheEntry::Vacant(_) => unreachable!(),
+        };
+        *entry.peek_long().1 = 70;
+        // `or_insert` keeps the existing value and doesn't promote it.
+        let mut entry = cache
+            .entry_api(7)
+            .or_insert_with(|| panic!("Should not be called"));
+        assert_eq!(*entry.peek_value(), 70);
+        assert_eq!(entry.get_promote(), (&7, &mut 70));
+        assert_eq!(cache.find_key(&7).unwrap().0, level.saturating_sub(1));
+    }
+
+    #[test]
+    fn test_entry_api_vacant() {
+        let mut cache = test_cache(20);
+        let expected_level = cache.levels.len() - 2;
+        let CacheEntry::Vacant(entry) = cache.entry_api(100) else {
+            panic!("Expected a vacant entry");
+        };
+        assert_eq!(*entry.key(), 100);
+        assert_eq!(entry.into_key(), 100);
+        assert_eq!(cache.size(), 20);
+
+        assert_eq!(*cache.entry_api(100).key(), 100);
+        let entry = cache.entry_api(100).or_insert(1000);
+        assert_eq!(entry.peek(), (&100, &1000));
+        assert_eq!(cache.size(), 21);
+        assert_eq!(cache.find_key(&100).unwrap().0, expected_level);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut cache = test_cache(50);
+        let keys = cache.snapshot_keys();
+        assert!(keys.iter().eq(cache.iter().map(|(key, _)| key)));
+        let items = cache.snapshot();
+        assert!(items.iter().map(|(k, v)| (k, v)).eq(cache.iter()));
+        // The cache can be mutated while going through the snapshot.
+        for key in keys {
+            if key % 2 == 0 {
+                cache.take(&key);
+            }
+        }
+        assert_eq!(cache.size(), 25);
+        assert_eq!(items.len(), 50);
+    }
+
+    #[test]
+    fn test_heat() {
+        let mut cache = test_cache(100);
+        let top = cache.iter_indices().next().unwrap();
+        let bottom = cache.iter_indices().next_back().unwrap();
+        assert_eq!(top.heat(&cache), 1.0);
+        assert!(top.heat(&cache) > bottom.heat(&cache));
+        let num_levels = cache.levels.len() as f64;
+        assert!((bottom.entry(&mut cache).heat() - 1.0 / num_levels).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_take() {
+        let mut cache = test_cache(20);
+        let generation = cache.generation;
+        assert_eq!(cache.take(&7), Some(7));
+        assert_eq!(cache.generation, generation + 1);
+        assert_eq!(cache.size(), 19);
+        assert!(cache.entry(&7).is_none());
+        assert_eq!(cache.take(&7), None);
+        assert_eq!(cache.generation, generation + 1);
+    }
+
+    #[test]
+    fn test_reinsert_preserving_generation() {
+        let mut cache = test_cache(20);
+        let generation = cache.generation;
+        let index = cache.entry(&7).unwrap().index();
+        let order: Vec<_> = cache.iter().map(|(&key, _)| key).collect();
+        let old = cache.entry(&7).unwrap().reinsert_preserving_generation(70);
+        assert_eq!(old, 7);
+        assert_eq!(cache.generation, generation);
+        // The index is still valid and points to the new value.
+        assert_eq!(index.peek_key_value(&cache), (&7, &70));
+        assert!(cache.iter().map(|(&key, _)| key).eq(order));
+    }
+
+    #[test]
+    fn test_clone_with_rng() {
+        let (mut cache, _old_rng) = test_cache(20).replace_rng(ChaCha8Rng::seed_from_u64(42));
+        let mut copy = cache.clone_with_rng(ChaCha8Rng::seed_from_u64(42));
+        assert!(cache.iter().eq(copy.iter()));
+        // Both caches have equally seeded RNGs, so they should continue to be
+        // equal.
+        for i in 10..40 {
+            cache.insert(i, i);
+            copy.insert(i, i);
+        }
+        assert!(cache.iter().eq(copy.iter()));
+    }
+
+    #[test]
+    fn test_clone_with_other_rng_diverges() {
+        let mut cache = test_cache(20);
+        let mut copy = cache.clone_with_rng(ChaCha8Rng::seed_from_u64(42));
+        assert!(cache.iter().eq(copy.iter()));
+        assert_eq!(copy.generation, cache.generation);
+        for i in 20..200 {
+            cache.insert(i, i);
+            copy.insert(i, i);
+        }
+        // The same items are in both caches, but at other positions.
+        assert!(!cache.iter().eq(copy.iter()));
+        let mut keys: Vec<_> = cache.iter().map(|(&key, _)| key).collect();
+        let mut copy_keys: Vec<_> = copy.iter().map(|(&key, _)| key).collect();
+        keys.sort_unstable();
+        copy_keys.sort_unstable();
+        assert_eq!(keys, copy_keys);
+    }
+
+    #[test]
+    fn test_top_n() {
+        let mut cache = test_cache(20);
+        // Make 3 the hottest item.
+        for _ in 0..10 {
+            cache.entry(&3).unwrap().get_value();
+        }
+        let top = cache.top_n(2);
+        assert_eq!(top.len(), 2);
+        let top: Vec<_> = top.collect();
+        assert_eq!(top[0], (&3, &3));
+        assert!(top.into_iter().eq(cache.iter().take(2)));
+        assert_eq!(cache.top_n(100).len(), cache.size());
+        assert!(cache.top_n(100).eq(cache.iter()));
+    }
+
+    #[test]
+    fn test_cursor() {
+        let mut cache = test_cache(20);
+        let mut cursor = Cursor::new(false);
+        let mut visited = Vec::new();
+        while let Some((&key, _)) = cursor.advance(&mut cache) {
+            visited.push(key);
+        }
+        assert!(visited.iter().eq(cache.iter().map(|(key, _)| key)));
+
+        let mut cursor = Cursor::new(true);
+        let mut visited = HashSet::new();
+        while let Some((&key, _)) = cursor.advance(&mut cache) {
+            assert!(visited.insert(key), "{key} was visited twice");
+        }
+        // Some items might have been discarded during promotion, but all remaining
+        // items should have been visited.
+        assert!(cache.iter().all(|(key, _)| visited.contains(key)));
+    }
+
+    #[test]
+    fn test_replace_rng() {
+        let mut cache = CommonCache::new(2, None);
+        for i in 0..20 {
+            cache.insert(i, i);
+        }
+        let copy = cache.clone();
+        let (mut cache, _old_rng) = cache.replace_rng(ChaCha8Rng::seed_from_u64(42));
+        let (mut copy, _old_rng) = copy.replace_rng(ChaCha8Rng::seed_from_u64(42));
+        assert!(cache.iter().eq(copy.iter()));
+        for i in 10..40 {
+            cache.insert(i, i);
+            copy.insert(i, i);
+        }
+        assert!(cache.iter().eq(copy.iter()));
     }
 }
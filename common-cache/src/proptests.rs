@@ -0,0 +1,300 @@
+//! Property tests checking that the structure of the cache follows the
+//! algorithm described in the module level documentation.
+
+use proptest::prelude::*;
+
+use super::*;
+
+/// The caches use a seeded `StdRng` (rather than `ChaCha8Rng`, as in the other
+/// tests) so that `set_max_size` is available.
+type TestCache = CommonCache<u8, u32>;
+
+/// An operation on the cache.
+#[derive(Debug, Clone)]
+enum Op {
+    Insert(u8, u32),
+    Get(u8),
+    Peek(u8),
+    Remove(u8),
+    Take(u8),
+    Promote(u8),
+    Demote(u8),
+}
+
+impl Op {
+    fn apply(&self, cache: &mut TestCache) {
+        match *self {
+            Op::Insert(key, value) => {
+                cache.insert(key, value);
+            }
+            Op::Get(key) => {
+                if let Some(mut entry) = cache.entry(&key) {
+                    entry.get_value();
+                }
+            }
+            Op::Peek(key) => {
+                if let Some(entry) = cache.entry(&key) {
+                    entry.peek_value();
+                }
+            }
+            Op::Remove(key) => {
+                if let Some(entry) = cache.entry(&key) {
+                    entry.remove();
+                }
+            }
+            Op::Take(key) => {
+                cache.take(&key);
+            }
+            Op::Promote(key) => {
+                if let Some(entry) = cache.entry(&key) {
+                    entry.promote();
+                }
+            }
+            Op::Demote(key) => {
+                if let Some(entry) = cache.entry(&key) {
+                    entry.demote();
+                }
+            }
+        }
+    }
+}
+
+/// Keys are drawn from a small range so that operations often hit existing
+/// items.
+fn arb_key() -> impl Strategy<Value = u8> {
+    0..48u8
+}
+
+fn arb_op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        4 => (arb_key(), any::<u32>()).prop_map(|(key, value)| Op::Insert(key, value)),
+        3 => arb_key().prop_map(Op::Get),
+        1 => arb_key().prop_map(Op::Peek),
+        1 => arb_key().prop_map(Op::Remove),
+        1 => arb_key().prop_map(Op::Take),
+        1 => arb_key().prop_map(Op::Promote),
+        1 => arb_key().prop_map(Op::Demote),
+    ]
+}
+
+/// A cache with a random base, max size and seed, after a random sequence of
+/// operations.
+fn arb_cache() -> impl Strategy<Value = TestCache> {
+    (
+        2..5usize,
+        prop::option::of(2..40usize),
+        any::<u64>(),
+        prop::collection::vec(arb_op(), 0..200),
+    )
+        .prop_map(|(base, max_size, seed, ops)| {
+            let mut cache = CommonCache::new_with_rng(base, max_size, StdRng::seed_from_u64(seed));
+            for op in &ops {
+                op.apply(&mut cache);
+            }
+            cache
+        })
+}
+
+/// The keys at each level of the cache.
+fn keys_by_level(cache: &TestCache) -> Vec<Vec<u8>> {
+    cache
+        .levels
+        .iter()
+        .map(|level| level.items.keys().copied().collect())
+        .collect()
+}
+
+/// The number of items in the cache, counted level by level.
+fn sum_of_levels(cache: &TestCache) -> usize {
+    cache.levels.iter().map(|level| level.items.len()).sum()
+}
+
+/// The level of a key in the cache.
+fn level_of(cache: &TestCache, key: u8) -> Option<usize> {
+    cache.find_key(&key).map(|(level, _)| level)
+}
+
+proptest! {
+    #[test]
+    fn levels_are_within_capacity(cache in arb_cache()) {
+        for (i, level) in cache.levels.iter().enumerate() {
+            let capacity = cache.base.checked_pow(i as u32).unwrap_or(usize::MAX);
+            prop_assert!(level.items.len() <= capacity, "level {} has {} items", i, level.items.len());
+        }
+    }
+
+    #[test]
+    fn last_level_is_non_empty(cache in arb_cache()) {
+        if let Some(last) = cache.levels.last() {
+            prop_assert!(!last.items.is_empty());
+        }
+    }
+
+    #[test]
+    fn size_is_within_max_size(cache in arb_cache()) {
+        prop_assert!(cache.size() <= cache.max_size);
+    }
+
+    #[test]
+    fn size_is_sum_of_levels(
+        mut cache in arb_cache(),
+        (max_size, min_size) in (2..40usize).prop_flat_map(|max| (Just(max), 0..=max)),
+        ops in prop::collection::vec(arb_op(), 0..100),
+    ) {
+        prop_assert_eq!(cache.size(), sum_of_levels(&cache));
+        cache.set_max_size(max_size);
+        prop_assert_eq!(cache.size(), sum_of_levels(&cache));
+        cache.set_min_size(min_size);
+        for op in &ops {
+            op.apply(&mut cache);
+            prop_assert_eq!(cache.size(), sum_of_levels(&cache), "after {:?}", op);
+        }
+        cache.clear();
+        prop_assert_eq!(cache.size(), 0);
+    }
+
+    #[test]
+    fn iter_is_ordered_by_level(cache in arb_cache()) {
+        let by_level: Vec<u8> = keys_by_level(&cache).into_iter().flatten().collect();
+        let iterated: Vec<u8> = cache.iter().map(|(&key, _)| key).collect();
+        prop_assert_eq!(iterated, by_level);
+    }
+
+    #[test]
+    fn iter_yields_all_items(cache in arb_cache()) {
+        prop_assert_eq!(cache.iter().count(), cache.size());
+    }
+
+    #[test]
+    fn iter_rev_is_reversed_iter(cache in arb_cache()) {
+        let mut forward: Vec<_> = cache.iter().collect();
+        forward.reverse();
+        prop_assert_eq!(cache.iter().rev().collect::<Vec<_>>(), forward);
+    }
+
+    #[test]
+    fn keys_are_unique(cache in arb_cache()) {
+        let mut keys: Vec<u8> = cache.iter().map(|(&key, _)| key).collect();
+        let len = keys.len();
+        keys.sort_unstable();
+        keys.dedup();
+        prop_assert_eq!(keys.len(), len);
+    }
+
+    #[test]
+    fn heat_is_non_increasing(cache in arb_cache()) {
+        let heats: Vec<f64> = cache.iter_indices().map(|index| index.heat(&cache)).collect();
+        for heat in &heats {
+            prop_assert!(*heat > 0.0 && *heat <= 1.0);
+        }
+        for pair in heats.windows(2) {
+            prop_assert!(pair[0] >= pair[1]);
+        }
+    }
+
+    #[test]
+    fn top_n_is_prefix_of_iter(cache in arb_cache(), n in 0..60usize) {
+        let top = cache.top_n(n);
+        prop_assert_eq!(top.len(), n.min(cache.size()));
+        prop_assert!(top.eq(cache.iter().take(n)));
+    }
+
+    #[test]
+    fn new_key_is_inserted_at_second_lowest_level(
+        mut cache in arb_cache(),
+        key in arb_key(),
+        value in any::<u32>(),
+    ) {
+        prop_assume!(level_of(&cache, key).is_none());
+        let expected_level = cache.levels.len().saturating_sub(2);
+        cache.insert(key, value);
+        prop_assert_eq!(level_of(&cache, key), Some(expected_level));
+        prop_assert_eq!(*cache.entry(&key).unwrap().peek_value(), value);
+    }
+
+    #[test]
+    fn reinserted_key_moves_up_one_level(
+        mut cache in arb_cache(),
+        key in arb_key(),
+        value in any::<u32>(),
+    ) {
+        let Some(level) = level_of(&cache, key) else {
+            return Ok(());
+        };
+        let size = cache.size();
+        cache.insert(key, value);
+        prop_assert_eq!(level_of(&cache, key), Some(level.saturating_sub(1)));
+        prop_assert_eq!(*cache.entry(&key).unwrap().peek_value(), value);
+        prop_assert!(cache.size() <= size);
+    }
+
+    #[test]
+    fn get_promotes_one_level(mut cache in arb_cache(), key in arb_key()) {
+        let Some(level) = level_of(&cache, key) else {
+            return Ok(());
+        };
+        let generation = cache.generation;
+        let value = *cache.entry(&key).unwrap().peek_value();
+        prop_assert_eq!(*cache.entry(&key).unwrap().get_value(), value);
+        prop_assert_eq!(level_of(&cache, key), Some(level.saturating_sub(1)));
+        prop_assert!(cache.generation > generation);
+    }
+
+    #[test]
+    fn peek_does_not_alter_the_cache(mut cache in arb_cache(), key in arb_key()) {
+        let before = keys_by_level(&cache);
+        let generation = cache.generation;
+        if let Some(mut entry) = cache.entry(&key) {
+            entry.peek_value_mut();
+            entry.peek();
+        }
+        prop_assert_eq!(keys_by_level(&cache), before);
+        prop_assert_eq!(cache.generation, generation);
+    }
+
+    #[test]
+    fn removed_key_is_absent(mut cache in arb_cache(), key in arb_key()) {
+        let size = cache.size();
+        let Some(entry) = cache.entry(&key) else {
+            return Ok(());
+        };
+        prop_assert_eq!(entry.remove().0, key);
+        prop_assert!(level_of(&cache, key).is_none());
+        prop_assert_eq!(cache.size(), size - 1);
+    }
+
+    #[test]
+    fn take_removes_and_invalidates(mut cache in arb_cache(), key in arb_key()) {
+        let expected = cache.entry(&key).map(|entry| *entry.peek_value());
+        let generation = cache.generation;
+        prop_assert_eq!(cache.take(&key), expected);
+        prop_assert!(level_of(&cache, key).is_none());
+        let expected_generation = generation + u64::from(expected.is_some());
+        prop_assert_eq!(cache.generation, expected_generation);
+    }
+
+    #[test]
+    fn set_max_size_bounds_size(mut cache in arb_cache(), max_size in 2..40usize) {
+        cache.set_max_size(max_size);
+        prop_assert!(cache.size() <= max_size);
+        if let Some(last) = cache.levels.last() {
+            prop_assert!(!last.items.is_empty());
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_same_cache(
+        base in 2..5usize,
+        seed in any::<u64>(),
+        ops in prop::collection::vec(arb_op(), 0..200),
+    ) {
+        let run = || {
+            let mut cache = CommonCache::new_with_rng(base, None, StdRng::seed_from_u64(seed));
+            for op in &ops {
+                op.apply(&mut cache);
+            }
+            keys_by_level(&cache)
+        };
+        prop_assert_eq!(run(), run());
+    }
+}
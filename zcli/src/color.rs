@@ -0,0 +1,101 @@
+//! Colors for the text output of zcli.
+
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::io::IsTerminal as _;
+
+use nu_ansi_term::{Color, Style};
+
+/// The styles used to color sender names, topics, timestamps and stream names,
+/// or no styles at all if colors are disabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    enabled: bool,
+}
+
+impl Palette {
+    /// A palette which doesn't color anything.
+    pub const PLAIN: Self = Self { enabled: false };
+
+    /// A palette which colors everything.
+    pub const COLORED: Self = Self { enabled: true };
+
+    /// Colors are used if stdout is a terminal and the `NO_COLOR` environment
+    /// variable is not set.
+    pub fn from_env() -> Self {
+        Self::detect(
+            std::io::stdout().is_terminal(),
+            std::env::var_os("NO_COLOR").as_deref(),
+        )
+    }
+
+    /// Colors are used if the output is a terminal and `no_color`, the value of
+    /// `NO_COLOR`, is unset or empty, see <https://no-color.org>.
+    pub fn detect(is_terminal: bool, no_color: Option<&OsStr>) -> Self {
+        Self {
+            enabled: is_terminal && no_color.is_none_or(OsStr::is_empty),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn paint(&self, style: Style, text: impl Display) -> String {
+        if self.enabled {
+            style.paint(text.to_string()).to_string()
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// The name of the sender of a message.
+    pub fn sender(&self, name: &str) -> String {
+        self.paint(Color::Cyan.bold(), name)
+    }
+
+    /// The name of a topic.
+    pub fn topic(&self, name: &str) -> String {
+        self.paint(Color::Yellow.bold(), name)
+    }
+
+    /// A (possibly humanized) timestamp.
+    pub fn timestamp(&self, timestamp: impl Display) -> String {
+        self.paint(Style::new().dimmed(), timestamp)
+    }
+
+    /// The name of a stream, in the color of the subscription if there is one.
+    pub fn stream(&self, name: &str, color: Option<zulib::stream::Color>) -> String {
+        let style = match color {
+            Some(zulib::stream::Color { r, g, b }) => Color::Rgb(r, g, b).bold(),
+            None => Color::Blue.bold(),
+        };
+        self.paint(style, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert!(Palette::detect(true, None).is_enabled());
+        assert!(Palette::detect(true, Some(OsStr::new(""))).is_enabled());
+        assert!(!Palette::detect(true, Some(OsStr::new("1"))).is_enabled());
+        assert!(!Palette::detect(false, None).is_enabled());
+        assert!(!Palette::detect(false, Some(OsStr::new("1"))).is_enabled());
+    }
+
+    #[test]
+    fn test_paint() {
+        let color = zulib::stream::Color::from_hex("#76ce90");
+        assert_eq!(Palette::PLAIN.stream("general", color), "general");
+        assert_eq!(Palette::PLAIN.sender("You"), "You");
+        assert_eq!(
+            Palette::COLORED.stream("general", color),
+            "\x1b[1;38;2;118;206;144mgeneral\x1b[0m"
+        );
+        assert!(Palette::COLORED.topic("greetings").contains("\x1b["));
+    }
+}
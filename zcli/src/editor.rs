@@ -0,0 +1,54 @@
+//! Composition of messages in the user's editor.
+
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+
+/// An environment variable with the editor command to use, taking precedence
+/// over `$VISUAL` and `$EDITOR`.
+pub const EDITOR_VAR: &str = "ZCLI_EDITOR";
+
+/// Get the command for the user's editor, from `$ZCLI_EDITOR`, `$VISUAL` or
+/// `$EDITOR`, defaulting to "vi".
+fn editor_command() -> String {
+    [EDITOR_VAR, "VISUAL", "EDITOR"]
+        .into_iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|x| !x.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string())
+}
+
+/// Let the user write a message in their editor.
+///
+/// The editor is opened on a temporary file containing `initial`, and the
+/// content of the file when the editor exits is returned. The editor command
+/// may contain arguments separated by whitespace, like "code --wait". If
+/// `editor` is `None`, it is taken from the environment.
+///
+/// Fails if the editor exits unsuccessfully.
+pub fn compose(editor: Option<&str>, initial: &str) -> Result<String> {
+    let path = std::env::temp_dir().join(format!(
+        "zcli-message-{}-{}.md",
+        std::process::id(),
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+    ));
+    std::fs::write(&path, initial)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    let command = editor.map_or_else(editor_command, str::to_string);
+    let mut args = command.split_whitespace();
+    let status = Command::new(args.next().unwrap_or_default())
+        .args(args)
+        .arg(&path)
+        .status();
+    let content = std::fs::read_to_string(&path);
+    // Don't leave the message behind, even if the editor failed.
+    let _ = std::fs::remove_file(&path);
+    let status = status.with_context(|| format!("Failed to run the editor: {command}"))?;
+    if !status.success() {
+        bail!("The editor ({command}) exited with {status}");
+    }
+    content.with_context(|| format!("Failed to read {}", path.display()))
+}
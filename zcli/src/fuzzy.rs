@@ -0,0 +1,108 @@
+//! Fuzzy matching of stream and topic names, as a friendlier alternative to
+//! regular expressions for interactive use.
+
+/// Bonus for every matched character directly following another matched
+/// character.
+const CONSECUTIVE_BONUS: i64 = 4;
+/// Bonus for every matched character at the start of a word.
+const WORD_START_BONUS: i64 = 3;
+/// Bonus if the query is a substring of the name.
+const SUBSTRING_BONUS: i64 = 10;
+/// Bonus if the name starts with the query.
+const PREFIX_BONUS: i64 = 20;
+/// Bonus if the name is equal to the query.
+const EXACT_BONUS: i64 = 100;
+
+/// Score how well a query fuzzy matches a name. Higher is better.
+///
+/// The name matches if all characters in the query occur in the name in the
+/// same order, case insensitively. Consecutive characters, characters at word
+/// starts and names containing, starting with or equal to the query are
+/// rewarded.
+///
+/// Returns `None` if the name doesn't match at all.
+pub fn score(query: &str, name: &str) -> Option<i64> {
+    let query = query.to_lowercase();
+    let name = name.to_lowercase();
+    let mut query_chars = query.chars().peekable();
+    let mut score = 0;
+    let mut prev: Option<char> = None;
+    let mut prev_matched = false;
+    for c in name.chars() {
+        if query_chars.peek() == Some(&c) {
+            query_chars.next();
+            score += 1;
+            if prev_matched {
+                score += CONSECUTIVE_BONUS;
+            }
+            if !prev.is_some_and(char::is_alphanumeric) {
+                score += WORD_START_BONUS;
+            }
+            prev_matched = true;
+        } else {
+            prev_matched = false;
+        }
+        prev = Some(c);
+    }
+    if query_chars.next().is_some() {
+        return None;
+    }
+    if name == query {
+        score += EXACT_BONUS;
+    } else if name.starts_with(&query) {
+        score += PREFIX_BONUS;
+    } else if name.contains(&query) {
+        score += SUBSTRING_BONUS;
+    }
+    Some(score)
+}
+
+/// Rank names by how well they match a query.
+///
+/// Returns the indices of all matching names together with their scores, with
+/// the best match first. Names with equal scores keep their original order.
+pub fn rank<'a>(query: &str, names: impl IntoIterator<Item = &'a str>) -> Vec<(usize, i64)> {
+    let mut ranking: Vec<_> = names
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, name)| Some((i, score(query, name)?)))
+        .collect();
+    ranking.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+    ranking
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rank_streams() {
+        let streams = [
+            "new members",
+            "Lean4 general",
+            "general",
+            "geometry",
+            "gen-z",
+            "off topic",
+        ];
+        let ranked = |query| {
+            rank(query, streams)
+                .into_iter()
+                .map(|(i, _)| streams[i])
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ranked("gen"), ["general", "gen-z", "Lean4 general"]);
+        assert_eq!(ranked("General"), ["general", "Lean4 general"]);
+        assert_eq!(ranked("ot"), ["off topic", "geometry"]);
+        assert_eq!(ranked("nm"), ["new members"]);
+        assert!(ranked("xyz").is_empty());
+    }
+
+    #[test]
+    fn test_score() {
+        assert_eq!(score("abc", "acb"), None);
+        // Regex metacharacters have no special meaning.
+        assert!(score("c++", "c++ help").is_some());
+        assert!(score("c++", "cplusplus").is_none());
+    }
+}
@@ -0,0 +1,227 @@
+//! Conversion of HTML, as in rendered message content, to readable plain text.
+
+/// Convert HTML to plain text suitable for printing in a terminal.
+///
+/// This is by no means a complete HTML renderer, but it handles the subset of
+/// HTML which the Zulip server produces when rendering messages reasonably
+/// well:
+/// - All tags are stripped.
+/// - Paragraphs and other blocks are separated by empty lines, and `<br>` is turned into a newline.
+/// - List items are prefixed with "- ".
+/// - HTML entities like `&amp;` are decoded.
+/// - Links are rendered as "text (url)".
+/// - Whitespace is collapsed, except within `<pre>` blocks.
+pub fn html_to_text(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    // The href of all currently open links together with the position in `out`
+    // where the text of the link starts.
+    let mut links: Vec<(Option<String>, usize)> = Vec::new();
+    // The number of currently open `<pre>` tags.
+    let mut pre_depth = 0usize;
+    let mut rest = html;
+    while !rest.is_empty() {
+        if let Some(after_lt) = rest.strip_prefix('<') {
+            let Some(tag_end) = after_lt.find('>') else {
+                // An unclosed tag, treat the rest as text.
+                push_text(&mut out, &decode_entities(rest), pre_depth > 0);
+                break;
+            };
+            let tag = &after_lt[..tag_end];
+            rest = &after_lt[tag_end + 1..];
+            let (closing, tag) = match tag.strip_prefix('/') {
+                Some(tag) => (true, tag),
+                None => (false, tag),
+            };
+            let name = tag
+                .split(|c: char| c.is_whitespace() || c == '/')
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            match (name.as_str(), closing) {
+                ("br", _) => line_break(&mut out),
+                ("li", false) => {
+                    if !out.is_empty() && !out.ends_with('\n') {
+                        line_break(&mut out);
+                    }
+                    out.push_str("- ");
+                }
+                ("pre", false) => {
+                    paragraph_break(&mut out);
+                    pre_depth += 1;
+                }
+                ("pre", true) => {
+                    pre_depth = pre_depth.saturating_sub(1);
+                    paragraph_break(&mut out);
+                }
+                (
+                    "p" | "div" | "blockquote" | "ul" | "ol" | "hr" | "h1" | "h2" | "h3" | "h4"
+                    | "h5" | "h6" | "table",
+                    _,
+                ) => paragraph_break(&mut out),
+                ("tr", _) if !out.is_empty() && !out.ends_with('\n') => line_break(&mut out),
+                ("a", false) => links.push((attribute(tag, "href"), out.len())),
+                ("a", true) => {
+                    if let Some((Some(href), text_start)) = links.pop() {
+                        if !href.is_empty() && out[text_start..].trim() != href {
+                            out.push_str(&format!(" ({href})"));
+                        }
+                    }
+                }
+                _ => (),
+            }
+            // Line and paragraph breaks might have removed trailing spaces, also
+            // from the text of open links.
+            for (_, text_start) in &mut links {
+                *text_start = (*text_start).min(out.len());
+            }
+        } else {
+            let text_end = rest.find('<').unwrap_or(rest.len());
+            push_text(&mut out, &decode_entities(&rest[..text_end]), pre_depth > 0);
+            rest = &rest[text_end..];
+        }
+    }
+    out.trim().to_string()
+}
+
+/// Push text to the output, collapsing whitespace unless `preformatted` is
+/// `true`.
+fn push_text(out: &mut String, text: &str, preformatted: bool) {
+    if preformatted {
+        out.push_str(text);
+        return;
+    }
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !out.is_empty() && !out.ends_with(char::is_whitespace) {
+                out.push(' ');
+            }
+        } else {
+            out.push(c);
+        }
+    }
+}
+
+/// Remove trailing spaces (but not newlines) from the output.
+fn trim_trailing_spaces(out: &mut String) {
+    out.truncate(
+        out.trim_end_matches(|c: char| c.is_whitespace() && c != '\n')
+            .len(),
+    );
+}
+
+/// End the current line.
+fn line_break(out: &mut String) {
+    trim_trailing_spaces(out);
+    out.push('\n');
+}
+
+/// End the current paragraph, so that the next text will be preceded by an
+/// empty line.
+fn paragraph_break(out: &mut String) {
+    trim_trailing_spaces(out);
+    if !out.is_empty() {
+        while !out.ends_with("\n\n") {
+            out.push('\n');
+        }
+    }
+}
+
+/// Get the (decoded) value of an attribute in a tag.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    ['"', '\''].into_iter().find_map(|quote| {
+        let start = tag.find(&format!("{name}={quote}"))? + name.len() + 2;
+        let len = tag[start..].find(quote)?;
+        Some(decode_entities(&tag[start..start + len]))
+    })
+}
+
+/// Decode all HTML entities, like `&amp;` or `&#39;`, in a string.
+///
+/// Unknown entities are left as they are.
+fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        // Entities are short, so don't look too far for the semicolon.
+        let decoded = rest
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| Some((decode_entity(&rest[1..end])?, end)));
+        if let Some((c, end)) = decoded {
+            out.push(c);
+            rest = &rest[end + 1..];
+        } else {
+            out.push('&');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Decode a single HTML entity, without the leading '&' and trailing ';'.
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let code = if let Some(hex) = entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+            {
+                u32::from_str_radix(hex, 16).ok()?
+            } else {
+                entity.strip_prefix('#')?.parse().ok()?
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html_to_text() {
+        let html = r#"<p>Hello <strong>world</strong> &amp; friends!<br>
+See <a href="https://example.com/?a=1&amp;b=2">this link</a> or <a href="https://zulip.com">https://zulip.com</a>.</p>
+<ul>
+<li>one</li>
+<li>two &#39;quoted&#x27;</li>
+</ul>
+<div class="codehilite"><pre><span></span><code>fn main() {
+    println!("&lt;3");
+}
+</code></pre></div>
+<p>Bye</p>"#;
+        assert_eq!(
+            html_to_text(html),
+            r#"Hello world & friends!
+See this link (https://example.com/?a=1&b=2) or https://zulip.com.
+
+- one
+- two 'quoted'
+
+fn main() {
+    println!("<3");
+}
+
+Bye"#
+        );
+    }
+
+    #[test]
+    fn test_line_break_in_preformatted_link() {
+        let html = r#"<pre>let url =   <a href="https://example.com"><br></a></pre>"#;
+        assert_eq!(html_to_text(html), "let url =\n (https://example.com)");
+        let html = r#"<pre>x   <a href="https://example.com"><br>åäö</a></pre>"#;
+        assert_eq!(html_to_text(html), "x\nåäö (https://example.com)");
+    }
+}
@@ -1,6 +1,7 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Context, Result};
 use common_cache::CommonCache;
@@ -8,7 +9,25 @@ use derive_more::Deref;
 use iter_tools::Itertools as _;
 use regex::{Regex, RegexBuilder};
 use serde::{Deserialize, Serialize};
-use zulib::{message::*, stream::*};
+use zulib::{draft::*, event::RegisterQueueRequest, message::*, stream::*};
+
+pub mod color;
+pub mod editor;
+mod fuzzy;
+pub mod html;
+#[cfg(test)]
+mod test_fixtures;
+
+/// A topic with unread messages, see `Client::get_unread_topics`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UnreadTopic {
+    /// The name of the topic.
+    pub name: String,
+    /// The number of unread messages in the topic.
+    pub unread_count: usize,
+    /// The message ID of the last message sent to this topic.
+    pub max_id: u64,
+}
 
 #[derive(Debug, Deref)]
 pub struct Client {
@@ -20,6 +39,11 @@ pub struct Client {
     /// The currently selected topic if any. If `selected_stream` is `None` then
     /// must also `selected_topic` be `None`.
     selected_topic: Option<String>,
+    /// The number of unread messages when they were last counted by
+    /// `Self::refresh_unread_count`, or `None` if they couldn't be counted.
+    unread_count: Option<usize>,
+    /// When the unread messages were last counted.
+    unread_count_time: Option<Instant>,
     cache: Cache,
 }
 
@@ -34,26 +58,92 @@ struct Cache {
     topics: CommonCache<String, u64>,
 }
 
+/// The dimensions of the stream and topic caches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::Args)]
+pub struct CacheConfig {
+    /// The base of the stream cache. A higher base makes it harder for streams
+    /// to be promoted in the cache.
+    #[clap(long, default_value_t = 2)]
+    pub stream_cache_base: usize,
+    /// The maximum number of streams in the stream cache.
+    #[clap(long, default_value_t = 128)]
+    pub stream_cache_size: usize,
+    /// The base of the topic cache. A higher base makes it harder for topics
+    /// to be promoted in the cache.
+    #[clap(long, default_value_t = 2)]
+    pub topic_cache_base: usize,
+    /// The maximum number of topics in the topic cache.
+    #[clap(long, default_value_t = 512)]
+    pub topic_cache_size: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            stream_cache_base: 2,
+            stream_cache_size: 128,
+            topic_cache_base: 2,
+            topic_cache_size: 512,
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Check that all bases and sizes are at least 2.
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in [
+            ("stream cache base", self.stream_cache_base),
+            ("stream cache size", self.stream_cache_size),
+            ("topic cache base", self.topic_cache_base),
+            ("topic cache size", self.topic_cache_size),
+        ] {
+            if value < 2 {
+                bail!("The {name} must be at least 2, got {value}");
+            }
+        }
+        Ok(())
+    }
+}
+
 impl Client {
-    pub fn new(rc: zulib::ZulipRc) -> Result<Self> {
+    pub fn new(rc: zulib::ZulipRc, cache_config: &CacheConfig) -> Result<Self> {
+        cache_config.validate()?;
         Ok(Self {
-            backend: zulib::Client::new(rc)?,
+            backend: zulib::Client::new(rc)?
+                .with_user_agent(concat!("zcli/", env!("CARGO_PKG_VERSION")))?,
             selected_stream: None,
             selected_topic: None,
+            unread_count: None,
+            unread_count_time: None,
             cache: Cache {
-                streams: CommonCache::new(2, Some(128)),
-                topics: CommonCache::new(2, Some(512)),
+                streams: CommonCache::new(
+                    cache_config.stream_cache_base,
+                    Some(cache_config.stream_cache_size),
+                ),
+                topics: CommonCache::new(
+                    cache_config.topic_cache_base,
+                    Some(cache_config.topic_cache_size),
+                ),
             },
         })
     }
 
     /// Create a new client from a cache file.
-    pub fn from_cache(cache_file_content: &str, rc: zulib::ZulipRc) -> Result<Self> {
-        Ok(Self {
-            cache: serde_json::from_str(cache_file_content)
-                .context("Failed to parse cache file.")?,
-            ..Self::new(rc)?
-        })
+    ///
+    /// The max sizes in `cache_config` are applied to the loaded caches, but
+    /// the bases are kept from the cache file since they can't be changed for
+    /// existing caches.
+    pub fn from_cache(
+        cache_file_content: &str,
+        rc: zulib::ZulipRc,
+        cache_config: &CacheConfig,
+    ) -> Result<Self> {
+        let mut cache: Cache =
+            serde_json::from_str(cache_file_content).context("Failed to parse cache file.")?;
+        let client = Self::new(rc, cache_config)?;
+        cache.streams.set_max_size(cache_config.stream_cache_size);
+        cache.topics.set_max_size(cache_config.topic_cache_size);
+        Ok(Self { cache, ..client })
     }
 
     /// Get the content of the cache file a(as it would be right now) as a
@@ -75,9 +165,12 @@ impl Client {
     }
 
     /// Clear the stream and topic cache.
+    ///
+    /// The memory of the caches is kept, since they are usually filled again
+    /// right away.
     pub fn clear_cache(&mut self) {
-        self.cache.streams.clear();
-        self.cache.topics.clear();
+        self.cache.streams.clear_keep_capacity();
+        self.cache.topics.clear_keep_capacity();
     }
 
     /// Get an iterator of all streams (filtered by a `GetStreamsRequest`) in
@@ -100,8 +193,9 @@ impl Client {
             .await?
             .into_iter()
             .partition::<Vec<_>, _>(|x| subscribed_streams.contains_key(&x.stream_id));
-        relevant_subscribed_streams
-            .sort_unstable_by_key(|x| subscribed_streams[&x.stream_id].stream_weekly_trafic);
+        relevant_subscribed_streams.sort_unstable_by(|x, y| {
+            subscribed_streams[&x.stream_id].cmp_by_traffic(&subscribed_streams[&y.stream_id])
+        });
         Ok(unsubscribed_streams
             .into_iter()
             .chain(relevant_subscribed_streams.into_iter()))
@@ -121,43 +215,37 @@ impl Client {
             .filter(|stream| re.is_match(&stream.name))
     }
 
-    /// Search for a stream by a regex. First considers the local cache and if
+    /// Search for a stream by a pattern. First considers the local cache and if
     /// that fails fetches subscribed streams from the server (ordered by
     /// weekly trafic). If that also failes, fetches all streams from the
     /// server. The found stream will be added to (or promoted in) the
     /// cache.
     async fn stream_search(
         &mut self,
-        re: &Regex,
+        pattern: &Pattern,
     ) -> Result<Option<common_cache::Entry<'_, u64, Stream>>> {
-        if let Some(cache_idx) = self
-            .cache
-            .streams
-            .find_first(|_, stream| re.is_match(&stream.name))
-            .map(|x| x.index())
+        if let Some(i) =
+            pattern.best_match(self.cache.streams.iter().map(|(_, x)| x.name.as_str()))?
         {
+            let cache_idx = self.cache.streams.iter_indices().nth(i).unwrap();
             Ok(Some(cache_idx.entry(&mut self.cache.streams)))
         } else {
             let mut streams = self.backend.get_subscribed_streams().await?;
-            streams.sort_unstable_by_key(|x| x.stream_weekly_trafic);
-            if let Some(stream) = streams
-                .into_iter()
-                .map(|x| x.stream)
-                .filter(|x| re.is_match(&x.name))
-                .next()
-            {
-                Ok(Some(self.cache.streams.insert(stream.stream_id, stream)))
-            } else if let Some(stream) = self
-                .backend
-                .get_streams(&GetStreamsRequest::default())
-                .await?
-                .into_iter()
-                .filter(|x| re.is_match(&x.name))
-                .next()
-            {
+            streams.sort_unstable_by(Subscription::cmp_by_traffic);
+            if let Some(i) = pattern.best_match(streams.iter().map(|x| x.stream.name.as_str()))? {
+                let stream = streams.swap_remove(i).stream;
                 Ok(Some(self.cache.streams.insert(stream.stream_id, stream)))
             } else {
-                Ok(None)
+                let mut streams = self
+                    .backend
+                    .get_streams(&GetStreamsRequest::default())
+                    .await?;
+                if let Some(i) = pattern.best_match(streams.iter().map(|x| x.name.as_str()))? {
+                    let stream = streams.swap_remove(i);
+                    Ok(Some(self.cache.streams.insert(stream.stream_id, stream)))
+                } else {
+                    Ok(None)
+                }
             }
         }
     }
@@ -172,7 +260,7 @@ impl Client {
             }
         }
         let mut streams = self.backend.get_subscribed_streams().await?;
-        streams.sort_unstable_by_key(|x| x.stream_weekly_trafic);
+        streams.sort_unstable_by(Subscription::cmp_by_traffic);
         for stream in streams.into_iter().map(|x| x.stream) {
             let entry = self.cache.streams.insert(stream.stream_id, stream);
             if let Some(x) = f(entry.peek_value()) {
@@ -230,10 +318,8 @@ impl Client {
                 .downcast_ref::<SkimStream>()
                 .unwrap()
                 .stream_id;
-            if let Some(entry) = self.cache.streams.entry(&stream_id) {
-                // The following litle cowboy-dance with entries and indices exists to satisfy
-                // the borrow checker.
-                Ok(Some(entry.index().entry(&mut self.cache.streams)))
+            if let Some(index) = self.cache.streams.index_of(&stream_id) {
+                Ok(Some(index.entry(&mut self.cache.streams)))
             } else {
                 // The selected stream is not in the cache, so we fetch it from the server.
                 let stream = self.backend.get_stream_by_id(stream_id).await?;
@@ -251,17 +337,28 @@ impl Client {
     /// the cache.
     ///
     /// Returns the name of the topic.
-    async fn topic_search(&mut self, stream_id: u64, re: &Regex) -> Result<Option<&'_ String>> {
-        if let Some(cache_idx) = self
+    async fn topic_search(
+        &mut self,
+        stream_id: u64,
+        pattern: &Pattern,
+    ) -> Result<Option<&'_ String>> {
+        let mut cached_topics: Vec<_> = self
             .cache
             .topics
-            .find_first(|topic, &stream| stream == stream_id && re.is_match(topic))
-            .map(|x| x.index())
-        {
+            .iter_indices()
+            .filter(|x| *x.peek_value(&self.cache.topics) == stream_id)
+            .collect();
+        if let Some(i) = pattern.best_match(
+            cached_topics
+                .iter()
+                .map(|x| x.peek_key(&self.cache.topics).as_str()),
+        )? {
+            let cache_idx = cached_topics.swap_remove(i);
             Ok(Some(cache_idx.get_key_value(&mut self.cache.topics).0))
         } else {
-            let topics = self.backend.get_topics_in_stream(stream_id).await?;
-            if let Some(topic) = topics.into_iter().filter(|x| re.is_match(&x.name)).next() {
+            let mut topics = self.backend.get_topics_in_stream(stream_id).await?;
+            if let Some(i) = pattern.best_match(topics.iter().map(|x| x.name.as_str()))? {
+                let topic = topics.swap_remove(i);
                 Ok(Some(
                     self.cache
                         .topics
@@ -292,21 +389,99 @@ impl Client {
         }
     }
 
-    /// Interpret the stream and topic fields of a narrow as regular expressions
-    /// and replace them with their real names.
+    /// Fetch a stream by id from the server, even if it is in the local
+    /// cache, and update (or add) it in the cache.
+    ///
+    /// If the server says that the stream doesn't exist but it is in the
+    /// cache, the cached id is probably stale because the stream has been
+    /// recreated. The stale entry is then evicted from the cache and the
+    /// stream is fetched once more by its cached name.
+    pub async fn refresh_stream(&mut self, id: u64) -> Result<&Stream> {
+        let stream = match self.backend.get_stream_by_id(id).await {
+            Ok(stream) => stream,
+            Err(e) if e.is_stream_not_found() => {
+                let Some(stale) = self.cache.streams.take(&id) else {
+                    return Err(e.into());
+                };
+                let new_id = self
+                    .backend
+                    .try_get_stream_id(&stale.name)
+                    .await?
+                    .with_context(|| format!("The stream {} no longer exists", stale.name))?;
+                self.backend.get_stream_by_id(new_id).await?
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(self
+            .cache
+            .streams
+            .insert(stream.stream_id, stream)
+            .peek_long()
+            .1)
+    }
+
+    /// Get all topics in a stream with unread messages, with the most recently
+    /// active topic first.
+    ///
+    /// Only the newest `MAX_MESSAGES_PER_REQUEST` unread messages in the
+    /// stream are counted.
+    pub async fn get_unread_topics(&mut self, stream_id: u64) -> Result<Vec<UnreadTopic>> {
+        let stream_name = self.get_stream(stream_id).await?.name.clone();
+        let mut range = MessageRange::new(MAX_MESSAGES_PER_REQUEST, 0);
+        range.narrow(vec![
+            Narrow {
+                operator: "stream".to_string(),
+                operand: stream_name,
+                negated: false,
+            },
+            Narrow::is_unread(),
+        ]);
+        let messages = self
+            .backend
+            .get_messages(GetMessagesRequest::new(range))
+            .await?
+            .messages;
+        let mut unread_counts: HashMap<String, usize> = HashMap::new();
+        for message in messages {
+            *unread_counts.entry(message.subject).or_default() += 1;
+        }
+        // The topics are already sorted with the most recent first.
+        let topics = self
+            .backend
+            .get_stream_topics(stream_id, None)
+            .await?
+            .into_iter()
+            .filter_map(|Topic { name, max_id }| {
+                Some(UnreadTopic {
+                    unread_count: unread_counts.remove(&name)?,
+                    name,
+                    max_id,
+                })
+            })
+            .collect();
+        Ok(topics)
+    }
+
+    /// Interpret the stream and topic fields of a narrow as patterns according
+    /// to `mode` and replace them with their real names.
     ///
     /// The topic/stream will be searched for in the local cache.
     /// If no matching stream/topic is found in the cache, fetches all
     /// streams / all topics in the stream from the server.
-    async fn unregex_narrow(&mut self, narrows: &mut [Narrow]) -> Result<()> {
+    ///
+    /// Stream operands which are stream ids, like "stream:5", and other
+    /// operators like "streams:public" are left as they are.
+    async fn unregex_narrow(&mut self, narrows: &mut [Narrow], mode: SearchMode) -> Result<()> {
         let mut found_stream = None;
         for Narrow {
             operator, operand, ..
         } in narrows.iter_mut()
         {
             if operator == "stream" {
-                if let Some(mut stream_cache_entry) =
-                    self.stream_search(&mk_regex(operand)?).await?
+                if let Ok(stream_id) = operand.parse() {
+                    found_stream = Some(stream_id);
+                } else if let Some(mut stream_cache_entry) =
+                    self.stream_search(&Pattern::new(operand, mode)?).await?
                 {
                     let stream = stream_cache_entry.get_value();
                     *operand = stream.name.clone();
@@ -324,7 +499,10 @@ impl Client {
             } in narrows.iter_mut()
             {
                 if operator == "topic" {
-                    if let Some(topic) = self.topic_search(stream, &mk_regex(operand)?).await? {
+                    if let Some(topic) = self
+                        .topic_search(stream, &Pattern::new(operand, mode)?)
+                        .await?
+                    {
                         *operand = topic.clone();
                     } else {
                         bail!("No topic found matching: {operand}");
@@ -338,7 +516,10 @@ impl Client {
     /// Add the current stream/topic to a narrow if no stream/topic is specified
     /// in the narrow.
     fn narrow_to_current(&self, narrows: &mut Vec<Narrow>) {
-        if !narrows.iter().any(|x| x.operator == "stream") {
+        if !narrows
+            .iter()
+            .any(|x| x.operator == "stream" || x.operator == "streams")
+        {
             if let Some(selected_stream) = self.selected_stream.as_ref() {
                 narrows.push(Narrow {
                     operator: "stream".to_string(),
@@ -360,8 +541,9 @@ impl Client {
 
     /// Get a list of all messages matching a query.
     ///
-    /// If `regex_search` is `true`, the topic and/or stream narrows will be
-    /// interpretted as regular expressions and searched in the local cache
+    /// If `search` is not `SearchMode::Exact`, the topic and/or stream narrows
+    /// will be interpretted as regular expressions or fuzzy patterns, as
+    /// specified by the mode, and searched in the local cache
     /// of recently read topics and streams. If no stream is found, all
     /// streams will be fetched from the server and searched. If a topic is
     /// not found, all topics for the searched stream (or currently selected
@@ -372,12 +554,12 @@ impl Client {
     pub async fn get_messages(
         &mut self,
         mut req: GetMessagesRequest,
-        regex_search: bool,
+        search: SearchMode,
         global: bool,
     ) -> Result<impl Iterator<Item = (String, Vec<ReceivedMessage>)>> {
         let narrows = req.range.narrow.get_or_insert(Default::default());
-        if regex_search {
-            self.unregex_narrow(narrows.as_mut_slice()).await?;
+        if search != SearchMode::Exact {
+            self.unregex_narrow(narrows.as_mut_slice(), search).await?;
         }
 
         // If no stream/topic was narrowed and `global` is `false` and a topic or stream
@@ -409,12 +591,12 @@ impl Client {
     pub async fn update_message_flags_for_narrow(
         &mut self,
         mut req: UpdateMessageFlagsForNarrowRequest,
-        regex: bool,
+        search: SearchMode,
         global: bool,
     ) -> Result<UpdateMessageFlagsForNarrowResponse> {
         let narrows = req.range.narrow.get_or_insert(Default::default());
-        if regex {
-            self.unregex_narrow(narrows.as_mut_slice()).await?;
+        if search != SearchMode::Exact {
+            self.unregex_narrow(narrows.as_mut_slice(), search).await?;
         }
 
         // If no stream/topic was narrowed and `global` is `false` and a topic or stream
@@ -431,15 +613,15 @@ impl Client {
         &mut self,
         stream: Option<zulib::Identifier>,
         topic: Option<String>,
-        regex: bool,
+        search: SearchMode,
         global: bool,
     ) -> Result<()> {
         let stream_id = if let Some(stream) = stream {
             Some(match stream {
                 zulib::Identifier::Id(x) => x,
-                zulib::Identifier::Name(name) if regex => {
+                zulib::Identifier::Name(name) if search != SearchMode::Exact => {
                     *self
-                        .stream_search(&mk_regex(&name)?)
+                        .stream_search(&Pattern::new(&name, search)?)
                         .await?
                         .context("Stream not found")?
                         .get_key_value()
@@ -454,8 +636,8 @@ impl Client {
         };
         if let Some(stream_id) = stream_id {
             if let Some(topic) = topic {
-                let topic = if regex {
-                    self.topic_search(stream_id, &mk_regex(&topic)?)
+                let topic = if search != SearchMode::Exact {
+                    self.topic_search(stream_id, &Pattern::new(&topic, search)?)
                         .await?
                         .context("No such topic found")?
                         .clone()
@@ -471,32 +653,188 @@ impl Client {
         }
     }
 
-    /// Select a stream by either a name or a regex for the name.
+    /// Resolve the recipients of a private message to user ids.
+    ///
+    /// The recipients may be given as a user id, a JSON list of user ids, or a
+    /// comma separated list of user ids, emails and full names. Emails and
+    /// names are matched case insensitively against the active users of the
+    /// realm, which are fetched at most once.
     ///
-    /// If a regex is provided, the
+    /// Returns a single user id or a JSON list of user ids, as expected by
+    /// `SendMessageRequest::Private`.
+    pub async fn resolve_private_recipients(
+        &self,
+        to: zulib::Identifier,
+    ) -> Result<zulib::Identifier> {
+        let names = match to {
+            zulib::Identifier::Name(names) if !names.trim_start().starts_with('[') => names,
+            to => return Ok(to),
+        };
+        let mut users = None;
+        let mut ids = Vec::new();
+        for name in names.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            if let Ok(id) = name.parse() {
+                ids.push(id);
+                continue;
+            }
+            if users.is_none() {
+                users = Some(self.backend.get_users().await?);
+            }
+            let lowercase_name = name.to_lowercase();
+            let matches: Vec<_> = users
+                .iter()
+                .flatten()
+                .filter(|x| {
+                    x.is_active
+                        && (x.email.eq_ignore_ascii_case(name)
+                            || x.full_name.to_lowercase() == lowercase_name)
+                })
+                .collect();
+            match matches[..] {
+                [user] => ids.push(user.user_id),
+                // Users from other realms, like some bots, are not listed but
+                // might still be found by their email.
+                [] if name.contains('@') => {
+                    ids.push(self.backend.get_user_by_email(name).await?.user_id)
+                }
+                [] => bail!("No user named: {name}"),
+                _ => bail!(
+                    "Several users named {name}: {}",
+                    matches.iter().map(|x| &x.email).join(", ")
+                ),
+            }
+        }
+        match ids[..] {
+            [] => bail!("No recipients given"),
+            [id] => Ok(zulib::Identifier::Id(id)),
+            _ => Ok(zulib::Identifier::Name(serde_json::to_string(&ids)?)),
+        }
+    }
+
+    /// Create a draft on the server from a message request.
+    ///
+    /// A stream may be given by name or id, and the recipients of a private
+    /// message as described in `Self::resolve_private_recipients`.
+    ///
+    /// Returns the id of the new draft.
+    pub async fn create_draft(&self, req: SendMessageRequest) -> Result<u64> {
+        let draft = match req {
+            SendMessageRequest::Stream { to, topic, content } => Draft {
+                id: None,
+                r#type: DraftType::Stream,
+                to: vec![match to {
+                    zulib::Identifier::Id(x) => x,
+                    zulib::Identifier::Name(name) => self.backend.get_stream_id(&name).await?,
+                }],
+                topic,
+                content,
+                timestamp: None,
+            },
+            SendMessageRequest::Private { to, content } => Draft {
+                id: None,
+                r#type: DraftType::Private,
+                to: match self.resolve_private_recipients(to).await? {
+                    zulib::Identifier::Id(x) => vec![x],
+                    zulib::Identifier::Name(name) => serde_json::from_str(&name)
+                        .with_context(|| format!("Bad list of user ids: {name}"))?,
+                },
+                topic: String::new(),
+                content,
+                timestamp: None,
+            },
+        };
+        let ids = self.backend.create_drafts(&[draft]).await?;
+        ids.first()
+            .copied()
+            .context("The server did not return an id for the draft")
+    }
+
+    /// Select a stream by either a name or a regex or fuzzy pattern for the
+    /// name.
+    ///
+    /// If a regex or fuzzy pattern is provided, the
     /// stream will first be searched for in the cache and then all streams will
     /// be fetched from the server. If a plain name is given, it will be
     /// checked that the stream indeed exists.
     ///
     /// Returns a reference to the newly selected stream.
-    pub async fn select_stream(&mut self, name: &str, is_regex: bool) -> Result<&Stream> {
-        if is_regex {
-            let re = mk_regex(name)?;
+    pub async fn select_stream(&mut self, name: &str, search: SearchMode) -> Result<&Stream> {
+        if search != SearchMode::Exact {
+            let pattern = Pattern::new(name, search)?;
             let stream = self
-                .stream_search(&re)
+                .stream_search(&pattern)
                 .await?
                 .with_context(|| format!("No stream matching: {name}"))?
-                .index();
-            self.selected_stream = Some(stream.peek_value(&self.cache.streams).clone());
-            Ok(stream.get_value(&mut self.cache.streams))
+                .index()
+                .take_value(&mut self.cache.streams)
+                .expect("The index was just created");
+            self.selected_topic = None;
+            Ok(self.selected_stream.insert(stream))
         } else {
-            let id = self.backend.get_stream_id(name).await?;
-            let stream = self.backend.get_stream_by_id(id).await?;
+            let stream = match self.backend.get_stream_by_name(name).await {
+                Ok(stream) => stream,
+                Err(e) if e.is_stream_not_found() => bail!("No stream named: {name}"),
+                Err(e) => return Err(e.into()),
+            };
             self.selected_stream = Some(stream.clone());
-            Ok(self.cache.streams.insert(id, stream).peek_long().1)
+            self.selected_topic = None;
+            Ok(self
+                .cache
+                .streams
+                .insert(stream.stream_id, stream)
+                .peek_long()
+                .1)
         }
     }
 
+    /// Select a topic in the currently selected stream by either a name or a
+    /// regex or fuzzy pattern for the name.
+    ///
+    /// If a regex or fuzzy pattern is provided, the topic will first be
+    /// searched for in the cache and then all topics in the stream will be
+    /// fetched from the server. A plain name is selected as it is, so that
+    /// new topics can be selected.
+    ///
+    /// Returns the name of the newly selected topic.
+    pub async fn select_topic(&mut self, name: &str, search: SearchMode) -> Result<&str> {
+        let stream_id = self.selected_stream_id().context("No stream selected")?;
+        let topic = if search != SearchMode::Exact {
+            self.topic_search(stream_id, &Pattern::new(name, search)?)
+                .await?
+                .with_context(|| format!("No topic matching: {name}"))?
+                .clone()
+        } else {
+            name.to_string()
+        };
+        Ok(self.selected_topic.insert(topic))
+    }
+
+    /// Get the currently selected topic.
+    pub fn selected_topic(&self) -> Option<&str> {
+        self.selected_topic.as_deref()
+    }
+
+    /// Send a message to the currently selected stream and topic.
+    ///
+    /// Fails if no stream or topic is selected. The topic will be added to (or
+    /// promoted in) the topic cache.
+    ///
+    /// Returns the id of the sent message.
+    pub async fn reply(&mut self, content: String) -> Result<u64> {
+        let stream_id = self.selected_stream_id().context("No stream selected")?;
+        let topic = self.selected_topic.clone().context("No topic selected")?;
+        let response = self
+            .backend
+            .send_message(SendMessageRequest::Stream {
+                to: zulib::Identifier::Id(stream_id),
+                topic: topic.clone(),
+                content,
+            })
+            .await?;
+        self.cache.topics.insert(topic, stream_id);
+        Ok(response.id)
+    }
+
     /// Get a reference to the currently selected stream.
     pub fn selected_stream(&self) -> Option<&Stream> {
         self.selected_stream.as_ref()
@@ -506,8 +844,118 @@ impl Client {
     pub fn selected_stream_id(&self) -> Option<u64> {
         self.selected_stream.as_ref().map(|x| x.stream_id)
     }
+
+    /// Count the unread messages again if they were counted more than
+    /// `max_age` ago, see `Self::unread_count`.
+    ///
+    /// The count is taken from the unread messages summary which the server
+    /// sends when an event queue is registered, so no messages are fetched.
+    /// If they can't be counted, for instance because of a network error, the
+    /// count is cleared rather than left stale.
+    pub async fn refresh_unread_count(&mut self, max_age: Duration) {
+        if self
+            .unread_count_time
+            .is_some_and(|x| x.elapsed() < max_age)
+        {
+            return;
+        }
+        self.unread_count = self.count_unread().await.ok().flatten();
+        self.unread_count_time = Some(Instant::now());
+    }
+
+    /// Count the unread messages by registering an event queue.
+    async fn count_unread(&self) -> zulib::Result<Option<usize>> {
+        let queue = self
+            .backend
+            .register_queue(&RegisterQueueRequest {
+                event_types: vec!["message".to_string()],
+                ..Default::default()
+            })
+            .await?;
+        // The queue is not used, and if it can't be deleted the server
+        // removes it after a few minutes anyway.
+        let _ = self.backend.delete_queue(&queue.queue_id).await;
+        Ok(queue.unread_msgs.map(|x| x.count as usize))
+    }
+
+    /// The number of unread messages when they were last counted by
+    /// `Self::refresh_unread_count`, if they could be counted.
+    pub fn unread_count(&self) -> Option<usize> {
+        self.unread_count
+    }
+}
+
+/// How names of streams and topics given by the user are matched against the
+/// real names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchMode {
+    /// The name must be given exactly.
+    Exact,
+    /// The name is a case insensitive regular expression. The first match is
+    /// used.
+    Regex,
+    /// The name is fuzzy matched, and the best match is used.
+    Fuzzy,
+}
+
+impl SearchMode {
+    /// Get the search mode from the regex and fuzzy command line flags.
+    pub fn from_flags(regex: bool, fuzzy: bool) -> Self {
+        if fuzzy {
+            Self::Fuzzy
+        } else if regex {
+            Self::Regex
+        } else {
+            Self::Exact
+        }
+    }
+}
+
+/// A pattern to search for streams or topics with.
+enum Pattern {
+    Regex(Regex),
+    Fuzzy(String),
+}
+
+impl Pattern {
+    /// Create a pattern from a string. `mode` must not be `SearchMode::Exact`.
+    fn new(pattern: &str, mode: SearchMode) -> Result<Self> {
+        match mode {
+            SearchMode::Regex | SearchMode::Exact => Ok(Self::Regex(mk_regex(pattern)?)),
+            SearchMode::Fuzzy => Ok(Self::Fuzzy(pattern.to_string())),
+        }
+    }
+
+    /// Find the best match among a list of names and return its index.
+    ///
+    /// For a regex, that is the first matching name. For a fuzzy pattern it
+    /// is the name with the highest score, and an error listing the top
+    /// candidates is returned if several names are equally good.
+    fn best_match<'a>(&self, names: impl IntoIterator<Item = &'a str>) -> Result<Option<usize>> {
+        match self {
+            Self::Regex(re) => Ok(names.into_iter().position(|x| re.is_match(x))),
+            Self::Fuzzy(query) => {
+                let names: Vec<_> = names.into_iter().collect();
+                match fuzzy::rank(query, names.iter().copied()).as_slice() {
+                    [] => Ok(None),
+                    ranking @ [(_, best), (_, second), ..] if best == second => bail!(
+                        "Ambiguous name: {query}, candidates: {}",
+                        ranking
+                            .iter()
+                            .take(MAX_CANDIDATES)
+                            .map(|&(i, _)| names[i])
+                            .join(", ")
+                    ),
+                    [(i, _), ..] => Ok(Some(*i)),
+                }
+            }
+        }
+    }
 }
 
+/// The maximum number of candidates listed for an ambiguous fuzzy pattern.
+const MAX_CANDIDATES: usize = 5;
+
 /// Create a case insensitive regex from a string.
 fn mk_regex(pattern: &str) -> Result<Regex> {
     RegexBuilder::new(pattern)
@@ -515,3 +963,209 @@ fn mk_regex(pattern: &str) -> Result<Regex> {
         .build()
         .with_context(|| format!("Bad regular expression: {pattern}"))
 }
+
+#[cfg(test)]
+mod tests {
+    use httpmock::{
+        Method::{GET, POST},
+        MockServer,
+    };
+
+    use super::*;
+    use crate::test_fixtures::*;
+
+    #[test]
+    fn test_cache_config() {
+        let server = MockServer::start();
+        let cache_config = CacheConfig {
+            stream_cache_base: 3,
+            stream_cache_size: 1000,
+            topic_cache_base: 4,
+            topic_cache_size: 5000,
+        };
+        let client = Client::new(test_rc(&server), &cache_config).unwrap();
+        assert_eq!(client.cache.streams.max_size(), 1000);
+        assert_eq!(client.cache.topics.max_size(), 5000);
+
+        let client = Client::from_cache(
+            &client.mk_cache_file(),
+            test_rc(&server),
+            &CacheConfig {
+                topic_cache_size: 100,
+                ..cache_config
+            },
+        )
+        .unwrap();
+        assert_eq!(client.cache.streams.max_size(), 1000);
+        assert_eq!(client.cache.topics.max_size(), 100);
+
+        let err = Client::new(
+            test_rc(&server),
+            &CacheConfig {
+                topic_cache_base: 1,
+                ..cache_config
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The topic cache base must be at least 2, got 1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reply() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/messages")
+                .body("type=stream&to=5&topic=greetings&content=Hello");
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "id": 42}"#);
+        });
+        let mut client = test_client(&server);
+        let err = client.reply("Hello".to_string()).await.unwrap_err();
+        assert_eq!(err.to_string(), "No stream selected");
+
+        client.selected_stream = Some(serde_json::from_str(&stream_json(5, "general")).unwrap());
+        let err = client.reply("Hello".to_string()).await.unwrap_err();
+        assert_eq!(err.to_string(), "No topic selected");
+        mock.assert_hits(0);
+
+        client
+            .select_topic("greetings", SearchMode::Exact)
+            .await
+            .unwrap();
+        let id = client.reply("Hello".to_string()).await.unwrap();
+        mock.assert();
+        assert_eq!(id, 42);
+        assert!(client.topic_cache_iter().eq(["greetings"]));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_stream_with_stale_id() {
+        let server = MockServer::start();
+        let stale_mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/streams/5");
+            then.status(400)
+                .body(r#"{"result": "error", "msg": "Invalid stream ID", "code": "BAD_REQUEST"}"#);
+        });
+        let resolve_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/get_stream_id")
+                .query_param("stream", "general");
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "stream_id": 7}"#);
+        });
+        let fresh_mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/streams/7");
+            then.status(200).body(format!(
+                r#"{{"result": "success", "msg": "", "stream": {}}}"#,
+                stream_json(7, "general")
+            ));
+        });
+        let mut client = test_client(&server);
+        client
+            .cache
+            .streams
+            .insert(5, serde_json::from_str(&stream_json(5, "general")).unwrap());
+
+        // A cached stream is returned without asking the server.
+        let stream = client.get_stream(5).await.unwrap();
+        assert_eq!(stream.stream_id, 5);
+        stale_mock.assert_hits(0);
+
+        let stream = client.refresh_stream(5).await.unwrap();
+        assert_eq!(stream.stream_id, 7);
+        assert_eq!(stream.name, "general");
+        stale_mock.assert();
+        resolve_mock.assert();
+        fresh_mock.assert();
+        assert!(client.cache.streams.entry(&5).is_none());
+        assert!(client.cache.streams.entry(&7).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_unregex_narrow() {
+        let server = MockServer::start();
+        let subscriptions_mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/users/me/subscriptions");
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "subscriptions": []}"#);
+        });
+        let streams_mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/streams");
+            then.status(200).body(format!(
+                r#"{{"result": "success", "msg": "", "streams": [{}, {}]}}"#,
+                stream_json(1, "general"),
+                stream_json(2, "announce"),
+            ));
+        });
+        let topics_mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/users/me/2/topics");
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "topics": [{"max_id": 4, "name": "news"}]}"#,
+            );
+        });
+        let mut client = test_client(&server);
+
+        let mut narrows = vec![Narrow::parse("streams:public"), Narrow::parse("search:x")];
+        let expected = narrows.clone();
+        client
+            .unregex_narrow(&mut narrows, SearchMode::Regex)
+            .await
+            .unwrap();
+        assert_eq!(narrows, expected);
+        subscriptions_mock.assert_hits(0);
+        streams_mock.assert_hits(0);
+
+        // The topic is still resolved in the stream with the given id.
+        let mut narrows = vec![Narrow::parse("stream:2"), Narrow::parse("topic:ne")];
+        client
+            .unregex_narrow(&mut narrows, SearchMode::Regex)
+            .await
+            .unwrap();
+        assert_eq!(
+            narrows,
+            [Narrow::parse("stream:2"), Narrow::parse("topic:news")]
+        );
+        subscriptions_mock.assert_hits(0);
+        streams_mock.assert_hits(0);
+        topics_mock.assert();
+
+        let mut narrows = vec![Narrow::parse("stream:ann")];
+        client
+            .unregex_narrow(&mut narrows, SearchMode::Regex)
+            .await
+            .unwrap();
+        assert_eq!(narrows, [Narrow::parse("stream:announce")]);
+        subscriptions_mock.assert();
+        streams_mock.assert();
+    }
+
+    #[tokio::test]
+    async fn test_create_draft() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/drafts")
+                // The form encoded version of:
+                // [{"type":"stream","to":[5],"topic":"greetings","content":"Hello"}]
+                .body(
+                    "drafts=%5B%7B%22type%22%3A%22stream%22%2C%22to%22%3A%5B5%5D%2C%22topic%22%3A%\
+                     22greetings%22%2C%22content%22%3A%22Hello%22%7D%5D",
+                );
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "ids": [17]}"#);
+        });
+        let client = test_client(&server);
+        let req = SendMessageRequest::Stream {
+            to: zulib::Identifier::Id(5),
+            topic: "greetings".to_string(),
+            content: "Hello".to_string(),
+        };
+        let id = client.create_draft(req).await.unwrap();
+        mock.assert();
+        assert_eq!(id, 17);
+    }
+}
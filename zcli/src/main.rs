@@ -1,19 +1,44 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::Write;
 use std::ops::ControlFlow;
+use std::path::Path;
 
 use anyhow::*;
 use chrono_humanize::HumanTime;
 use clap::Parser as _;
-use zcli::Client;
+use zcli::color::Palette;
+use zcli::{Client, UnreadTopic};
+use zulib::event::*;
 use zulib::message::*;
 use zulib::stream::*;
+use zulib::user::UserStatus;
+
+#[cfg(test)]
+mod test_fixtures;
 
 #[derive(clap::Parser)]
 #[command(author, version, about)]
 struct Args {
+    #[command(flatten)]
+    cache_config: zcli::CacheConfig,
+    /// The format of the output.
+    #[clap(long, global = true, value_enum, default_value_t = Format::Text)]
+    format: Format,
     #[command(subcommand)]
     command: CommandOrRepl,
 }
 
+/// The format of the output.
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+enum Format {
+    /// Human readable text.
+    Text,
+    /// JSON, suitable for scripts.
+    Json,
+}
+
 #[derive(clap::Subcommand)]
 enum CommandOrRepl {
     #[clap(flatten)]
@@ -26,17 +51,27 @@ enum CommandOrRepl {
 #[derive(clap::Subcommand)]
 enum Command {
     /// Print various things, like messages or streams.
-    #[clap(subcommand)]
     Ls(Ls),
+    /// Send a message.
+    Send {
+        /// Write the message in your editor ($ZCLI_EDITOR, $VISUAL or
+        /// $EDITOR), or in COMMAND if given. The content argument, if given,
+        /// is used as the initial text.
+        #[clap(short, long, num_args = 0..=1, require_equals = true, value_name = "COMMAND")]
+        editor: Option<Option<String>>,
+        #[clap(subcommand)]
+        req: SendMessageRequest,
+    },
+    /// List, create or delete drafts stored on the server.
     #[clap(subcommand)]
-    Send(SendMessageRequest),
+    Draft(Draft),
     /// Mark all messages, possibly in a specific stream or topic, as read.
     MarkRead {
         /// Specify a stream in which to mark as read. Otherwise all of your
         /// messages will be read.
         ///
         /// The stream should be a stream id or a name, which can optionally be
-        /// a regex.
+        /// a regex or a fuzzy pattern.
         stream: Option<zulib::Identifier>,
         /// Specify a topic to mark as read.
         topic: Option<String>,
@@ -50,6 +85,13 @@ enum Command {
         /// considered.
         #[clap(short, long)]
         regex: bool,
+        /// Fuzzy match the "stream" and "topic" names instead and use the best
+        /// match.
+        ///
+        /// Unlike "--regex", no characters have special meaning. If several
+        /// names match equally well, the top candidates are listed instead.
+        #[clap(short, long, conflicts_with = "regex")]
+        fuzzy: bool,
     },
     UpdateFlags {
         #[clap(flatten)]
@@ -64,13 +106,123 @@ enum Command {
         /// considered.
         #[clap(short, long)]
         regex: bool,
+        /// Fuzzy match the "stream" and "topic" names instead and use the best
+        /// match.
+        ///
+        /// Unlike "--regex", no characters have special meaning. If several
+        /// names match equally well, the top candidates are listed instead.
+        #[clap(short, long, conflicts_with = "regex")]
+        fuzzy: bool,
+    },
+    /// Edit the content, topic or stream of a message.
+    Edit {
+        /// The id of the message.
+        message_id: i64,
+        /// The new content of the message.
+        #[clap(long)]
+        content: Option<String>,
+        /// Move the message to a new topic.
+        #[clap(long)]
+        topic: Option<String>,
+        /// Move the message to another stream, given by name or id.
+        #[clap(long)]
+        stream: Option<zulib::Identifier>,
+        /// Which messages to move when changing the topic or stream.
+        #[clap(long, value_enum, default_value_t = PropagateMode::ChangeOne)]
+        propagate_mode: PropagateMode,
+    },
+    /// Move a whole topic to another stream, rename it, or both.
+    Move {
+        /// The stream of the topic, given by name or id.
+        stream: zulib::Identifier,
+        /// The name of the topic.
+        topic: String,
+        /// The stream to move the topic to, given by name or id.
+        #[clap(long)]
+        to_stream: Option<zulib::Identifier>,
+        /// The new name of the topic.
+        #[clap(long)]
+        to_topic: Option<String>,
+    },
+    /// Add or remove an emoji reaction to a message.
+    React {
+        /// The id of the message.
+        message_id: i64,
+        /// The name of the emoji, like "smile" or ":smile:".
+        emoji: String,
+        /// Remove the reaction instead of adding it.
+        #[clap(long)]
+        remove: bool,
+    },
+    /// Star a message.
+    Star {
+        /// The id of the message.
+        message_id: u64,
+        /// Unstar the message instead.
+        #[clap(long)]
+        remove: bool,
+    },
+    /// Create a new stream and subscribe to it.
+    CreateStream {
+        /// The name of the stream.
+        name: String,
+        /// A description of the stream.
+        #[clap(long, default_value = "")]
+        description: String,
+        /// Make the stream private, so that only subscribers can see it.
+        #[clap(long)]
+        invite_only: bool,
+    },
+    /// Delete a message.
+    Delete {
+        /// The id of the message.
+        message_id: i64,
+    },
+    /// Set your status text, or whether you are away.
+    ///
+    /// Only the given parts of the status are changed.
+    Status {
+        /// The status text, like "lunch". An empty text clears it.
+        text: Option<String>,
+        /// The name of an emoji to show with the status, like "sandwich". An
+        /// empty name clears it.
+        #[clap(long)]
+        emoji: Option<String>,
+        /// Mark yourself as away.
+        #[clap(long)]
+        away: bool,
+        /// Mark yourself as no longer away.
+        #[clap(long, conflicts_with = "away")]
+        back: bool,
+    },
+    /// Print new messages, possibly in a specific stream or topic, as they
+    /// arrive until interrupted with Ctrl-C.
+    Watch {
+        /// The stream to watch, given by name or id.
+        stream: Option<zulib::Identifier>,
+        /// The topic to watch.
+        topic: Option<String>,
+        /// Print the content of messages as raw HTML instead of converting it
+        /// to plain text.
+        #[clap(long)]
+        raw_html: bool,
     },
     /// Clear the caches of streams and topics.
     ClearCache,
 }
 
+#[derive(clap::Args)]
+struct Ls {
+    /// Only print the number of messages, streams, topics or cache entries
+    /// instead of the items themselves.
+    #[clap(long, global = true)]
+    count: bool,
+    #[command(subcommand)]
+    what: LsWhat,
+}
+
 #[derive(clap::Subcommand)]
-enum Ls {
+enum LsWhat {
     #[clap(short_flag = 'm')]
     Messages {
         #[clap(flatten)]
@@ -85,27 +237,76 @@ enum Ls {
         /// considered.
         #[clap(short, long)]
         regex: bool,
+        /// Fuzzy match the "stream" and "topic" names instead and use the best
+        /// match.
+        ///
+        /// Unlike "--regex", no characters have special meaning. If several
+        /// names match equally well, the top candidates are listed instead.
+        #[clap(short, long, conflicts_with = "regex")]
+        fuzzy: bool,
         /// Only print the name of all topics and the timestamp of their last
         /// message.
+        ///
+        /// Together with "--count", the number of topics is printed instead
+        /// of the number of messages.
         #[clap(short, long)]
         only_topics: bool,
+        /// Print the content of HTML messages as raw HTML instead of
+        /// converting it to plain text.
+        #[clap(long)]
+        raw_html: bool,
+        /// Print at most this many messages, or topics with "--only-topics".
+        ///
+        /// The limit applies to what is printed, not to the number of
+        /// messages fetched from the server.
+        #[clap(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+        limit: Option<u64>,
+        /// Instead of stopping after "--limit" messages, wait for the user to
+        /// press enter before printing the next page.
+        #[clap(long, requires = "limit")]
+        page: bool,
     },
     #[clap(short_flag = 's')]
     Streams(GetStreamsRequest),
     /// Get all subscribed streams.
     #[clap(short_flag = 'b')]
     Subscribed,
-    /// Get all topics for a stream.
+    /// Get all topics for a stream, most recently active first.
     #[clap(short_flag = 't')]
     Topics {
         /// The name or id of the stream.
         stream: zulib::Identifier,
+        /// Only show topics with unread messages, together with the number of
+        /// unread messages.
+        #[clap(long)]
+        unread: bool,
+        /// Only show this many of the most recently active topics.
+        #[clap(short, long)]
+        limit: Option<usize>,
     },
-    /// List streams or topics in the cache.
+    /// List streams or topics in the cache, from most to least
+    /// commonly/recently used.
     Cache {
         /// Whether to show the stream or topic cache.
         #[clap(value_enum)]
         kind: StreamOrTopic,
+        /// List the least commonly/recently used items first.
+        #[clap(short, long)]
+        reverse: bool,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum Draft {
+    /// List all drafts.
+    Ls,
+    /// Create a new draft.
+    #[clap(subcommand)]
+    Create(SendMessageRequest),
+    /// Delete a draft.
+    Delete {
+        /// The id of the draft.
+        id: u64,
     },
 }
 
@@ -116,112 +317,471 @@ enum StreamOrTopic {
 }
 
 impl Ls {
-    async fn run(self, client: &mut Client) -> Result<()> {
-        match self {
-            Ls::Messages {
+    /// Run the command, writing the output to `out`, colored with `palette`
+    /// if the format is text.
+    async fn run(
+        self,
+        client: &mut Client,
+        format: Format,
+        palette: Palette,
+        out: &mut impl Write,
+    ) -> Result<()> {
+        let count = self.count;
+        match self.what {
+            LsWhat::Messages {
                 req,
                 regex,
+                fuzzy,
                 only_topics,
+                raw_html,
+                limit,
+                page,
             } => {
-                for (topic, messages) in client.get_messages(req, regex, false).await? {
+                let search = zcli::SearchMode::from_flags(regex, fuzzy);
+                let grouped_messages = client.get_messages(req, search, false).await?;
+                if count {
+                    let (topics, messages) = grouped_messages
+                        .fold((0, 0), |(topics, messages), (_, x)| {
+                            (topics + 1, messages + x.len())
+                        });
+                    writeln!(out, "{}", if only_topics { topics } else { messages })?;
+                    return Ok(());
+                }
+                if format == Format::Json {
+                    let mut remaining = limit.map_or(usize::MAX, |x| x as usize);
+                    let json: Vec<_> = grouped_messages
+                        .map_while(|(topic, mut messages)| {
+                            if remaining == 0 {
+                                return None;
+                            }
+                            if only_topics {
+                                remaining -= 1;
+                            } else {
+                                messages.truncate(remaining);
+                                remaining -= messages.len();
+                            }
+                            Some((topic, messages))
+                        })
+                        .map(|(topic, messages)| {
+                            if only_topics {
+                                serde_json::json!({
+                                    "stream": match &messages[0].display_recipient {
+                                        DisplayRecipient::Stream(s) => Some(s),
+                                        _ => None,
+                                    },
+                                    "topic": topic,
+                                    "last_message_timestamp": messages[messages.len() - 1]
+                                        .timestamp
+                                        .timestamp(),
+                                    "message_count": messages.len(),
+                                })
+                            } else {
+                                serde_json::json!({ "topic": topic, "messages": messages })
+                            }
+                        })
+                        .collect();
+                    return write_json(out, &json);
+                }
+                // Whether to print another item after `printed` items,
+                // considering the limit and paging.
+                let mut printed = 0;
+                let mut next_item = |out: &mut dyn Write| -> Result<bool> {
+                    if let Some(limit) = limit {
+                        if printed > 0 && printed % limit == 0 && !(page && prompt_more(out)?) {
+                            return Ok(false);
+                        }
+                    }
+                    printed += 1;
+                    Ok(true)
+                };
+                'topics: for (topic, messages) in grouped_messages {
                     if only_topics {
-                        println!(
-                            "{}: {topic}: {}, {} messages",
+                        if !next_item(out)? {
+                            break;
+                        }
+                        writeln!(
+                            out,
+                            "{}: {}: {}, {} messages",
                             match &messages.as_slice()[0].display_recipient {
-                                DisplayRecipient::Stream(s) => s.as_str(),
-                                _ => "private",
+                                DisplayRecipient::Stream(s) => palette.stream(s, None),
+                                _ => "private".to_string(),
                             },
-                            HumanTime::from(messages.as_slice()[0].timestamp),
+                            palette.topic(&topic),
+                            palette.timestamp(HumanTime::from(messages.as_slice()[0].timestamp)),
                             messages.as_slice().len()
-                        );
+                        )?;
                     } else {
-                        println!("\n----------");
-                        println!("{topic}:");
-                        for message in messages {
-                            println!(
+                        for (i, message) in messages.into_iter().enumerate() {
+                            if !next_item(out)? {
+                                break 'topics;
+                            }
+                            if i == 0 {
+                                writeln!(out, "\n----------")?;
+                                writeln!(out, "{}:", palette.topic(&topic))?;
+                            }
+                            writeln!(
+                                out,
                                 "  - {} -- {}",
-                                message.sender_full_name,
-                                HumanTime::from(message.timestamp)
-                            );
-                            println!(
-                                "{}\n",
-                                textwrap::fill(
-                                    &message.content,
-                                    textwrap::Options::with_termwidth()
-                                        .initial_indent("    ")
-                                        .subsequent_indent("    ")
-                                )
-                            );
+                                palette.sender(&message.sender_full_name),
+                                palette.timestamp(HumanTime::from(message.timestamp))
+                            )?;
+                            write_content(out, &message, raw_html)?;
                         }
                     }
                 }
             }
-            Ls::Streams(req) => {
+            LsWhat::Streams(req) => {
                 let streams = client.get_streams(&req).await?;
+                if count {
+                    writeln!(out, "{}", streams.count())?;
+                    return Ok(());
+                }
+                if format == Format::Json {
+                    return write_json(out, &streams.collect::<Vec<_>>());
+                }
+                // The colors of the subscriptions are only needed, and thus only
+                // fetched, if the output is colored.
+                let colors: HashMap<u64, _> = if palette.is_enabled() {
+                    client
+                        .get_subscribed_streams()
+                        .await?
+                        .into_iter()
+                        .map(|x| (x.stream.stream_id, x.color_rgb()))
+                        .collect()
+                } else {
+                    HashMap::new()
+                };
                 for stream in streams {
-                    println!("{} -- {}", stream.name, stream.description);
+                    let color = colors.get(&stream.stream_id).copied().flatten();
+                    writeln!(
+                        out,
+                        "{} -- {}",
+                        palette.stream(&stream.name, color),
+                        stream.description
+                    )?;
                 }
             }
-            Ls::Subscribed => {
+            LsWhat::Subscribed => {
                 let subscriptions = client.get_subscribed_streams().await?;
+                if count {
+                    writeln!(out, "{}", subscriptions.len())?;
+                    return Ok(());
+                }
+                if format == Format::Json {
+                    return write_json(out, &subscriptions);
+                }
                 for subscription in subscriptions {
-                    println!(
+                    writeln!(
+                        out,
                         "{} -- {}",
-                        subscription.stream.name,
+                        palette.stream(&subscription.stream.name, subscription.color_rgb()),
                         if subscription.is_muted {
                             "Muted"
                         } else {
                             "Unmuted"
                         }
-                    );
+                    )?;
                 }
             }
-            Ls::Topics { stream } => {
+            LsWhat::Topics {
+                stream,
+                unread,
+                limit,
+            } => {
                 let stream_id = match stream {
                     zulib::Identifier::Id(x) => x,
                     zulib::Identifier::Name(x) => client.get_stream_id(&x).await?,
                 };
-                let mut topics = client.get_topics_in_stream(stream_id).await?;
-                topics.sort();
+                if unread {
+                    let mut topics = client.get_unread_topics(stream_id).await?;
+                    topics.truncate(limit.unwrap_or(usize::MAX));
+                    if count {
+                        writeln!(out, "{}", topics.len())?;
+                        return Ok(());
+                    }
+                    if format == Format::Json {
+                        return write_json(out, &topics);
+                    }
+                    for UnreadTopic {
+                        name, unread_count, ..
+                    } in topics
+                    {
+                        writeln!(out, "{name} ({unread_count})")?;
+                    }
+                    return Ok(());
+                }
+                let topics = client.get_stream_topics(stream_id, limit).await?;
+                if count {
+                    writeln!(out, "{}", topics.len())?;
+                    return Ok(());
+                }
+                if format == Format::Json {
+                    return write_json(out, &topics);
+                }
                 for Topic { name, .. } in topics {
-                    println!("{name}");
+                    writeln!(out, "{name}")?;
                 }
             }
-            Ls::Cache {
+            LsWhat::Cache {
                 kind: StreamOrTopic::Stream,
+                reverse,
             } => {
-                for stream in client.stream_cache_iter().rev() {
-                    println!("{}", stream.name);
+                if count {
+                    writeln!(out, "{}", client.stream_cache_iter().count())?;
+                    return Ok(());
+                }
+                let mut streams: Vec<_> = client.stream_cache_iter().collect();
+                if reverse {
+                    streams.reverse();
+                }
+                if format == Format::Json {
+                    return write_json(out, &streams);
+                }
+                for stream in streams {
+                    writeln!(out, "{}", palette.stream(&stream.name, None))?;
                 }
             }
-            Ls::Cache {
+            LsWhat::Cache {
                 kind: StreamOrTopic::Topic,
+                reverse,
             } => {
-                for topic in client.topic_cache_iter().rev() {
-                    println!("{topic}");
+                if count {
+                    writeln!(out, "{}", client.topic_cache_iter().count())?;
+                    return Ok(());
+                }
+                let mut topics: Vec<_> = client.topic_cache_iter().collect();
+                if reverse {
+                    topics.reverse();
+                }
+                if format == Format::Json {
+                    return write_json(out, &topics);
+                }
+                for topic in topics {
+                    writeln!(out, "{topic}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Draft {
+    async fn run(self, client: &mut Client, format: Format) -> Result<()> {
+        match self {
+            Draft::Ls => {
+                let drafts = client.get_drafts().await?;
+                if format == Format::Json {
+                    return write_json(&mut std::io::stdout(), &drafts);
+                }
+                for draft in drafts {
+                    println!(
+                        "{}: {} -- {}",
+                        draft.id.unwrap_or_default(),
+                        match draft.r#type {
+                            zulib::draft::DraftType::Stream => draft.topic.as_str(),
+                            zulib::draft::DraftType::Private => "private",
+                        },
+                        draft.content
+                    );
                 }
             }
+            Draft::Create(req) => {
+                let id = client.create_draft(req).await?;
+                println!("Created draft {id}");
+            }
+            Draft::Delete { id } => client.delete_draft(id).await?,
         }
         Ok(())
     }
 }
 
 impl Command {
-    async fn run(self, client: &mut Client) -> Result<()> {
+    async fn run(self, client: &mut Client, format: Format) -> Result<()> {
         match self {
-            Command::Ls(x) => x.run(client).await?,
-            Command::Send(req) => {
-                println!("Sending: {req:?}");
+            Command::Ls(x) => {
+                x.run(client, format, Palette::from_env(), &mut std::io::stdout())
+                    .await?
+            }
+            Command::Send { editor, mut req } => {
+                let (SendMessageRequest::Stream { content, .. }
+                | SendMessageRequest::Private { content, .. }) = &mut req;
+                if let Some(editor) = editor {
+                    *content = zcli::editor::compose(editor.as_deref(), content)?;
+                }
+                if content.trim().is_empty() {
+                    bail!("Not sending an empty message");
+                }
+                if let SendMessageRequest::Private { to, .. } = &mut req {
+                    *to = client.resolve_private_recipients(to.clone()).await?;
+                }
+                let id = client.send_message(req).await?.id;
+                println!("Sent message {id}");
+            }
+            Command::Draft(x) => x.run(client, format).await?,
+            Command::Edit {
+                message_id,
+                content,
+                topic,
+                stream,
+                propagate_mode,
+            } => {
+                if content.is_none() && topic.is_none() && stream.is_none() {
+                    bail!("Nothing to edit, specify a new content, topic or stream");
+                }
+                let mut req = EditMessageRequest::new(message_id);
+                req.propagate_mode(propagate_mode);
+                if let Some(content) = content {
+                    req.content(&content);
+                }
+                if let Some(topic) = topic {
+                    req.topic(&topic);
+                }
+                if let Some(stream) = stream {
+                    let stream_id = match stream {
+                        zulib::Identifier::Id(x) => x,
+                        zulib::Identifier::Name(x) => client.get_stream_id(&x).await?,
+                    };
+                    req.stream_id(stream_id.try_into()?);
+                }
+                client.edit_message(req).await?;
+                println!("Edited message {message_id}");
+            }
+            Command::Move {
+                stream,
+                topic,
+                to_stream,
+                to_topic,
+            } => {
+                if to_stream.is_none() && to_topic.is_none() {
+                    bail!("Nothing to do, specify a new stream or topic");
+                }
+                let stream_id = match stream {
+                    zulib::Identifier::Id(x) => x,
+                    zulib::Identifier::Name(x) => client.get_stream_id(&x).await?,
+                };
+                let new_stream_id = match to_stream {
+                    Some(zulib::Identifier::Id(x)) => Some(x),
+                    Some(zulib::Identifier::Name(x)) => Some(client.get_stream_id(&x).await?),
+                    None => None,
+                };
+                client
+                    .move_topic(stream_id, &topic, new_stream_id, to_topic.as_deref())
+                    .await?;
+                println!("Moved topic {topic}");
+            }
+            Command::React {
+                message_id,
+                emoji,
+                remove,
+            } => {
+                let emoji = emoji.trim().trim_matches(':');
+                if emoji.is_empty() {
+                    bail!("The emoji name must not be empty");
+                }
+                if remove {
+                    let mut req = RemoveEmojiReactionRequest::new(message_id);
+                    req.emoji_name(emoji);
+                    client
+                        .remove_emoji_reaction(req)
+                        .await
+                        .with_context(|| format!("Failed to remove the reaction :{emoji}:"))?;
+                } else {
+                    client
+                        .add_emoji_reaction(AddEmojiReactionRequest::new(message_id, emoji))
+                        .await
+                        .with_context(|| format!("Failed to react with :{emoji}:"))?;
+                }
+            }
+            Command::Star { message_id, remove } => {
+                client.set_star(&[message_id], !remove).await?;
+            }
+            Command::CreateStream {
+                name,
+                description,
+                invite_only,
+            } => {
+                // Subscribing creates the stream only if it doesn't exist, so
+                // check that first.
+                if client.try_get_stream_id(&name).await?.is_some() {
+                    bail!("A stream named {name} already exists");
+                }
+                let response = client
+                    .create_stream(&name, &description, invite_only)
+                    .await?;
+                if !response.unauthorized.is_empty() {
+                    bail!("Not allowed to create the stream {name}");
+                }
+                println!("Created stream {name}");
+            }
+            Command::Status {
+                text,
+                emoji,
+                away,
+                back,
+            } => {
+                client
+                    .update_status(UserStatus {
+                        status_text: text,
+                        away: (away || back).then_some(away),
+                        emoji_name: emoji.map(|x| x.trim().trim_matches(':').to_string()),
+                    })
+                    .await
+                    .context("Failed to update the status")?;
+            }
+            Command::Delete { message_id } => {
+                client.delete_message(message_id).await?;
+                println!("Deleted message {message_id}");
+            }
+            Command::Watch {
+                stream,
+                topic,
+                raw_html,
+            } => {
+                let mut narrow = Vec::new();
+                if let Some(stream) = stream {
+                    // The narrow of an event queue takes stream names, not ids.
+                    let name = match stream {
+                        zulib::Identifier::Id(x) => client.refresh_stream(x).await?.name.clone(),
+                        zulib::Identifier::Name(x) => x,
+                    };
+                    narrow.push(("stream".to_string(), name));
+                }
+                if let Some(topic) = topic {
+                    narrow.push(("topic".to_string(), topic));
+                }
+                let req = RegisterQueueRequest {
+                    event_types: vec!["message".to_string()],
+                    narrow,
+                    apply_markdown: true,
+                };
+                let ctrl_c = async {
+                    let _ = tokio::signal::ctrl_c().await;
+                };
+                watch(
+                    client,
+                    &req,
+                    raw_html,
+                    format,
+                    Palette::from_env(),
+                    &mut std::io::stdout(),
+                    ctrl_c,
+                )
+                .await?;
             }
             Command::ClearCache => client.clear_cache(),
             Command::MarkRead {
                 stream,
                 topic,
                 regex,
-            } => client.mark_read(stream, topic, regex, false).await?,
-            Command::UpdateFlags { req, regex } => {
+                fuzzy,
+            } => {
+                let search = zcli::SearchMode::from_flags(regex, fuzzy);
+                client.mark_read(stream, topic, search, false).await?
+            }
+            Command::UpdateFlags { req, regex, fuzzy } => {
+                let search = zcli::SearchMode::from_flags(regex, fuzzy);
                 client
-                    .update_message_flags_for_narrow(req, regex, false)
+                    .update_message_flags_for_narrow(req, search, false)
                     .await?;
             }
         }
@@ -230,12 +790,44 @@ impl Command {
 }
 
 impl CommandOrRepl {
-    async fn run(self, client: &mut Client) -> Result<()> {
+    async fn run(
+        self,
+        client: &mut Client,
+        format: Format,
+        cache_file_path: Option<&Path>,
+    ) -> Result<()> {
         match self {
-            Self::Command(x) => x.run(client).await,
+            Self::Command(x) => x.run(client, format).await,
             Self::Repl => {
-                clap_repl::run_repl(prompt_str, |x, y| Box::pin(ReplCommand::run(x, y)), client)
-                    .await
+                // Save the cache after every command so that nothing is lost if the repl
+                // crashes.
+                let hooks = clap_repl::Hooks {
+                    after: cache_file_path.map(|path| {
+                        Box::new(move |client: &mut Client, _: &_| {
+                            if let Err(e) = save_cache(client, path) {
+                                eprintln!("Error: {e:#}");
+                            }
+                        }) as Box<_>
+                    }),
+                    ..Default::default()
+                };
+                client.refresh_unread_count(UNREAD_COUNT_MAX_AGE).await;
+                clap_repl::run_repl_with_hooks(
+                    prompt_str,
+                    |x, y| {
+                        Box::pin(async move {
+                            let res = ReplCommand::run(x, y, format).await;
+                            // Update the unread count in the prompt unless the repl is quitting.
+                            if !res.as_ref().is_ok_and(|x| x.is_break()) {
+                                y.refresh_unread_count(UNREAD_COUNT_MAX_AGE).await;
+                            }
+                            res
+                        })
+                    },
+                    hooks,
+                    client,
+                )
+                .await
             }
         }
     }
@@ -256,19 +848,77 @@ enum ReplCommand {
         /// Don't interpret the stream name as a regular expression.
         #[clap(short = 's', long)]
         no_regex: bool,
+        /// Fuzzy match the stream name instead of interpreting it as a regular
+        /// expression.
+        #[clap(short, long, conflicts_with = "no_regex")]
+        fuzzy: bool,
     },
+    /// Select a topic in the currently selected stream.
+    #[clap(visible_aliases=&["st"])]
+    SelectTopic {
+        /// The name of the topic to select. Can be a regular expression.
+        topic: String,
+        /// Don't interpret the topic name as a regular expression. This is
+        /// needed to select a topic without any messages.
+        #[clap(short = 's', long)]
+        no_regex: bool,
+        /// Fuzzy match the topic name instead of interpreting it as a regular
+        /// expression.
+        #[clap(short, long, conflicts_with = "no_regex")]
+        fuzzy: bool,
+    },
+    /// Send a message to the selected stream and topic.
+    Reply {
+        /// The content of the message.
+        content: String,
+    },
+    /// Write a message in your editor ($ZCLI_EDITOR, $VISUAL or $EDITOR) and
+    /// send it to the selected stream and topic.
+    Compose,
 }
 
 impl ReplCommand {
-    async fn run(self, client: &mut Client) -> Result<ControlFlow<(), ()>> {
+    async fn run(self, client: &mut Client, format: Format) -> Result<ControlFlow<(), ()>> {
         match self {
-            Self::Command(x) => x.run(client).await.map(ControlFlow::Continue),
+            Self::Command(x) => x.run(client, format).await.map(ControlFlow::Continue),
             Self::Quit => Ok(ControlFlow::Break(())),
-            Self::SelectStream { stream, no_regex } => {
-                let stream = client.select_stream(&stream, !no_regex).await?;
+            Self::SelectStream {
+                stream,
+                no_regex,
+                fuzzy,
+            } => {
+                let search = zcli::SearchMode::from_flags(!no_regex, fuzzy);
+                let stream = client.select_stream(&stream, search).await?;
                 println!("Selected stream {}", stream.name);
                 Ok(ControlFlow::Continue(()))
             }
+            Self::SelectTopic {
+                topic,
+                no_regex,
+                fuzzy,
+            } => {
+                let search = zcli::SearchMode::from_flags(!no_regex, fuzzy);
+                let topic = client.select_topic(&topic, search).await?;
+                println!("Selected topic {topic}");
+                Ok(ControlFlow::Continue(()))
+            }
+            Self::Reply { content } => {
+                let id = client.reply(content).await?;
+                println!("Sent message {id}");
+                Ok(ControlFlow::Continue(()))
+            }
+            Self::Compose => {
+                if client.selected_topic().is_none() {
+                    bail!("No topic selected");
+                }
+                let content = zcli::editor::compose(None, "")?;
+                if content.trim().is_empty() {
+                    bail!("Not sending an empty message");
+                }
+                let id = client.reply(content).await?;
+                println!("Sent message {id}");
+                Ok(ControlFlow::Continue(()))
+            }
         }
     }
 }
@@ -283,23 +933,22 @@ async fn main() -> Result<()> {
     let zuliprc_path = dirs::home_dir()
         .context("No home dir (in which to find .zuliprc) found.")?
         .join(".zuliprc");
-    let zuliprc = zulib::ZulipRc::parse_from_str(
-        &std::fs::read_to_string(&zuliprc_path)
-            .map_err(|e| {
-                if e.kind() == std::io::ErrorKind::NotFound {
-                    anyhow!(
-                        "A .zuliprc file corresponding to your account on a particular Zulip \
-                         server can be downloaded via Web or Desktop applications connected to \
-                         that server. In recent versions this can be found in your Personal \
-                         settings in the Account & privacy section, under API key as 'Show/change \
-                         your API key'."
-                    )
-                } else {
-                    anyhow!(e)
-                }
-            })
-            .with_context(|| format!("Failed to read .zuliprc at {}", zuliprc_path.display()))?,
-    )?;
+    // Fall back to environment variables if there is no .zuliprc, so that zcli
+    // can be run headless.
+    let zuliprc = zulib::ZulipRc::parse_or_env(&zuliprc_path).map_err(|e| {
+        if zuliprc_path.exists() {
+            e
+        } else {
+            e.context(
+                "A .zuliprc file corresponding to your account on a particular Zulip server can \
+                 be downloaded via Web or Desktop applications connected to that server. In \
+                 recent versions this can be found in your Personal settings in the Account & \
+                 privacy section, under API key as 'Show/change your API key'. It should be \
+                 placed in your home directory, or the environment variables ZULIP_EMAIL, \
+                 ZULIP_API_KEY and ZULIP_SITE can be set instead.",
+            )
+        }
+    })?;
 
     let cache_file_path: Option<_> = dirs::cache_dir().map(|x| x.join("zcli.json"));
     let cache_file_content: Option<String> = cache_file_path
@@ -314,23 +963,781 @@ async fn main() -> Result<()> {
         )
         .transpose()?;
     let mut client = if let Some(cache) = cache_file_content {
-        Client::from_cache(&cache, zuliprc)?
+        Client::from_cache(&cache, zuliprc, &args.cache_config)?
     } else {
-        Client::new(zuliprc)?
+        Client::new(zuliprc, &args.cache_config)?
     };
 
-    args.command.run(&mut client).await?;
+    args.command
+        .run(&mut client, args.format, cache_file_path.as_deref())
+        .await?;
     if let Some(cache_file_path) = cache_file_path {
-        std::fs::write(cache_file_path, client.mk_cache_file())?;
+        save_cache(&client, &cache_file_path)?;
+    }
+    Ok(())
+}
+
+/// Write the cache of a client to the cache file.
+fn save_cache(client: &Client, cache_file_path: &Path) -> Result<()> {
+    std::fs::write(cache_file_path, client.mk_cache_file()).with_context(|| {
+        format!(
+            "Failed to write cache file at {}",
+            cache_file_path.display()
+        )
+    })
+}
+
+/// Write a value as pretty printed JSON, followed by a newline.
+fn write_json(out: &mut impl Write, value: &impl serde::Serialize) -> Result<()> {
+    serde_json::to_writer_pretty(&mut *out, value)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Write the content of a message, indented and wrapped to the terminal width.
+///
+/// HTML content is converted to plain text unless `raw_html` is set.
+fn write_content(out: &mut impl Write, message: &ReceivedMessage, raw_html: bool) -> Result<()> {
+    let content = if message.content_type == "text/html" && !raw_html {
+        Cow::Owned(zcli::html::html_to_text(&message.content))
+    } else {
+        Cow::Borrowed(&message.content)
+    };
+    writeln!(
+        out,
+        "{}\n",
+        textwrap::fill(
+            &content,
+            textwrap::Options::with_termwidth()
+                .initial_indent("    ")
+                .subsequent_indent("    ")
+        )
+    )?;
+    Ok(())
+}
+
+/// Register an event queue with `req` and print the new messages from it as
+/// they arrive, until `shutdown` completes.
+///
+/// If the queue is garbage collected by the server, a new one is registered.
+async fn watch(
+    client: &Client,
+    req: &RegisterQueueRequest,
+    raw_html: bool,
+    format: Format,
+    palette: Palette,
+    out: &mut impl Write,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    tokio::pin!(shutdown);
+    let mut queue = client.register_queue(req).await?;
+    loop {
+        let events = tokio::select! {
+            _ = &mut shutdown => break,
+            events = client.get_events(&queue.queue_id, queue.last_event_id) => events,
+        };
+        let events = match events {
+            Err(e) if e.is_bad_event_queue() => {
+                queue = client.register_queue(req).await?;
+                continue;
+            }
+            events => events?,
+        };
+        for event in events {
+            queue.last_event_id = queue.last_event_id.max(event.id);
+            let EventKind::Message { message } = event.kind else {
+                continue;
+            };
+            if format == Format::Json {
+                serde_json::to_writer(&mut *out, &message)?;
+                writeln!(out)?;
+                continue;
+            }
+            let recipient = match &message.display_recipient {
+                DisplayRecipient::Stream(stream) => format!(
+                    "{} > {}",
+                    palette.stream(stream, None),
+                    palette.topic(&message.subject)
+                ),
+                _ => "private".to_string(),
+            };
+            writeln!(
+                out,
+                "{}: {} -- {}",
+                recipient,
+                palette.sender(&message.sender_full_name),
+                palette.timestamp(HumanTime::from(message.timestamp))
+            )?;
+            write_content(out, &message, raw_html)?;
+        }
+        out.flush()?;
     }
+    // The server garbage collects the queue eventually anyway, so a failure
+    // to delete it is not worth reporting when quitting.
+    let _ = client.delete_queue(&queue.queue_id).await;
     Ok(())
 }
 
+/// Ask the user on stderr whether to print another page of output.
+///
+/// Returns false if the user answers "q" or stdin is closed.
+fn prompt_more(out: &mut dyn Write) -> Result<bool> {
+    out.flush()?;
+    eprint!("-- More (press enter to continue, q to quit) --");
+    let mut answer = String::new();
+    let read = std::io::stdin().read_line(&mut answer)?;
+    Ok(read > 0 && !answer.trim().eq_ignore_ascii_case("q"))
+}
+
+/// How often the number of unread messages in the prompt of the repl is
+/// updated at most.
+const UNREAD_COUNT_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(60);
+
 /// Generate a prompt string.
 fn prompt_str(client: &mut Client) -> String {
-    if let Some(stream) = client.selected_stream() {
-        format!("(zcli)->{}: ", stream.name)
-    } else {
-        "(zcli) ".to_string()
+    format_prompt(
+        client.selected_stream().map(|x| x.name.as_str()),
+        client.selected_topic(),
+        client.unread_count(),
+    )
+}
+
+/// Format the prompt from the selected stream and topic and the number of
+/// unread messages, which is left out if unknown.
+fn format_prompt(stream: Option<&str>, topic: Option<&str>, unread_count: Option<usize>) -> String {
+    let unread = unread_count.map_or(String::new(), |x| format!(" ({x} unread)"));
+    match (stream, topic) {
+        (Some(stream), Some(topic)) => format!("(zcli)->{stream}>{topic}{unread}: "),
+        (Some(stream), None) => format!("(zcli)->{stream}{unread}: "),
+        _ => format!("(zcli){unread} "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use httpmock::{
+        Method::{DELETE, GET, PATCH, POST},
+        MockServer,
+    };
+
+    use super::*;
+    use crate::test_fixtures::*;
+
+    /// Parse command line arguments which should be a command and not the
+    /// repl.
+    fn parse_command(args: &[&str]) -> (Command, Format) {
+        let Args {
+            command: CommandOrRepl::Command(command),
+            format,
+            ..
+        } = Args::parse_from(args)
+        else {
+            panic!("Expected a command");
+        };
+        (command, format)
+    }
+
+    /// Parse command line arguments which should be an ls command.
+    fn parse_ls(args: &[&str]) -> (Ls, Format) {
+        let (Command::Ls(ls), format) = parse_command(args) else {
+            panic!("Expected an ls command");
+        };
+        (ls, format)
+    }
+
+    /// A message in stream 1 in the JSON format of the API.
+    fn message_json(id: u64, topic: &str, content: &str) -> String {
+        format!(
+            r#"{{"id": {id}, "timestamp": 1691057093, "content": "{content}",
+                "content_type": "text/x-markdown", "avatar_url": null,
+                "client": "website", "display_recipient": "general",
+                "is_me_message": false, "reactions": [], "recipient_id": 10,
+                "sender_email": "you@example.com", "sender_full_name": "You",
+                "sender_id": 3, "sender_realm_str": "example", "stream_id": 1,
+                "subject": "{topic}", "type": "stream", "flags": []}}"#
+        )
+    }
+
+    #[tokio::test]
+    async fn test_count_streams() {
+        let server = MockServer::start();
+        // `Client::get_streams` orders the streams by subscription.
+        let subscriptions_mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/users/me/subscriptions");
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "subscriptions": []}"#);
+        });
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/streams");
+            then.status(200).body(format!(
+                r#"{{"result": "success", "msg": "", "streams": [{}, {}, {}]}}"#,
+                stream_json(1, "general"),
+                stream_json(2, "announce"),
+                stream_json(3, "off topic"),
+            ));
+        });
+        let mut client = test_client(&server);
+        let (ls, format) = parse_ls(&["zcli", "ls", "streams", "--count"]);
+        let mut out = Vec::new();
+        ls.run(&mut client, format, Palette::PLAIN, &mut out)
+            .await
+            .unwrap();
+        subscriptions_mock.assert();
+        mock.assert();
+        assert_eq!(String::from_utf8(out).unwrap(), "3\n");
+    }
+
+    #[tokio::test]
+    async fn test_messages_as_json() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/messages");
+            then.status(200).body(format!(
+                r#"{{"result": "success", "msg": "", "anchor": 2, "found_newest": true,
+                    "found_anchor": false, "messages": [{}, {}]}}"#,
+                message_json(2, "greetings", "Hello"),
+                message_json(1, "greetings", "Hi"),
+            ));
+        });
+        let mut client = test_client(&server);
+        let (ls, format) = parse_ls(&["zcli", "--format", "json", "ls", "messages"]);
+        let mut out = Vec::new();
+        ls.run(&mut client, format, Palette::PLAIN, &mut out)
+            .await
+            .unwrap();
+        mock.assert();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 1);
+        assert_eq!(json[0]["topic"], "greetings");
+        let messages = json[0]["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["id"], 1);
+        assert_eq!(messages[0]["content"], "Hi");
+        assert_eq!(messages[1]["content"], "Hello");
+    }
+
+    #[tokio::test]
+    async fn test_messages_limit() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/messages");
+            then.status(200).body(format!(
+                r#"{{"result": "success", "msg": "", "anchor": 3, "found_newest": true,
+                    "found_anchor": false, "messages": [{}, {}, {}]}}"#,
+                message_json(1, "greetings", "Hi"),
+                message_json(2, "greetings", "Hello"),
+                message_json(3, "farewells", "Bye"),
+            ));
+        });
+        let mut client = test_client(&server);
+
+        let (ls, format) = parse_ls(&["zcli", "ls", "messages", "--limit", "2"]);
+        let mut out = Vec::new();
+        ls.run(&mut client, format, Palette::PLAIN, &mut out)
+            .await
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches("  - You -- ").count(), 2);
+        assert!(out.contains("Hi") && out.contains("Hello"));
+        assert!(!out.contains("farewells"));
+
+        let (ls, format) = parse_ls(&["zcli", "ls", "messages", "--only-topics", "-l", "1"]);
+        let mut out = Vec::new();
+        ls.run(&mut client, format, Palette::PLAIN, &mut out)
+            .await
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 1);
+
+        let (ls, format) = parse_ls(&["zcli", "--format", "json", "ls", "messages", "-l", "1"]);
+        let mut out = Vec::new();
+        ls.run(&mut client, format, Palette::PLAIN, &mut out)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 1);
+        assert_eq!(json[0]["messages"].as_array().unwrap().len(), 1);
+        mock.assert_hits(3);
+    }
+
+    #[tokio::test]
+    async fn test_messages_colors() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/messages");
+            then.status(200).body(format!(
+                r#"{{"result": "success", "msg": "", "anchor": 1, "found_newest": true,
+                    "found_anchor": false, "messages": [{}]}}"#,
+                message_json(1, "greetings", "Hi"),
+            ));
+        });
+        let mut client = test_client(&server);
+        let no_color = std::ffi::OsStr::new("1");
+        for (palette, colored) in [
+            (Palette::detect(false, None), false),
+            (Palette::detect(true, Some(no_color)), false),
+            (Palette::detect(true, None), true),
+        ] {
+            let (ls, format) = parse_ls(&["zcli", "ls", "messages"]);
+            let mut out = Vec::new();
+            ls.run(&mut client, format, palette, &mut out)
+                .await
+                .unwrap();
+            let out = String::from_utf8(out).unwrap();
+            assert_eq!(out.contains('\x1b'), colored, "{out:?}");
+            assert!(out.contains("greetings") && out.contains("You"));
+        }
+        mock.assert_hits(3);
+    }
+
+    #[tokio::test]
+    async fn test_unread_topics() {
+        let server = MockServer::start();
+        let stream_mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/streams/1");
+            then.status(200).body(format!(
+                r#"{{"result": "success", "msg": "", "stream": {}}}"#,
+                stream_json(1, "general")
+            ));
+        });
+        let messages_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/messages")
+                .query_param_exists("narrow");
+            then.status(200).body(format!(
+                r#"{{"result": "success", "msg": "", "anchor": 5, "found_newest": true,
+                    "found_anchor": false, "messages": [{}, {}, {}]}}"#,
+                message_json(3, "old", "a"),
+                message_json(4, "new", "b"),
+                message_json(5, "old", "c"),
+            ));
+        });
+        let topics_mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/users/me/1/topics");
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "topics": [
+                    {"name": "read", "max_id": 9},
+                    {"name": "old", "max_id": 5},
+                    {"name": "new", "max_id": 7}]}"#,
+            );
+        });
+        let mut client = test_client(&server);
+        let (ls, format) = parse_ls(&["zcli", "ls", "topics", "1", "--unread"]);
+        let mut out = Vec::new();
+        ls.run(&mut client, format, Palette::PLAIN, &mut out)
+            .await
+            .unwrap();
+        stream_mock.assert();
+        messages_mock.assert();
+        topics_mock.assert();
+        assert_eq!(String::from_utf8(out).unwrap(), "new (1)\nold (2)\n");
+    }
+
+    #[tokio::test]
+    async fn test_cache_reverse() {
+        let server = MockServer::start();
+        let mut topics = common_cache::CommonCache::new(2, None);
+        for topic in ["a", "b", "c", "d"] {
+            topics.insert(topic.to_string(), 1u64);
+        }
+        topics.entry("c").unwrap().get_value();
+        let streams = common_cache::CommonCache::<u64, zulib::stream::Stream>::new(2, None);
+        // Not using `serde_json::json!` since it would reorder the items.
+        let cache = format!(
+            r#"{{"streams": {}, "topics": {}}}"#,
+            serde_json::to_string(&streams).unwrap(),
+            serde_json::to_string(&topics).unwrap(),
+        );
+        let mut client = Client::from_cache(&cache, test_rc(&server), &Default::default()).unwrap();
+        let expected: Vec<_> = topics.iter().map(|(topic, _)| topic.as_str()).collect();
+
+        let mut out = Vec::new();
+        let (ls, format) = parse_ls(&["zcli", "ls", "cache", "topic"]);
+        ls.run(&mut client, format, Palette::PLAIN, &mut out)
+            .await
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.lines().eq(expected.iter().copied()));
+
+        let mut out = Vec::new();
+        let (ls, format) = parse_ls(&["zcli", "ls", "cache", "topic", "--reverse"]);
+        ls.run(&mut client, format, Palette::PLAIN, &mut out)
+            .await
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.lines().eq(expected.iter().rev().copied()));
+    }
+
+    #[tokio::test]
+    async fn test_edit() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(PATCH)
+                .path("/api/v1/messages/42")
+                .body_contains("content=New+content")
+                .body_contains("propagate_mode=change_one");
+            then.status(200).body(r#"{"result": "success", "msg": ""}"#);
+        });
+        let mut client = test_client(&server);
+        let (command, format) = parse_command(&["zcli", "edit", "42", "--content", "New content"]);
+        command.run(&mut client, format).await.unwrap();
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn test_react() {
+        let server = MockServer::start();
+        let add_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/messages/42/reactions")
+                .body_contains("emoji_name=smile");
+            then.status(200).body(r#"{"result": "success", "msg": ""}"#);
+        });
+        let remove_mock = server.mock(|when, then| {
+            when.method(DELETE)
+                .path("/api/v1/messages/42/reactions")
+                .body_contains("emoji_name=smile");
+            then.status(200).body(r#"{"result": "success", "msg": ""}"#);
+        });
+        let mut client = test_client(&server);
+        for (args, hits) in [
+            (&["zcli", "react", "42", ":smile:"][..], (1, 0)),
+            (&["zcli", "react", "42", "smile", "--remove"], (1, 1)),
+        ] {
+            let (command, format) = parse_command(args);
+            command.run(&mut client, format).await.unwrap();
+            assert_eq!((add_mock.hits(), remove_mock.hits()), hits);
+        }
+        let (command, format) = parse_command(&["zcli", "react", "42", "::"]);
+        assert!(command.run(&mut client, format).await.is_err());
+        assert_eq!(add_mock.hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_status() {
+        let server = MockServer::start();
+        let text_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/users/me/status")
+                .body("status_text=lunch&emoji_name=sandwich");
+            then.status(200).body(r#"{"result": "success", "msg": ""}"#);
+        });
+        let back_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/users/me/status")
+                .body("away=false");
+            then.status(200).body(r#"{"result": "success", "msg": ""}"#);
+        });
+        let mut client = test_client(&server);
+        for args in [
+            &["zcli", "status", "lunch", "--emoji", ":sandwich:"][..],
+            &["zcli", "status", "--back"],
+        ] {
+            let (command, format) = parse_command(args);
+            command.run(&mut client, format).await.unwrap();
+        }
+        text_mock.assert();
+        back_mock.assert();
+        let (command, format) = parse_command(&["zcli", "status"]);
+        assert!(command.run(&mut client, format).await.is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_send_with_editor() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/messages")
+                .body_contains("content=Hello+from+the+editor");
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "id": 7}"#);
+        });
+        let mut client = test_client(&server);
+        // A fake editor which writes `text` to the file.
+        let editor = std::env::temp_dir().join(format!("zcli-test-editor-{}", std::process::id()));
+        let editor_arg = format!("--editor={}", editor.display());
+        let send = |text: &str| {
+            std::fs::write(&editor, format!("#!/bin/sh\nprintf '{text}' > \"$1\"\n")).unwrap();
+            std::fs::set_permissions(&editor, std::fs::Permissions::from_mode(0o755)).unwrap();
+            parse_command(&[
+                "zcli",
+                "send",
+                &editor_arg,
+                "stream",
+                "general",
+                "greetings",
+            ])
+        };
+
+        let (command, format) = send("Hello from the editor");
+        command.run(&mut client, format).await.unwrap();
+        mock.assert();
+        // The message is not sent if the file is left empty.
+        let (command, format) = send("");
+        assert!(command.run(&mut client, format).await.is_err());
+        mock.assert_hits(1);
+
+        std::fs::remove_file(&editor).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_send_private_by_name() {
+        let server = MockServer::start();
+        let users_mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/users");
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "members": [
+                    {"user_id": 8, "email": "alice@example.com", "full_name": "Alice Smith"},
+                    {"user_id": 9, "email": "bob@example.com", "full_name": "Bob"},
+                    {"user_id": 10, "email": "old@example.com", "full_name": "Bob",
+                        "is_active": false}
+                ]}"#,
+            );
+        });
+        let single_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/messages")
+                .body_contains("to=8&");
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "id": 1}"#);
+        });
+        let several_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/messages")
+                // The form encoded version of: to=[8,9,3]
+                .body_contains("to=%5B8%2C9%2C3%5D&");
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "id": 2}"#);
+        });
+        let mut client = test_client(&server);
+
+        let (command, format) = parse_command(&["zcli", "send", "private", "Alice Smith", "hi"]);
+        command.run(&mut client, format).await.unwrap();
+        single_mock.assert();
+
+        let (command, format) = parse_command(&[
+            "zcli",
+            "send",
+            "private",
+            "alice smith, BOB@example.com,3",
+            "hi",
+        ]);
+        command.run(&mut client, format).await.unwrap();
+        several_mock.assert();
+
+        let (command, format) = parse_command(&["zcli", "send", "private", "Carol", "hi"]);
+        assert!(command.run(&mut client, format).await.is_err());
+        users_mock.assert_hits(3);
+    }
+
+    #[test]
+    fn test_format_prompt() {
+        assert_eq!(format_prompt(None, None, None), "(zcli) ");
+        assert_eq!(format_prompt(None, None, Some(3)), "(zcli) (3 unread) ");
+        assert_eq!(
+            format_prompt(Some("general"), None, Some(0)),
+            "(zcli)->general (0 unread): "
+        );
+        assert_eq!(
+            format_prompt(Some("general"), Some("greetings"), None),
+            "(zcli)->general>greetings: "
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prompt_unread_count() {
+        let server = MockServer::start();
+        let mut register_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/register")
+                .body_contains("event_types=%5B%22message%22%5D");
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "queue_id": "1375801870:2942",
+                    "last_event_id": -1, "unread_msgs": {"count": 2, "pms": [],
+                    "streams": [], "huddles": [], "mentions": []}}"#,
+            );
+        });
+        let delete_mock = server.mock(|when, then| {
+            when.method(DELETE)
+                .path("/api/v1/events")
+                .query_param("queue_id", "1375801870:2942");
+            then.status(200).body(r#"{"result": "success", "msg": ""}"#);
+        });
+        let mut client = test_client(&server);
+        assert_eq!(prompt_str(&mut client), "(zcli) ");
+        client.refresh_unread_count(Duration::ZERO).await;
+        register_mock.assert();
+        delete_mock.assert();
+        assert_eq!(prompt_str(&mut client), "(zcli) (2 unread) ");
+        // A recent count is not refreshed.
+        client.refresh_unread_count(UNREAD_COUNT_MAX_AGE).await;
+        register_mock.assert_hits(1);
+
+        // The count is left out if it can't be fetched.
+        register_mock.delete();
+        server.mock(|when, then| {
+            when.method(POST).path("/api/v1/register");
+            then.status(500).body("Internal server error");
+        });
+        client.refresh_unread_count(Duration::ZERO).await;
+        assert_eq!(prompt_str(&mut client), "(zcli) ");
+    }
+
+    #[tokio::test]
+    async fn test_star() {
+        let server = MockServer::start();
+        let mock = |op| {
+            server.mock(|when, then| {
+                when.method(POST)
+                    .path("/api/v1/messages/flags")
+                    .query_param("op", op)
+                    .query_param("flag", "starred")
+                    .query_param("messages", "[42]");
+                then.status(200)
+                    .body(r#"{"result": "success", "msg": "", "messages": [42]}"#);
+            })
+        };
+        let (star_mock, unstar_mock) = (mock("add"), mock("remove"));
+        let mut client = test_client(&server);
+        let (command, format) = parse_command(&["zcli", "star", "42"]);
+        command.run(&mut client, format).await.unwrap();
+        star_mock.assert();
+        unstar_mock.assert_hits(0);
+        let (command, format) = parse_command(&["zcli", "star", "42", "--remove"]);
+        command.run(&mut client, format).await.unwrap();
+        star_mock.assert();
+        unstar_mock.assert();
+    }
+
+    #[tokio::test]
+    async fn test_create_stream() {
+        let server = MockServer::start();
+        let exists_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/get_stream_id")
+                .query_param("stream", "general");
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "stream_id": 1}"#);
+        });
+        let not_found_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/get_stream_id")
+                .query_param("stream", "design");
+            then.status(400).body(
+                r#"{"result": "error", "msg": "Invalid stream name 'design'", "code": "BAD_REQUEST"}"#,
+            );
+        });
+        let create_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/users/me/subscriptions")
+                .body_contains("%22name%22%3A%22design%22")
+                .body_contains("invite_only=true");
+            then.status(200).body(
+                r#"{"result": "success", "msg": "",
+                    "subscribed": {"me@example.com": ["design"]}, "already_subscribed": {}}"#,
+            );
+        });
+        let mut client = test_client(&server);
+        let (command, format) = parse_command(&["zcli", "create-stream", "general"]);
+        let err = command.run(&mut client, format).await.unwrap_err();
+        assert_eq!(err.to_string(), "A stream named general already exists");
+        exists_mock.assert();
+        create_mock.assert_hits(0);
+
+        let (command, format) = parse_command(&[
+            "zcli",
+            "create-stream",
+            "design",
+            "--description",
+            "All about design",
+            "--invite-only",
+        ]);
+        command.run(&mut client, format).await.unwrap();
+        not_found_mock.assert();
+        create_mock.assert();
+    }
+
+    #[tokio::test]
+    async fn test_delete() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(DELETE).path("/api/v1/messages/42");
+            then.status(200).body(r#"{"result": "success", "msg": ""}"#);
+        });
+        let mut client = test_client(&server);
+        let (command, format) = parse_command(&["zcli", "delete", "42"]);
+        command.run(&mut client, format).await.unwrap();
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let server = MockServer::start();
+        let register_mock = server.mock(|when, then| {
+            when.method(POST).path("/api/v1/register").body(
+                "event_types=%5B%22message%22%5D&narrow=%5B%5B%22stream%22%2C%22general%22%5D%5D&\
+                 apply_markdown=true",
+            );
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "queue_id": "q1", "last_event_id": -1}"#);
+        });
+        let events_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/events")
+                .query_param("queue_id", "q1")
+                .query_param("last_event_id", "-1");
+            then.status(200).body(format!(
+                r#"{{"result": "success", "msg": "", "events": [
+                    {{"type": "message", "id": 0, "flags": [], "message": {}}},
+                    {{"type": "heartbeat", "id": 1}}]}}"#,
+                message_json(40, "greetings", "Hello there")
+            ));
+        });
+        // The next poll waits for new events until the watch is stopped.
+        let next_events_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/events")
+                .query_param("last_event_id", "1");
+            then.status(200)
+                .delay(std::time::Duration::from_secs(30))
+                .body(r#"{"result": "success", "msg": "", "events": []}"#);
+        });
+        let delete_mock = server.mock(|when, then| {
+            when.method(DELETE)
+                .path("/api/v1/events")
+                .query_param("queue_id", "q1");
+            then.status(200).body(r#"{"result": "success", "msg": ""}"#);
+        });
+        let client = test_client(&server);
+        let req = RegisterQueueRequest {
+            event_types: vec!["message".to_string()],
+            narrow: vec![("stream".to_string(), "general".to_string())],
+            apply_markdown: true,
+        };
+        let mut out = Vec::new();
+        let shutdown = tokio::time::sleep(std::time::Duration::from_secs(1));
+        watch(
+            &client,
+            &req,
+            false,
+            Format::Text,
+            Palette::PLAIN,
+            &mut out,
+            shutdown,
+        )
+        .await
+        .unwrap();
+        register_mock.assert();
+        events_mock.assert();
+        next_events_mock.assert_hits(1);
+        delete_mock.assert();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("general > greetings: You -- "), "{out}");
+        assert!(out.contains("    Hello there\n"), "{out}");
     }
 }
@@ -0,0 +1,30 @@
+//! Fixtures shared by the tests of the library and the binary.
+use httpmock::MockServer;
+
+use crate::Client;
+
+/// Create a zuliprc for testing based on a mock server.
+pub fn test_rc(server: &MockServer) -> zulib::ZulipRc {
+    zulib::ZulipRc {
+        email: "me@example.com".to_string(),
+        key: "testkey".to_string(),
+        site: server.base_url(),
+    }
+}
+
+/// Create a client for testing based on a mock server.
+pub fn test_client(server: &MockServer) -> Client {
+    Client::new(test_rc(server), &Default::default()).unwrap()
+}
+
+/// A stream in the JSON format of the API.
+pub fn stream_json(id: u64, name: &str) -> String {
+    format!(
+        r#"{{"stream_id": {id}, "name": "{name}", "description": "",
+            "rendered_description": "", "date_created": 1691057093,
+            "invite_only": false, "stream_post_policy": 1,
+            "message_retention_days": null,
+            "history_public_to_subscribers": true, "first_message_id": null,
+            "can_remove_subscribers": null}}"#
+    )
+}
@@ -1,8 +1,21 @@
-use reqwest::{Method, RequestBuilder};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::sync::Mutex;
+
+use chrono::prelude::*;
+use common_cache::CommonCache;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ETAG, IF_NONE_MATCH, USER_AGENT};
+use reqwest::{IntoUrl, Method, RequestBuilder, StatusCode, Url};
 use serde::{de::DeserializeOwned, Deserialize};
 
+use crate::draft::*;
+use crate::emoji::*;
+use crate::event::*;
+use crate::linkifier::*;
 use crate::message::*;
+use crate::rc::{normalize_site, FetchApiKeyResponse};
 use crate::stream::*;
+use crate::user::*;
 use crate::ZulipRc;
 
 /// An error that might occur when making a reqwest to the Zulip server.
@@ -25,6 +38,10 @@ pub enum Error {
         stream: Option<String>,
     },
 
+    /// The request was found to be invalid before it was sent to the server.
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
     /// The parsing of the JSON data in the response body (from the server)
     /// failed.
     #[error("Failed to parse response body")]
@@ -35,6 +52,55 @@ pub enum Error {
     Network(#[from] reqwest::Error),
 }
 
+impl Error {
+    /// Check whether this is an error from the server saying that a requested
+    /// stream does not exist.
+    ///
+    /// Older servers respond with the generic "BAD_REQUEST" code in this case,
+    /// so the message is checked as well for that code.
+    pub fn is_stream_not_found(&self) -> bool {
+        match self {
+            Self::Unsuccessful { code, msg, .. } => {
+                code == "STREAM_DOES_NOT_EXIST"
+                    || code == "BAD_REQUEST"
+                        && (msg.starts_with("Invalid stream name")
+                            || msg.starts_with("Invalid stream ID"))
+            }
+            _ => false,
+        }
+    }
+
+    /// Check whether this is an error from the server saying that read
+    /// receipts are disabled in the organization.
+    ///
+    /// The server uses the generic "BAD_REQUEST" code for this, so the message
+    /// is checked as well.
+    pub fn is_read_receipts_disabled(&self) -> bool {
+        matches!(
+            self,
+            Self::Unsuccessful { code, msg, .. }
+                if code == "BAD_REQUEST" && msg.starts_with("Read receipts are disabled")
+        )
+    }
+
+    /// Check whether this is an error from the server saying that an event
+    /// queue does not exist, for instance because it was garbage collected
+    /// after not being polled for a while.
+    pub fn is_bad_event_queue(&self) -> bool {
+        matches!(self, Self::Unsuccessful { code, .. } if code == "BAD_EVENT_QUEUE_ID")
+    }
+
+    /// Check whether this is an error from the server saying that the user
+    /// lacks the privileges for the request, like when a request requiring an
+    /// organization administrator is made by a member.
+    ///
+    /// This does not hold for the "UNAUTHORIZED" code, which the server sends
+    /// when the credentials are invalid.
+    pub fn is_permission_denied(&self) -> bool {
+        matches!(self, Self::Unsuccessful { code, .. } if code == "UNAUTHORIZED_PRINCIPAL")
+    }
+}
+
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 /// A response from the server in a unified format parameterized by the type of
@@ -65,27 +131,107 @@ impl<T> Response<T> {
 /// Parse a JSON response from the server and convert it to a `Result<T>` where
 /// `T` is the type of the requested data.
 async fn parse_response<T: DeserializeOwned>(response: reqwest::Response) -> Result<T> {
-    let bytes = response.bytes().await?;
+    parse_body(&response.bytes().await?)
+}
+
+/// Parse the JSON body of a response from the server, like `parse_response`.
+fn parse_body<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
     // Uncomment the below line if you want to se the response in the log.
-    log::debug!("Received responce: {}", String::from_utf8_lossy(&bytes));
-    serde_json::from_slice::<Response<T>>(&bytes)?.into_result()
+    log::debug!("Received responce: {}", String::from_utf8_lossy(bytes));
+    serde_json::from_slice::<Response<T>>(bytes)?.into_result()
+}
+
+/// The user agent used by default, "zulib/<version>".
+const DEFAULT_USER_AGENT: &str = concat!("zulib/", env!("CARGO_PKG_VERSION"));
+
+/// The headers sent with every request by default.
+fn default_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(USER_AGENT, HeaderValue::from_static(DEFAULT_USER_AGENT));
+    headers
 }
 
+/// The maximum number of message ids sent in a single request by
+/// `Client::are_messages_read`, since they are sent in the query string.
+const MAX_IDS_PER_REQUEST: usize = 100;
+
+/// The maximum number of responses kept in the ETag cache of a `Client`.
+const ETAG_CACHE_SIZE: usize = 64;
+
 #[derive(Debug)]
 pub struct Client {
     rc: ZulipRc,
     http_client: reqwest::Client,
+    /// Headers sent with every request, including the user agent.
+    default_headers: HeaderMap,
+    /// The custom emoji of the realm, fetched on the first call to
+    /// `Self::get_realm_emoji`.
+    realm_emoji: Mutex<Option<HashMap<String, RealmEmoji>>>,
+    /// The ETag and body of the latest response to each URL requested with
+    /// `Self::send_conditional` for which the server sent an ETag. At most
+    /// `ETAG_CACHE_SIZE` responses are kept, the least used are evicted first.
+    etag_cache: Mutex<CommonCache<String, (HeaderValue, Vec<u8>)>>,
 }
 
 impl Client {
+    /// Create a new client.
+    ///
+    /// Fails if the site in `rc` is not an http(s) URL. A trailing slash in
+    /// the site is removed.
     pub fn new(rc: ZulipRc) -> anyhow::Result<Self> {
         Ok(Self {
-            rc,
+            rc: ZulipRc {
+                site: normalize_site(&rc.site)?,
+                ..rc
+            },
             http_client: reqwest::Client::new(),
+            default_headers: default_headers(),
+            realm_emoji: Mutex::new(None),
+            etag_cache: Mutex::new(CommonCache::new(2, Some(ETAG_CACHE_SIZE))),
         })
     }
 
+    /// Set the user agent sent with every request, instead of the default
+    /// "zulib/<version>".
+    ///
+    /// Fails if `user_agent` is not a valid header value.
+    pub fn with_user_agent(self, user_agent: &str) -> anyhow::Result<Self> {
+        self.with_default_header(USER_AGENT.as_str(), user_agent)
+    }
+
+    /// Add a header which is sent with every request, replacing any previous
+    /// default header with the same name.
+    ///
+    /// Fails if `name` or `value` is not a valid header name or value.
+    pub fn with_default_header(mut self, name: &str, value: &str) -> anyhow::Result<Self> {
+        self.default_headers.insert(
+            HeaderName::from_bytes(name.as_bytes())?,
+            HeaderValue::from_str(value)?,
+        );
+        Ok(self)
+    }
+
+    /// Fetch the API key of a user by logging in with a username (usually the
+    /// email) and a password.
+    ///
+    /// This doesn't require a `ZulipRc`, so it can be used to create one when
+    /// setting up a new account. The site is checked like in `Self::new`, and
+    /// the request is sent with the default user agent.
+    pub async fn fetch_api_key(site: &str, username: &str, password: &str) -> Result<String> {
+        let site = normalize_site(site).map_err(|e| Error::InvalidRequest(format!("{:#}", e)))?;
+        let response = reqwest::Client::new()
+            .post(format!("{}/api/v1/fetch_api_key", site))
+            .headers(default_headers())
+            .form(&[("username", username), ("password", password)])
+            .send()
+            .await?;
+        parse_response::<FetchApiKeyResponse>(response)
+            .await
+            .map(|x| x.api_key)
+    }
+
     pub async fn send_message(&self, req: SendMessageRequest) -> Result<SendMessageResponse> {
+        req.validate()?;
         let response = self
             .http_client(Method::POST, "/api/v1/messages")
             .form(&req)
@@ -93,13 +239,137 @@ impl Client {
             .await?;
         parse_response(response).await
     }
-    pub async fn get_messages(&self, req: GetMessagesRequest) -> Result<GetMessagesResponse> {
+
+    /// Send a message and then fetch it with `Self::get_message`, to get the
+    /// message as it was received by the server, with rendered content.
+    ///
+    /// If the message was sent but could not be fetched, an error is returned
+    /// although the message is not sent again.
+    pub async fn send_message_and_fetch(&self, req: SendMessageRequest) -> Result<ReceivedMessage> {
+        let id = self.send_message(req).await?.id;
+        self.get_message(id).await
+    }
+
+    /// Send a message with a `queue_id` and a `local_id`, which the server
+    /// includes in the message event delivered to the event queue `queue_id`.
+    ///
+    /// This lets a client with that event queue recognize the event of a
+    /// message it sent, for instance to replace a local echo of the message.
+    /// The server does not deduplicate messages by these ids, so sending the
+    /// same request twice sends two messages.
+    pub async fn send_message_with_local_id(
+        &self,
+        req: SendMessageRequest,
+        queue_id: &str,
+        local_id: &str,
+    ) -> Result<SendMessageResponse> {
+        req.validate()?;
+        let response = self
+            .http_client(Method::POST, "/api/v1/messages")
+            .form(&SendMessageWithLocalIdRequest {
+                message: &req,
+                queue_id,
+                local_id,
+            })
+            .send()
+            .await?;
+        parse_response(response).await
+    }
+
+    /// Schedule a message to be sent at a specific time.
+    ///
+    /// Returns the id of the scheduled message.
+    pub async fn create_scheduled_message(
+        &self,
+        req: SendMessageRequest,
+        deliver_at: DateTime<Utc>,
+    ) -> Result<u64> {
+        req.validate()?;
+        let response = self
+            .http_client(Method::POST, "/api/v1/scheduled_messages")
+            .form(&CreateScheduledMessageRequest {
+                message: &req,
+                scheduled_delivery_timestamp: deliver_at,
+            })
+            .send()
+            .await?;
+        parse_response::<CreateScheduledMessageResponse>(response)
+            .await
+            .map(|x| x.scheduled_message_id)
+    }
+
+    /// Get all messages scheduled by the user which have not yet been sent.
+    pub async fn get_scheduled_messages(&self) -> Result<Vec<ScheduledMessage>> {
+        let response = self
+            .http_client(Method::GET, "/api/v1/scheduled_messages")
+            .send()
+            .await?;
+        parse_response::<GetScheduledMessagesResponse>(response)
+            .await
+            .map(|x| x.scheduled_messages)
+    }
+
+    /// Delete a scheduled message by id, so that it will not be sent.
+    pub async fn delete_scheduled_message(&self, id: u64) -> Result<()> {
         let response = self
+            .http_client(Method::DELETE, &format!("/api/v1/scheduled_messages/{id}"))
+            .send()
+            .await?;
+        parse_response(response).await
+    }
+
+    /// Get messages in a range.
+    ///
+    /// If more than `MAX_MESSAGES_PER_REQUEST` messages are requested, the
+    /// range is clamped (see `MessageRange::clamp`) and a warning is logged.
+    pub async fn get_messages(&self, req: GetMessagesRequest) -> Result<GetMessagesResponse> {
+        parse_response(self.send_get_messages(req).await?).await
+    }
+
+    /// Get messages like `Self::get_messages`, but parse them one at a time
+    /// while the response is downloaded instead of keeping the whole response
+    /// in memory.
+    ///
+    /// Only the messages are yielded, the other fields of `GetMessagesResponse`
+    /// are skipped. Must be called within a Tokio runtime.
+    pub async fn get_messages_stream(&self, req: GetMessagesRequest) -> Result<MessageStream> {
+        Ok(MessageStream::new(self.send_get_messages(req).await?))
+    }
+
+    /// Send a get_messages request, with the range clamped to
+    /// `MAX_MESSAGES_PER_REQUEST`.
+    async fn send_get_messages(&self, mut req: GetMessagesRequest) -> Result<reqwest::Response> {
+        let (num_before, num_after) = (req.range.num_before, req.range.num_after);
+        if req.range.clamp() {
+            log::warn!(
+                "Requested {} messages before and {} after the anchor, but at most {} can be \
+                 fetched at once. Clamped to {} before and {} after.",
+                num_before,
+                num_after,
+                MAX_MESSAGES_PER_REQUEST,
+                req.range.num_before,
+                req.range.num_after
+            );
+        }
+        Ok(self
             .http_client(Method::GET, "/api/v1/messages")
             .query(&req)
             .send()
+            .await?)
+    }
+
+    /// Render a message from markdown to HTML without sending it.
+    ///
+    /// This is useful to preview how a message will look like.
+    pub async fn render_message(&self, content: &str) -> Result<String> {
+        let response = self
+            .http_client(Method::POST, "/api/v1/messages/render")
+            .form(&[("content", content)])
+            .send()
             .await?;
-        parse_response(response).await
+        parse_response::<RenderMessageResponse>(response)
+            .await
+            .map(|x| x.rendered)
     }
 
     /// Add or remove personal message flags like read and starred on a list of
@@ -160,6 +430,29 @@ impl Client {
         parse_response(response).await
     }
 
+    /// Mark a list of messages as read, for instance after displaying them.
+    pub async fn mark_messages_as_read(&self, ids: &[u64]) -> Result<UpdateMessageFlagsResponse> {
+        self.update_message_flags(&UpdateMessageFlagsRequest::new(
+            UpdateFlag::new(FlagOperation::Add, EditableFlag::Read),
+            ids.to_vec(),
+        ))
+        .await
+    }
+
+    /// Star or unstar a list of messages.
+    pub async fn set_star(&self, ids: &[u64], starred: bool) -> Result<UpdateMessageFlagsResponse> {
+        let operation = if starred {
+            FlagOperation::Add
+        } else {
+            FlagOperation::Remove
+        };
+        self.update_message_flags(&UpdateMessageFlagsRequest::new(
+            UpdateFlag::new(operation, EditableFlag::Starred),
+            ids.to_vec(),
+        ))
+        .await
+    }
+
     pub async fn delete_message(&self, id: i64) -> Result<()> {
         let response = self
             .http_client(Method::DELETE, &format!("/api/v1/messages/{}", id))
@@ -167,6 +460,90 @@ impl Client {
             .await?;
         parse_response(response).await
     }
+
+    /// Get a single message by its id.
+    pub async fn get_message(&self, id: u64) -> Result<ReceivedMessage> {
+        let response = self
+            .http_client(Method::GET, &format!("/api/v1/messages/{}", id))
+            .send()
+            .await?;
+        Ok(parse_response::<GetMessageResponse>(response)
+            .await?
+            .message)
+    }
+
+    /// Get the raw Markdown content of a message, as it was written by the
+    /// sender.
+    pub async fn get_message_raw_content(&self, id: u64) -> Result<String> {
+        let response = self
+            .http_client(Method::GET, &format!("/api/v1/messages/{}", id))
+            .query(&[("apply_markdown", "false")])
+            .send()
+            .await?;
+        let response = parse_response::<GetMessageResponse>(response).await?;
+        // Older servers only send the Markdown in `raw_content`, newer servers
+        // put it in the content of the message since `apply_markdown` is false.
+        Ok(response.raw_content.unwrap_or(response.message.content))
+    }
+
+    /// Get the edit history of a message.
+    ///
+    /// Unlike `ReceivedMessage::edit_history`, this is available regardless of
+    /// how the message was fetched. The first item is the original message and
+    /// the rest are the edits, from the oldest to the newest.
+    pub async fn get_message_history(&self, id: u64) -> Result<Vec<EditHistory>> {
+        let response = self
+            .http_client(Method::GET, &format!("/api/v1/messages/{}/history", id))
+            .send()
+            .await?;
+        Ok(parse_response::<GetMessageHistoryResponse>(response)
+            .await?
+            .message_history)
+    }
+
+    /// Get the IDs of the users who have read a message.
+    ///
+    /// If read receipts are disabled in the organization an error is
+    /// returned, for which `Error::is_read_receipts_disabled` holds.
+    pub async fn get_read_receipts(&self, message_id: u64) -> Result<Vec<u64>> {
+        let response = self
+            .http_client(
+                Method::GET,
+                &format!("/api/v1/messages/{}/read_receipts", message_id),
+            )
+            .send()
+            .await?;
+        Ok(parse_response::<GetReadReceiptsResponse>(response)
+            .await?
+            .user_ids)
+    }
+
+    /// Check whether the user has read each of the messages in `ids`.
+    ///
+    /// Zulip has no endpoint for reading back message flags, so the messages
+    /// are fetched by their ids with `GetMessagesRequest::message_ids`, up to
+    /// `MAX_IDS_PER_REQUEST` messages per request. An error is returned if
+    /// some message does not exist or is not accessible.
+    ///
+    /// This requires Zulip 10.0 (feature level 300) or later. Older servers
+    /// don't support `message_ids`, so the request fails on them.
+    pub async fn are_messages_read(&self, ids: &[u64]) -> Result<Vec<bool>> {
+        let mut read = HashMap::with_capacity(ids.len());
+        for chunk in ids.chunks(MAX_IDS_PER_REQUEST) {
+            let response = self
+                .get_messages(GetMessagesRequest::with_ids(chunk.to_vec()))
+                .await?;
+            read.extend(response.messages.iter().map(|x| (x.id, x.is_read())));
+        }
+        ids.iter()
+            .map(|id| {
+                read.get(id)
+                    .copied()
+                    .ok_or_else(|| Error::InvalidRequest(format!("Message {} not found", id)))
+            })
+            .collect()
+    }
+
     pub async fn edit_message(&self, req: EditMessageRequest) -> Result<()> {
         let response = self
             .http_client(
@@ -178,6 +555,59 @@ impl Client {
             .await?;
         parse_response(response).await
     }
+
+    /// Move a whole topic to another stream, rename it, or both.
+    ///
+    /// This finds the first message of the topic and edits it with
+    /// `PropagateMode::ChangeLater`, so that all messages in the topic are
+    /// moved. At least one of `new_stream_id` and `new_topic` must be given.
+    pub async fn move_topic(
+        &self,
+        stream_id: u64,
+        topic: &str,
+        new_stream_id: Option<u64>,
+        new_topic: Option<&str>,
+    ) -> Result<()> {
+        if new_stream_id.is_none() && new_topic.is_none() {
+            return Err(Error::InvalidRequest(
+                "Neither a new stream nor a new topic was given".to_string(),
+            ));
+        }
+        let to_i64 = |id: u64| {
+            i64::try_from(id).map_err(|_| Error::InvalidRequest(format!("Too large id: {}", id)))
+        };
+        let mut range = MessageRange::new(0, 1);
+        range.anchor(Anchor::Oldest).narrow(vec![
+            Narrow {
+                operator: "stream".to_string(),
+                operand: stream_id.to_string(),
+                negated: false,
+            },
+            Narrow {
+                operator: "topic".to_string(),
+                operand: topic.to_string(),
+                negated: false,
+            },
+        ]);
+        let first_message = self
+            .get_messages(GetMessagesRequest::new(range))
+            .await?
+            .messages
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                Error::InvalidRequest(format!("There are no messages in the topic {}", topic))
+            })?;
+        let mut req = EditMessageRequest::new(to_i64(first_message.id)?);
+        req.propagate_mode(PropagateMode::ChangeLater);
+        if let Some(new_stream_id) = new_stream_id {
+            req.stream_id(to_i64(new_stream_id)?);
+        }
+        if let Some(new_topic) = new_topic {
+            req.topic(new_topic);
+        }
+        self.edit_message(req).await
+    }
     pub async fn add_emoji_reaction(&self, req: AddEmojiReactionRequest) -> Result<()> {
         let response = self
             .http_client(
@@ -201,10 +631,184 @@ impl Client {
         parse_response(response).await
     }
 
+    /// Get all custom emoji in the realm, with the emoji ids as keys.
+    ///
+    /// The emoji are only fetched from the server on the first call, later
+    /// calls will use a cached copy. Use `Self::clear_realm_emoji_cache` to
+    /// fetch them again.
+    pub async fn get_realm_emoji(&self) -> Result<HashMap<String, RealmEmoji>> {
+        // Clone the cached emoji in a separate statement so that the lock isn't held
+        // across the await below.
+        let cached = self.realm_emoji.lock().unwrap().clone();
+        if let Some(emoji) = cached {
+            return Ok(emoji);
+        }
+        let response = self
+            .http_client(Method::GET, "/api/v1/realm/emoji")
+            .send()
+            .await?;
+        let emoji = parse_response::<GetRealmEmojiResponse>(response)
+            .await?
+            .emoji;
+        *self.realm_emoji.lock().unwrap() = Some(emoji.clone());
+        Ok(emoji)
+    }
+
+    /// Clear the cache of custom emoji, so that they are fetched from the server
+    /// on the next call to `Self::get_realm_emoji`.
+    pub fn clear_realm_emoji_cache(&self) {
+        *self.realm_emoji.lock().unwrap() = None;
+    }
+
+    /// Get all linkifiers of the realm.
+    pub async fn get_linkifiers(&self) -> Result<Vec<Linkifier>> {
+        let response = self
+            .http_client(Method::GET, "/api/v1/realm/linkifiers")
+            .send()
+            .await?;
+        parse_response::<GetLinkifiersResponse>(response)
+            .await
+            .map(|x| x.linkifiers)
+    }
+
+    /// Get all users and bots in the realm, including deactivated users.
+    pub async fn get_users(&self) -> Result<Vec<User>> {
+        let response = self
+            .http_client(Method::GET, "/api/v1/users")
+            .send()
+            .await?;
+        parse_response::<GetUsersResponse>(response)
+            .await
+            .map(|x| x.members)
+    }
+
+    /// Get a user by their Zulip API email address, for instance to get the ID
+    /// needed to send them a private message.
+    pub async fn get_user_by_email(&self, email: &str) -> Result<User> {
+        // The site is checked to be an http(s) URL in `Self::new`, so it can be
+        // parsed and used as a base.
+        let mut url = Url::parse(&format!("{}/api/v1/users", self.rc.site)).unwrap();
+        // Percent-encode the email as a single path segment.
+        url.path_segments_mut().unwrap().push(email);
+        let response = self.request(Method::GET, url).send().await?;
+        parse_response::<GetUserResponse>(response)
+            .await
+            .map(|x| x.user)
+    }
+
+    /// Update the status of the current user.
+    ///
+    /// Only the fields of `status` which are not `None` are changed, and it is
+    /// an error if all of them are `None`.
+    pub async fn update_status(&self, status: UserStatus) -> Result<()> {
+        if status == UserStatus::default() {
+            return Err(Error::InvalidRequest(
+                "The status update must change at least one field".to_string(),
+            ));
+        }
+        let response = self
+            .http_client(Method::POST, "/api/v1/users/me/status")
+            .form(&status)
+            .send()
+            .await?;
+        parse_response(response).await
+    }
+
+    /// Get all user groups of the realm.
+    pub async fn get_user_groups(&self) -> Result<Vec<UserGroup>> {
+        let response = self
+            .http_client(Method::GET, "/api/v1/user_groups")
+            .send()
+            .await?;
+        parse_response::<GetUserGroupsResponse>(response)
+            .await
+            .map(|x| x.user_groups)
+    }
+
+    /// Register an event queue.
+    ///
+    /// Events are then fetched with `Self::get_events`, and the queue should be
+    /// deleted with `Self::delete_queue` when it is no longer needed.
+    pub async fn register_queue(
+        &self,
+        req: &RegisterQueueRequest,
+    ) -> Result<RegisterQueueResponse> {
+        let response = self
+            .http_client(Method::POST, "/api/v1/register")
+            .form(req)
+            .send()
+            .await?;
+        parse_response(response).await
+    }
+
+    /// Get the events after `last_event_id` from an event queue.
+    ///
+    /// If there are no such events, the server waits until there are some, or
+    /// responds with a heartbeat event after about a minute.
+    pub async fn get_events(&self, queue_id: &str, last_event_id: i64) -> Result<Vec<Event>> {
+        let response = self
+            .http_client(Method::GET, "/api/v1/events")
+            .query(&[
+                ("queue_id", queue_id),
+                ("last_event_id", &last_event_id.to_string()),
+            ])
+            .send()
+            .await?;
+        parse_response::<GetEventsResponse>(response)
+            .await
+            .map(|x| x.events)
+    }
+
+    /// Delete an event queue.
+    pub async fn delete_queue(&self, queue_id: &str) -> Result<()> {
+        let response = self
+            .http_client(Method::DELETE, "/api/v1/events")
+            .query(&[("queue_id", queue_id)])
+            .send()
+            .await?;
+        parse_response(response).await
+    }
+
+    /// Deactivate a user, which logs them out and prevents them from logging
+    /// in.
+    ///
+    /// Requires organization administrator privileges, otherwise an error is
+    /// returned for which `Error::is_permission_denied` holds.
+    pub async fn deactivate_user(&self, user_id: u64) -> Result<()> {
+        let response = self
+            .http_client(Method::DELETE, &format!("/api/v1/users/{}", user_id))
+            .send()
+            .await?;
+        parse_response(response).await
+    }
+
+    /// Reactivate a deactivated user.
+    ///
+    /// Requires organization administrator privileges, otherwise an error is
+    /// returned for which `Error::is_permission_denied` holds.
+    pub async fn reactivate_user(&self, user_id: u64) -> Result<()> {
+        let response = self
+            .http_client(
+                Method::POST,
+                &format!("/api/v1/users/{}/reactivate", user_id),
+            )
+            .send()
+            .await?;
+        parse_response(response).await
+    }
+
     /// Get information about all streams that the user is subscribed to.
     pub async fn get_subscribed_streams(&self) -> Result<Vec<Subscription>> {
+        self.get_subscriptions(false).await
+    }
+
+    /// Get information about all streams that the user is subscribed to,
+    /// optionally with the ids of all subscribers of each stream in
+    /// `Subscription::subscribers`.
+    pub async fn get_subscriptions(&self, include_subscribers: bool) -> Result<Vec<Subscription>> {
         let response = self
             .http_client(Method::GET, "/api/v1/users/me/subscriptions")
+            .query(&[("include_subscribers", include_subscribers)])
             .send()
             .await?;
         parse_response::<GetSubscribedStreamsResponse>(response)
@@ -212,6 +816,52 @@ impl Client {
             .map(|x| x.subscriptions)
     }
 
+    /// Create a stream and subscribe the user to it.
+    ///
+    /// If a stream named `name` already exists, the user is only subscribed to
+    /// it and `description` and `invite_only` are ignored. If the user was
+    /// already subscribed, that is indicated by
+    /// `SubscribeResponse::already_subscribed`.
+    pub async fn create_stream(
+        &self,
+        name: &str,
+        description: &str,
+        invite_only: bool,
+    ) -> Result<SubscribeResponse> {
+        let response = self
+            .http_client(Method::POST, "/api/v1/users/me/subscriptions")
+            .form(&SubscribeRequest {
+                subscriptions: &[NewSubscription { name, description }],
+                invite_only,
+            })
+            .send()
+            .await?;
+        parse_response(response).await
+    }
+
+    /// Change the settings of the subscription to a stream.
+    ///
+    /// Only the settings which are set in `changes` are updated. Returns
+    /// `Error::InvalidRequest` if no settings are set.
+    pub async fn update_subscription_settings(
+        &self,
+        stream_id: u64,
+        changes: SubscriptionSettings,
+    ) -> Result<()> {
+        let subscription_data = changes.to_properties(stream_id);
+        if subscription_data.is_empty() {
+            return Err(Error::InvalidRequest(
+                "No subscription settings to update".to_string(),
+            ));
+        }
+        let response = self
+            .http_client(Method::POST, "/api/v1/users/me/subscriptions/properties")
+            .form(&UpdateSubscriptionSettingsRequest { subscription_data })
+            .send()
+            .await?;
+        parse_response(response).await
+    }
+
     /// Get a list of streams based on some options.
     pub async fn get_streams(&self, req: &GetStreamsRequest) -> Result<Vec<Stream>> {
         let response = self
@@ -224,15 +874,84 @@ impl Client {
             .map(|x| x.streams)
     }
 
+    /// Get a list of streams like `Self::get_streams`, each paired with whether
+    /// the user is subscribed to it.
+    pub async fn get_streams_with_subscription(
+        &self,
+        req: &GetStreamsRequest,
+    ) -> Result<Vec<(Stream, bool)>> {
+        let subscribed: HashSet<u64> = self
+            .get_subscribed_streams()
+            .await?
+            .into_iter()
+            .map(|x| x.stream.stream_id)
+            .collect();
+        Ok(self
+            .get_streams(req)
+            .await?
+            .into_iter()
+            .map(|stream| {
+                let is_subscribed = subscribed.contains(&stream.stream_id);
+                (stream, is_subscribed)
+            })
+            .collect())
+    }
+
     /// Get all the topics in a specific stream
+    ///
+    /// The response is cached, and if the server supports ETags the topics are
+    /// only downloaded again if they have changed.
     pub async fn get_topics_in_stream(&self, stream_id: u64) -> Result<Vec<Topic>> {
-        let response = self
-            .http_client(Method::GET, &format!("/api/v1/users/me/{stream_id}/topics"))
-            .send()
-            .await?;
-        parse_response::<TopicsInStreamResponse>(response)
-            .await
-            .map(|x| x.topics)
+        self.send_conditional::<TopicsInStreamResponse>(
+            self.http_client(Method::GET, &format!("/api/v1/users/me/{stream_id}/topics")),
+        )
+        .await
+        .map(|x| x.topics)
+    }
+
+    /// Get the topics in a stream sorted by their last message, with the most
+    /// recently active topic first.
+    ///
+    /// If `limit` is given, only the `limit` most recent topics are returned.
+    pub async fn get_stream_topics(
+        &self,
+        stream_id: u64,
+        limit: Option<usize>,
+    ) -> Result<Vec<Topic>> {
+        let mut topics = self.get_topics_in_stream(stream_id).await?;
+        topics.sort_by(Topic::cmp_by_recency);
+        topics.truncate(limit.unwrap_or(usize::MAX));
+        Ok(topics)
+    }
+
+    /// Get the topics in a stream together with the time of their last
+    /// message, with the most recent topic first.
+    ///
+    /// Unlike `Self::get_stream_topics`, this needs one request per topic to
+    /// fetch the last message, so `limit` can be used to only get the `limit`
+    /// most recent topics. Topics whose last message can't be fetched, for
+    /// instance because it was just deleted, are left out.
+    pub async fn get_topics_with_times(
+        &self,
+        stream_id: u64,
+        limit: Option<usize>,
+    ) -> Result<Vec<(Topic, DateTime<Utc>)>> {
+        let topics = self.get_stream_topics(stream_id, limit).await?;
+        let mut topics_with_times = Vec::with_capacity(topics.len());
+        for topic in topics {
+            let mut range = MessageRange::new(0, 0);
+            range.anchor(Anchor::MessageId(topic.max_id));
+            let last_message = self
+                .get_messages(GetMessagesRequest::new(range))
+                .await?
+                .messages
+                .into_iter()
+                .find(|x| x.id == topic.max_id);
+            if let Some(message) = last_message {
+                topics_with_times.push((topic, message.timestamp));
+            }
+        }
+        Ok(topics_with_times)
     }
 
     /// Get the unique ID of a given stream.
@@ -247,6 +966,19 @@ impl Client {
             .map(|x| x.stream_id)
     }
 
+    /// Get the unique ID of a given stream, or `None` if there is no stream
+    /// with that name.
+    ///
+    /// Unlike `Self::get_stream_id`, a non-existing stream is not considered
+    /// an error, but all other errors are propagated.
+    pub async fn try_get_stream_id(&self, stream_name: &str) -> Result<Option<u64>> {
+        match self.get_stream_id(stream_name).await {
+            Ok(id) => Ok(Some(id)),
+            Err(e) if e.is_stream_not_found() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
     /// Get a stream by id.
     pub async fn get_stream_by_id(&self, id: u64) -> Result<Stream> {
         let response = self
@@ -258,21 +990,134 @@ impl Client {
             .map(|x| x.stream)
     }
 
-    fn http_client(&self, method: Method, endpoint: &str) -> RequestBuilder {
-        let url = format!("{}{}", &self.rc.site, endpoint);
-        self.http_client
-            .request(method, url)
-            .basic_auth(&self.rc.email, Some(&self.rc.key))
-            .header("application", "x-www-form-urlencoded")
+    /// Get a stream by name.
+    ///
+    /// This first looks up the id of the stream and then fetches the stream by
+    /// that id. If there is no stream with the name, the error from the server
+    /// is returned, for which `Error::is_stream_not_found` holds.
+    pub async fn get_stream_by_name(&self, name: &str) -> Result<Stream> {
+        let id = self.get_stream_id(name).await?;
+        self.get_stream_by_id(id).await
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::net::SocketAddr;
+    /// Search for streams whose names contain `query`, ignoring case.
+    ///
+    /// A stream named exactly `query` is returned first, the other streams are
+    /// kept in the order they were returned by the server.
+    ///
+    /// The API cannot search streams, so this falls back to fetching all
+    /// streams with `Self::get_streams` and filtering them locally, which may
+    /// be slow in large organizations. Use `Self::try_get_stream_id` to only
+    /// look up a stream by its exact name.
+    pub async fn search_streams(&self, query: &str) -> Result<Vec<Stream>> {
+        let lowercase_query = query.to_lowercase();
+        let mut streams = self.get_streams(&GetStreamsRequest::default()).await?;
+        streams.retain(|stream| stream.name.to_lowercase().contains(&lowercase_query));
+        // The sort is stable, so the order of the other streams is kept.
+        streams.sort_by_key(|stream| stream.name != query);
+        Ok(streams)
+    }
 
-    use httpmock::{
-        Method::{DELETE, GET, POST},
+    /// Get all drafts of the user.
+    pub async fn get_drafts(&self) -> Result<Vec<Draft>> {
+        let response = self
+            .http_client(Method::GET, "/api/v1/drafts")
+            .send()
+            .await?;
+        parse_response::<GetDraftsResponse>(response)
+            .await
+            .map(|x| x.drafts)
+    }
+
+    /// Create one or more drafts on the server.
+    ///
+    /// Returns the IDs of the newly created drafts in the same order as they
+    /// were given.
+    pub async fn create_drafts(&self, drafts: &[Draft]) -> Result<Vec<u64>> {
+        let response = self
+            .http_client(Method::POST, "/api/v1/drafts")
+            .form(&CreateDraftsRequest { drafts })
+            .send()
+            .await?;
+        parse_response::<CreateDraftsResponse>(response)
+            .await
+            .map(|x| x.ids)
+    }
+
+    /// Delete a draft by id.
+    pub async fn delete_draft(&self, id: u64) -> Result<()> {
+        let response = self
+            .http_client(Method::DELETE, &format!("/api/v1/drafts/{id}"))
+            .send()
+            .await?;
+        parse_response(response).await
+    }
+
+    fn http_client(&self, method: Method, endpoint: &str) -> RequestBuilder {
+        self.request(method, format!("{}{}", &self.rc.site, endpoint))
+    }
+
+    /// Like `Self::http_client`, but with a full URL instead of an endpoint.
+    fn request(&self, method: Method, url: impl IntoUrl) -> RequestBuilder {
+        self.http_client
+            .request(method, url)
+            .headers(self.default_headers.clone())
+            .basic_auth(&self.rc.email, Some(&self.rc.key))
+        // The Content-Type header is set by `RequestBuilder::form` for requests
+        // with a body.
+    }
+
+    /// Send a request and parse the response like `parse_response`, but with
+    /// a conditional request if possible.
+    ///
+    /// If an earlier response to the same URL had an ETag, it is sent in an
+    /// If-None-Match header, and if the server responds with 304 Not Modified
+    /// the body of the earlier response is parsed instead. This should only be
+    /// used for GET requests, and since whole bodies are kept in the cache it is
+    /// only used for endpoints with large responses that rarely change,
+    /// currently `Self::get_topics_in_stream`.
+    async fn send_conditional<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        let mut request = request.build()?;
+        let url = request.url().to_string();
+        let cached = self
+            .etag_cache
+            .lock()
+            .unwrap()
+            .entry(url.as_str())
+            .map(|mut x| x.get_value().clone());
+        if let Some((etag, _)) = &cached {
+            request.headers_mut().insert(IF_NONE_MATCH, etag.clone());
+        }
+        let response = self.http_client.execute(request).await?;
+        if let Some((_, body)) = cached {
+            if response.status() == StatusCode::NOT_MODIFIED {
+                log::debug!("Reusing the cached response for {url}");
+                return parse_body(&body);
+            }
+        }
+        let etag = response.headers().get(ETAG).cloned();
+        let body = response.bytes().await?.to_vec();
+        let result = parse_body(&body);
+        let mut etag_cache = self.etag_cache.lock().unwrap();
+        match etag {
+            Some(etag) if result.is_ok() => {
+                etag_cache.insert(url, (etag, body));
+            }
+            _ => {
+                etag_cache.take(url.as_str());
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use chrono::prelude::*;
+    use httpmock::{
+        Method::{DELETE, GET, PATCH, POST},
         MockServer,
     };
 
@@ -289,6 +1134,35 @@ mod tests {
         .unwrap()
     }
     #[tokio::test]
+    async fn test_fetch_api_key() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/fetch_api_key")
+                .header("User-Agent", DEFAULT_USER_AGENT)
+                .body(form_encode(&[
+                    ("username", "me@example.com"),
+                    ("password", "secret"),
+                ]));
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "api_key": "abc123",
+                    "email": "me@example.com", "user_id": 5}"#,
+            );
+        });
+        let site = format!("{}/", server.base_url());
+        let key = Client::fetch_api_key(&site, "me@example.com", "secret")
+            .await
+            .unwrap();
+        mock.assert();
+        assert_eq!(key, "abc123");
+
+        let err = Client::fetch_api_key("ftp://example.com", "me@example.com", "secret")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidRequest(_)), "{:?}", err);
+        mock.assert_hits(1);
+    }
+    #[tokio::test]
     async fn test_send_private_message() {
         let server = MockServer::start();
         let mock = server.mock(|when, then| {
@@ -324,6 +1198,126 @@ mod tests {
         assert!(result.is_ok());
     }
     #[tokio::test]
+    async fn test_send_message_and_fetch() {
+        let server = MockServer::start();
+        let send_mock = server.mock(|when, then| {
+            when.method(POST).path("/api/v1/messages");
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "id": 40}"#);
+        });
+        let get_mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/messages/40");
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "raw_content": "**Hi**",
+                    "message": {"id": 40, "timestamp": 1691057093,
+                    "content": "<p><strong>Hi</strong></p>", "content_type": "text/html",
+                    "avatar_url": null, "client": "website", "display_recipient": "general",
+                    "is_me_message": false, "reactions": [], "recipient_id": 10,
+                    "sender_email": "you@example.com", "sender_full_name": "You",
+                    "sender_id": 3, "sender_realm_str": "example", "stream_id": 3,
+                    "subject": "greetings", "type": "stream", "flags": ["read"]}}"#,
+            );
+        });
+        let client = test_client(server.address());
+        let req = SendMessageRequest::Stream {
+            to: Identifier::Name("general".to_string()),
+            topic: "greetings".to_string(),
+            content: "**Hi**".to_string(),
+        };
+        let message = client.send_message_and_fetch(req).await.unwrap();
+        send_mock.assert();
+        get_mock.assert();
+        assert_eq!(message.id, 40);
+        assert_eq!(message.content, "<p><strong>Hi</strong></p>");
+    }
+    #[tokio::test]
+    async fn test_get_message_raw_content() {
+        let server = MockServer::start();
+        let message = |id: u64, content: &str| {
+            format!(
+                r#"{{"id": {id}, "timestamp": 1691057093, "content": "{content}",
+                    "content_type": "text/x-markdown", "avatar_url": null,
+                    "client": "website", "display_recipient": "general",
+                    "is_me_message": false, "reactions": [], "recipient_id": 10,
+                    "sender_email": "you@example.com", "sender_full_name": "You",
+                    "sender_id": 3, "sender_realm_str": "example", "stream_id": 3,
+                    "subject": "greetings", "type": "stream", "flags": ["read"]}}"#
+            )
+        };
+        let old_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/messages/40")
+                .query_param("apply_markdown", "false");
+            then.status(200).body(format!(
+                r#"{{"result": "success", "msg": "", "raw_content": "**Hi**",
+                    "message": {}}}"#,
+                message(40, "<p><strong>Hi</strong></p>")
+            ));
+        });
+        let new_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/messages/41")
+                .query_param("apply_markdown", "false");
+            then.status(200).body(format!(
+                r#"{{"result": "success", "msg": "", "message": {}}}"#,
+                message(41, "*Bye*")
+            ));
+        });
+        let client = test_client(server.address());
+        assert_eq!(client.get_message_raw_content(40).await.unwrap(), "**Hi**");
+        assert_eq!(client.get_message_raw_content(41).await.unwrap(), "*Bye*");
+        old_mock.assert();
+        new_mock.assert();
+    }
+    #[tokio::test]
+    async fn test_send_message_with_local_id() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/messages")
+                .body(form_encode(&[
+                    ("type", "stream"),
+                    ("to", "general"),
+                    ("topic", "test"),
+                    ("content", "abc"),
+                    ("queue_id", "1375801870:2942"),
+                    ("local_id", "42.01"),
+                ]));
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "id": 123}"#);
+        });
+        let client = test_client(server.address());
+        let req = SendMessageRequest::Stream {
+            to: Identifier::Name("general".to_string()),
+            topic: "test".to_string(),
+            content: "abc".to_string(),
+        };
+        let response = client
+            .send_message_with_local_id(req, "1375801870:2942", "42.01")
+            .await
+            .unwrap();
+        mock.assert();
+        assert_eq!(response.id, 123);
+    }
+    #[tokio::test]
+    async fn test_send_stream_message_with_empty_topic() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST).path("/api/v1/messages");
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "id": 123}"#);
+        });
+        let client = test_client(server.address());
+        let req = SendMessageRequest::Stream {
+            to: Identifier::Name("[8]".to_string()),
+            topic: " ".to_string(),
+            content: "abc".to_string(),
+        };
+        let result = client.send_message(req).await;
+        mock.assert_hits(0);
+        assert!(matches!(result, Err(Error::InvalidRequest(_))));
+    }
+    #[tokio::test]
     async fn test_get_messages() {
         let server = MockServer::start();
         let mock = server.mock(|when, then| {
@@ -339,6 +1333,168 @@ mod tests {
         assert!(result.is_ok());
     }
     #[tokio::test]
+    async fn test_get_messages_clamped() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/messages")
+                .query_param("num_before", MAX_MESSAGES_PER_REQUEST.to_string())
+                .query_param("num_after", "0");
+            then.status(200).body(message_template());
+        });
+        let client = test_client(server.address());
+        let req = GetMessagesRequest::new(MessageRange::new(MAX_MESSAGES_PER_REQUEST + 1000, 10));
+        client.get_messages(req).await.unwrap();
+        mock.assert();
+
+        let mut range = MessageRange::new(3000, 3000);
+        assert!(range.clamp());
+        assert_eq!((range.num_before, range.num_after), (3000, 2000));
+        assert!(!range.clamp());
+    }
+    #[tokio::test]
+    async fn test_get_messages_stream() {
+        const COUNT: u64 = 5000;
+        let messages: Vec<String> = (0..COUNT)
+            .map(|id| {
+                format!(
+                    r#"{{"id": {}, "timestamp": 1691057093, "content": "Message {}",
+                    "content_type": "text/x-markdown", "avatar_url": null,
+                    "client": "website", "display_recipient": "general",
+                    "is_me_message": false, "reactions": [], "recipient_id": 10,
+                    "sender_email": "you@example.com", "sender_full_name": "You",
+                    "sender_id": 3, "sender_realm_str": "example", "stream_id": 3,
+                    "subject": "greetings", "type": "stream", "flags": []}}"#,
+                    id, id
+                )
+            })
+            .collect();
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/messages");
+            then.status(200).body(format!(
+                r#"{{"result": "success", "msg": "", "anchor": 0, "messages": [{}],
+                    "found_newest": true, "found_anchor": false}}"#,
+                messages.join(",")
+            ));
+        });
+        let client = test_client(server.address());
+        let req = GetMessagesRequest::new(MessageRange::new(0, MAX_MESSAGES_PER_REQUEST));
+        let mut stream = client.get_messages_stream(req).await.unwrap();
+        let mut ids = Vec::new();
+        while let Some(message) = stream.next().await {
+            ids.push(message.unwrap().id);
+        }
+        mock.assert();
+        assert_eq!(ids, (0..COUNT).collect::<Vec<_>>());
+    }
+    #[tokio::test]
+    async fn test_get_messages_stream_error() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/api/v1/messages");
+            then.status(400).body(
+                r#"{"result": "error", "msg": "Invalid narrow operator: unknown operator",
+                    "code": "BAD_NARROW"}"#,
+            );
+        });
+        let client = test_client(server.address());
+        let req = GetMessagesRequest::new(MessageRange::new(0, 10));
+        let mut stream = client.get_messages_stream(req).await.unwrap();
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(Error::Unsuccessful { code, .. })) if code == "BAD_NARROW"
+        ));
+        assert!(stream.next().await.is_none());
+    }
+    #[tokio::test]
+    async fn test_render_message() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/messages/render")
+                .body(form_encode(&[("content", "**foo**")]));
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "rendered": "<p><strong>foo</strong></p>"}"#,
+            );
+        });
+        let client = test_client(server.address());
+        let rendered = client.render_message("**foo**").await.unwrap();
+        mock.assert();
+        assert_eq!(rendered, "<p><strong>foo</strong></p>");
+    }
+    #[tokio::test]
+    async fn test_move_message() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(PATCH)
+                .path("/api/v1/messages/123")
+                .body(form_encode(&[
+                    ("topic", "new topic"),
+                    ("propagate_mode", "change_all"),
+                    ("send_notification_to_old_thread", "true"),
+                    ("send_notification_to_new_thread", "true"),
+                    ("stream_id", "7"),
+                ]));
+            then.status(200).body(r#"{"result": "success", "msg": ""}"#);
+        });
+        let client = test_client(server.address());
+        let mut req = EditMessageRequest::new(123);
+        req.move_to(7, "new topic");
+        let result = client.edit_message(req).await;
+        mock.assert();
+        assert!(result.is_ok());
+    }
+    #[tokio::test]
+    async fn test_move_topic() {
+        let server = MockServer::start();
+        let messages_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/messages")
+                .query_param("anchor", "oldest")
+                .query_param("num_before", "0")
+                .query_param("num_after", "1")
+                .query_param(
+                    "narrow",
+                    r#"[{"operator":"stream","operand":3,"negated":false},{"operator":"topic","operand":"old topic","negated":false}]"#,
+                );
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "anchor": 0, "found_newest": false,
+                    "found_anchor": false, "messages": [{"id": 40,
+                    "timestamp": 1691057093, "content": "Hi",
+                    "content_type": "text/x-markdown", "avatar_url": null,
+                    "client": "website", "display_recipient": "general",
+                    "is_me_message": false, "reactions": [], "recipient_id": 10,
+                    "sender_email": "you@example.com", "sender_full_name": "You",
+                    "sender_id": 3, "sender_realm_str": "example", "stream_id": 3,
+                    "subject": "old topic", "type": "stream", "flags": []}]}"#,
+            );
+        });
+        let edit_mock = server.mock(|when, then| {
+            when.method(PATCH)
+                .path("/api/v1/messages/40")
+                .body(form_encode(&[
+                    ("topic", "new topic"),
+                    ("propagate_mode", "change_later"),
+                    ("send_notification_to_old_thread", "true"),
+                    ("send_notification_to_new_thread", "true"),
+                    ("stream_id", "7"),
+                ]));
+            then.status(200).body(r#"{"result": "success", "msg": ""}"#);
+        });
+        let client = test_client(server.address());
+        client
+            .move_topic(3, "old topic", Some(7), Some("new topic"))
+            .await
+            .unwrap();
+        messages_mock.assert();
+        edit_mock.assert();
+
+        let result = client.move_topic(3, "old topic", None, None).await;
+        assert!(matches!(result, Err(Error::InvalidRequest(_))));
+        messages_mock.assert_hits(1);
+    }
+    #[tokio::test]
     async fn test_delete_messages() {
         let server = MockServer::start();
         let id = 123;
@@ -351,6 +1507,876 @@ mod tests {
         mock.assert();
         assert!(result.is_ok());
     }
+    #[tokio::test]
+    async fn test_mark_messages_as_read() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/messages/flags")
+                .query_param("op", "add")
+                .query_param("flag", "read")
+                .query_param("messages", "[4,8,15]");
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "messages": [4, 15]}"#);
+        });
+        let client = test_client(server.address());
+        let response = client.mark_messages_as_read(&[4, 8, 15]).await.unwrap();
+        mock.assert();
+        assert_eq!(response.messages, [4, 15]);
+    }
+    #[tokio::test]
+    async fn test_set_star() {
+        let server = MockServer::start();
+        let star_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/messages/flags")
+                .query_param("op", "add")
+                .query_param("flag", "starred")
+                .query_param("messages", "[16,23]");
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "messages": [16, 23]}"#);
+        });
+        let unstar_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/messages/flags")
+                .query_param("op", "remove")
+                .query_param("flag", "starred")
+                .query_param("messages", "[42]");
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "messages": [42]}"#);
+        });
+        let client = test_client(server.address());
+        let response = client.set_star(&[16, 23], true).await.unwrap();
+        star_mock.assert();
+        assert_eq!(response.messages, [16, 23]);
+        let response = client.set_star(&[42], false).await.unwrap();
+        unstar_mock.assert();
+        assert_eq!(response.messages, [42]);
+    }
+    #[tokio::test]
+    async fn test_get_stream_topics() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/users/me/3/topics");
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "topics": [
+                    {"max_id": 12, "name": "old"}, {"max_id": 40, "name": "new"},
+                    {"max_id": 7, "name": "older"}, {"max_id": 25, "name": "newer"}]}"#,
+            );
+        });
+        let client = test_client(server.address());
+        let names =
+            |topics: Vec<Topic>| -> Vec<String> { topics.into_iter().map(|x| x.name).collect() };
+        let topics = client.get_stream_topics(3, None).await.unwrap();
+        assert_eq!(names(topics), ["new", "newer", "old", "older"]);
+        let topics = client.get_stream_topics(3, Some(2)).await.unwrap();
+        assert_eq!(names(topics), ["new", "newer"]);
+        mock.assert_hits(2);
+    }
+    #[tokio::test]
+    async fn test_get_topics_in_stream_not_modified() {
+        let server = MockServer::start();
+        let mut mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/users/me/3/topics");
+            then.status(200).header("ETag", r#""v1""#).body(
+                r#"{"result": "success", "msg": "", "topics": [{"max_id": 12, "name": "old"}]}"#,
+            );
+        });
+        let client = test_client(server.address());
+        let topics = client.get_topics_in_stream(3).await.unwrap();
+        mock.assert();
+        mock.delete();
+        let not_modified_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/users/me/3/topics")
+                .header("If-None-Match", r#""v1""#);
+            then.status(304);
+        });
+        let cached_topics = client.get_topics_in_stream(3).await.unwrap();
+        not_modified_mock.assert();
+        assert_eq!(
+            cached_topics.iter().map(|x| &x.name).collect::<Vec<_>>(),
+            ["old"]
+        );
+        assert_eq!(cached_topics.len(), topics.len());
+    }
+
+    #[tokio::test]
+    async fn test_etag_cache_is_bounded() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path_contains("/topics");
+            then.status(200)
+                .header("ETag", r#""v1""#)
+                .body(r#"{"result": "success", "msg": "", "topics": []}"#);
+        });
+        let client = test_client(server.address());
+        for stream_id in 0..2 * ETAG_CACHE_SIZE as u64 {
+            client.get_topics_in_stream(stream_id).await.unwrap();
+        }
+        mock.assert_hits(2 * ETAG_CACHE_SIZE);
+        assert!(client.etag_cache.lock().unwrap().size() <= ETAG_CACHE_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_get_topics_with_times() {
+        let server = MockServer::start();
+        let topics_mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/users/me/3/topics");
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "topics": [
+                    {"max_id": 12, "name": "old"}, {"max_id": 40, "name": "new"},
+                    {"max_id": 7, "name": "older"}]}"#,
+            );
+        });
+        let message_mock = |id: u64, timestamp: i64| {
+            server.mock(move |when, then| {
+                when.method(GET)
+                    .path("/api/v1/messages")
+                    .query_param("anchor", id.to_string())
+                    .query_param("num_before", "0")
+                    .query_param("num_after", "0");
+                then.status(200).body(format!(
+                    r#"{{"result": "success", "msg": "", "anchor": {id}, "found_newest": false,
+                        "found_anchor": true, "messages": [{{"id": {id},
+                        "timestamp": {timestamp}, "content": "Hi",
+                        "content_type": "text/x-markdown", "avatar_url": null,
+                        "client": "website", "display_recipient": "general",
+                        "is_me_message": false, "reactions": [], "recipient_id": 10,
+                        "sender_email": "you@example.com", "sender_full_name": "You",
+                        "sender_id": 3, "sender_realm_str": "example", "stream_id": 3,
+                        "subject": "topic", "type": "stream", "flags": []}}]}}"#
+                ));
+            })
+        };
+        let new_mock = message_mock(40, 1691057093);
+        let old_mock = message_mock(12, 1690000000);
+        let older_mock = message_mock(7, 1680000000);
+        let client = test_client(server.address());
+
+        let topics = client.get_topics_with_times(3, Some(2)).await.unwrap();
+        topics_mock.assert();
+        let topics: Vec<_> = topics
+            .iter()
+            .map(|(topic, time)| (topic.name.as_str(), time.timestamp()))
+            .collect();
+        assert_eq!(topics, [("new", 1691057093), ("old", 1690000000)]);
+        new_mock.assert();
+        old_mock.assert();
+        older_mock.assert_hits(0);
+
+        let topics = client.get_topics_with_times(3, None).await.unwrap();
+        assert_eq!(topics.len(), 3);
+        assert_eq!(topics[2].0.name, "older");
+        older_mock.assert();
+    }
+    #[tokio::test]
+    async fn test_get_subscriptions() {
+        let server = MockServer::start();
+        let subscription = |subscribers: &str| {
+            format!(
+                r##"{{"result": "success", "msg": "", "subscriptions": [{{"stream_id": 1,
+                    "name": "general", "description": "", "rendered_description": "",
+                    "date_created": 1691057093, "invite_only": false,
+                    "stream_post_policy": 1, "message_retention_days": null,
+                    "history_public_to_subscribers": true, "first_message_id": null,
+                    "can_remove_subscribers": null, "desktop_notifications": null,
+                    "email_notifications": null, "wildcard_mentions_notify": null,
+                    "push_notifications": null, "audible_notifications": null,
+                    "pin_to_top": false, "email_address": "general@example.com",
+                    "is_muted": false, "is_web_public": false, "color": "#76ce90",
+                    "stream_weekly_traffic": 12{}}}]}}"##,
+                subscribers
+            )
+        };
+        let with_subscribers_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/users/me/subscriptions")
+                .query_param("include_subscribers", "true");
+            then.status(200)
+                .body(subscription(r#", "subscribers": [3, 5, 8]"#));
+        });
+        let without_subscribers_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/users/me/subscriptions")
+                .query_param("include_subscribers", "false");
+            then.status(200).body(subscription(""));
+        });
+        let client = test_client(server.address());
+        let subscriptions = client.get_subscriptions(true).await.unwrap();
+        with_subscribers_mock.assert();
+        assert_eq!(subscriptions[0].name, "general");
+        assert_eq!(subscriptions[0].subscribers, [3, 5, 8]);
+        let subscriptions = client.get_subscribed_streams().await.unwrap();
+        without_subscribers_mock.assert();
+        assert!(subscriptions[0].subscribers.is_empty());
+    }
+    #[tokio::test]
+    async fn test_get_streams_with_subscription() {
+        let server = MockServer::start();
+        let stream = |id: u64, name: &str, extra: &str| {
+            format!(
+                r##"{{"stream_id": {}, "name": "{}", "description": "",
+                    "rendered_description": "", "date_created": 1691057093,
+                    "invite_only": false, "stream_post_policy": 1,
+                    "message_retention_days": null, "history_public_to_subscribers": true,
+                    "first_message_id": null, "can_remove_subscribers": null,
+                    "is_web_public": false{}}}"##,
+                id, name, extra
+            )
+        };
+        let streams_mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/streams");
+            then.status(200).body(format!(
+                r#"{{"result": "success", "msg": "", "streams": [{}, {}, {}]}}"#,
+                stream(1, "general", ""),
+                stream(2, "design", ""),
+                stream(3, "random", "")
+            ));
+        });
+        let subscriptions_mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/users/me/subscriptions");
+            then.status(200).body(format!(
+                r#"{{"result": "success", "msg": "", "subscriptions": [{}]}}"#,
+                stream(
+                    3,
+                    "random",
+                    r##", "desktop_notifications": null, "email_notifications": null,
+                    "wildcard_mentions_notify": null, "push_notifications": null,
+                    "audible_notifications": null, "pin_to_top": false,
+                    "email_address": "random@example.com", "is_muted": false,
+                    "color": "#76ce90", "stream_weekly_traffic": 12"##
+                )
+            ));
+        });
+        let client = test_client(server.address());
+        let streams = client
+            .get_streams_with_subscription(&GetStreamsRequest::default())
+            .await
+            .unwrap();
+        streams_mock.assert();
+        subscriptions_mock.assert();
+        let streams: Vec<_> = streams
+            .iter()
+            .map(|(stream, is_subscribed)| (stream.name.as_str(), *is_subscribed))
+            .collect();
+        assert_eq!(
+            streams,
+            [("general", false), ("design", false), ("random", true)]
+        );
+    }
+    #[tokio::test]
+    async fn test_user_agent_and_default_headers() {
+        let server = MockServer::start();
+        let default_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/mark_all_as_read")
+                .header("user-agent", DEFAULT_USER_AGENT);
+            then.status(200).body(r#"{"result": "success", "msg": ""}"#);
+        });
+        let custom_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/mark_all_as_read")
+                .header("user-agent", "zcli/1.2.3")
+                .header("x-request-source", "test")
+                .header_exists("authorization");
+            then.status(200).body(r#"{"result": "success", "msg": ""}"#);
+        });
+        let client = test_client(server.address());
+        let _ = client.mark_all_as_read().await;
+        default_mock.assert();
+        let client = client
+            .with_user_agent("zcli/1.2.3")
+            .unwrap()
+            .with_default_header("X-Request-Source", "test")
+            .unwrap();
+        let _ = client.mark_all_as_read().await;
+        custom_mock.assert();
+        default_mock.assert_hits(1);
+        assert!(test_client(server.address())
+            .with_user_agent("bad\nagent")
+            .is_err());
+    }
+    #[tokio::test]
+    async fn test_create_stream() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/users/me/subscriptions")
+                // The form encoded version of:
+                // subscriptions=[{"name":"design","description":"All about design"}]
+                .body(
+                    "subscriptions=%5B%7B%22name%22%3A%22design%22%2C%22description%22%3A%\
+                     22All+about+design%22%7D%5D&invite_only=true",
+                );
+            then.status(200).body(
+                r#"{"result": "success", "msg": "",
+                    "subscribed": {"me@example.com": ["design"]},
+                    "already_subscribed": {}}"#,
+            );
+        });
+        let client = test_client(server.address());
+        let response = client
+            .create_stream("design", "All about design", true)
+            .await
+            .unwrap();
+        mock.assert();
+        assert_eq!(response.subscribed["me@example.com"], ["design"]);
+        assert!(response.already_subscribed.is_empty());
+        assert!(response.unauthorized.is_empty());
+    }
+    #[tokio::test]
+    async fn test_get_linkifiers() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/realm/linkifiers");
+            then.status(200).body(
+                r##"{"result": "success", "msg": "", "linkifiers": [
+                    {"id": 1, "pattern": "#(?P<id>[0-9]+)",
+                        "url_template": "https://github.com/zulip/zulip/issues/{id}"},
+                    {"id": 2, "pattern": "T(?P<id>[0-9]+)",
+                        "url_format_string": "https://tickets.example.com/%(id)s"}
+                ]}"##,
+            );
+        });
+        let client = test_client(server.address());
+        let linkifiers = client.get_linkifiers().await.unwrap();
+        mock.assert();
+        assert_eq!(
+            linkifiers,
+            [
+                Linkifier {
+                    id: 1,
+                    pattern: "#(?P<id>[0-9]+)".to_string(),
+                    url_template: "https://github.com/zulip/zulip/issues/{id}".to_string(),
+                },
+                Linkifier {
+                    id: 2,
+                    pattern: "T(?P<id>[0-9]+)".to_string(),
+                    url_template: "https://tickets.example.com/%(id)s".to_string(),
+                },
+            ]
+        );
+    }
+    #[tokio::test]
+    async fn test_update_subscription_settings() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/users/me/subscriptions/properties")
+                // The form encoded version of:
+                // subscription_data=[{"stream_id":5,"property":"color","value":"#76ce90"},
+                //                    {"stream_id":5,"property":"is_muted","value":true}]
+                .body(
+                    "subscription_data=%5B%7B%22stream_id%22%3A5%2C%22property%22%3A%22color%22%\
+                     2C%22value%22%3A%22%2376ce90%22%7D%2C%7B%22stream_id%22%3A5%2C%22property%22%\
+                     3A%22is_muted%22%2C%22value%22%3Atrue%7D%5D",
+                );
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "subscription_data": []}"#);
+        });
+        let client = test_client(server.address());
+        let mut changes = SubscriptionSettings::new();
+        changes
+            .is_muted(true)
+            .color(Color::from_hex("#76ce90").unwrap());
+        client
+            .update_subscription_settings(5, changes)
+            .await
+            .unwrap();
+        mock.assert();
+
+        let result = client
+            .update_subscription_settings(5, SubscriptionSettings::new())
+            .await;
+        mock.assert_hits(1);
+        assert!(matches!(result, Err(Error::InvalidRequest(_))));
+    }
+    #[tokio::test]
+    async fn test_get_read_receipts() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/messages/42/read_receipts");
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "user_ids": [3, 7, 9]}"#);
+        });
+        let disabled_mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/messages/43/read_receipts");
+            then.status(400).body(
+                r#"{"result": "error", "code": "BAD_REQUEST",
+                    "msg": "Read receipts are disabled in this organization."}"#,
+            );
+        });
+        let client = test_client(server.address());
+        assert_eq!(client.get_read_receipts(42).await.unwrap(), [3, 7, 9]);
+        mock.assert();
+        let err = client.get_read_receipts(43).await.unwrap_err();
+        disabled_mock.assert();
+        assert!(err.is_read_receipts_disabled(), "{}", err);
+    }
+    #[tokio::test]
+    async fn test_are_messages_read() {
+        let server = MockServer::start();
+        let message = |id: u64, flags: &str| {
+            format!(
+                r#"{{"id": {id}, "timestamp": 1691057093, "content": "Hi",
+                    "content_type": "text/x-markdown", "avatar_url": null,
+                    "client": "website", "display_recipient": "general",
+                    "is_me_message": false, "reactions": [], "recipient_id": 10,
+                    "sender_email": "you@example.com", "sender_full_name": "You",
+                    "sender_id": 3, "sender_realm_str": "example", "stream_id": 3,
+                    "subject": "greetings", "type": "stream", "flags": {flags}}}"#,
+                id = id,
+                flags = flags
+            )
+        };
+        let mut mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/messages")
+                .query_param("message_ids", "[41,40,42]");
+            then.status(200).body(format!(
+                r#"{{"result": "success", "msg": "", "messages": [{}, {}]}}"#,
+                message(40, r#"["read", "starred"]"#),
+                message(41, r#"["mentioned"]"#)
+            ));
+        });
+        let client = test_client(server.address());
+        let err = client.are_messages_read(&[41, 40, 42]).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidRequest(msg) if msg == "Message 42 not found"));
+        mock.assert();
+        mock.delete();
+
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/messages")
+                .query_param("message_ids", "[41,40]");
+            then.status(200).body(format!(
+                r#"{{"result": "success", "msg": "", "messages": [{}, {}]}}"#,
+                message(40, r#"["read", "starred"]"#),
+                message(41, r#"["mentioned"]"#)
+            ));
+        });
+        assert_eq!(
+            client.are_messages_read(&[41, 40]).await.unwrap(),
+            [false, true]
+        );
+        mock.assert();
+        assert!(client.are_messages_read(&[]).await.unwrap().is_empty());
+
+        // The ids are split into several requests.
+        let ids: Vec<u64> = (0..MAX_IDS_PER_REQUEST as u64 + 1).collect();
+        let chunk_mock = |chunk: &[u64]| {
+            let messages = chunk.iter().map(|&id| message(id, r#"["read"]"#));
+            let body = format!(
+                r#"{{"result": "success", "msg": "", "messages": [{}]}}"#,
+                messages.collect::<Vec<_>>().join(", ")
+            );
+            let message_ids = serde_json::to_string(chunk).unwrap();
+            server.mock(|when, then| {
+                when.method(GET)
+                    .path("/api/v1/messages")
+                    .query_param("message_ids", message_ids);
+                then.status(200).body(body);
+            })
+        };
+        let first_mock = chunk_mock(&ids[..MAX_IDS_PER_REQUEST]);
+        let second_mock = chunk_mock(&ids[MAX_IDS_PER_REQUEST..]);
+        let read = client.are_messages_read(&ids).await.unwrap();
+        assert_eq!(read.len(), ids.len());
+        assert!(read.iter().all(|&x| x));
+        first_mock.assert();
+        second_mock.assert();
+    }
+    #[tokio::test]
+    async fn test_get_users() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/users");
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "members": [
+                    {"user_id": 8, "email": "alice@example.com", "full_name": "Alice Smith",
+                        "is_active": true, "is_bot": false},
+                    {"user_id": 9, "email": "notify-bot@example.com", "full_name": "Notify",
+                        "is_active": false, "is_bot": true, "bot_type": 1}
+                ]}"#,
+            );
+        });
+        let client = test_client(server.address());
+        let users = client.get_users().await.unwrap();
+        mock.assert();
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].full_name, "Alice Smith");
+        assert!(!users[1].is_active && users[1].is_bot);
+    }
+    #[tokio::test]
+    async fn test_update_status() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/users/me/status")
+                .body(form_encode(&[
+                    ("status_text", "on vacation"),
+                    ("away", "true"),
+                ]));
+            then.status(200).body(r#"{"result": "success", "msg": ""}"#);
+        });
+        let client = test_client(server.address());
+        client
+            .update_status(UserStatus {
+                status_text: Some("on vacation".to_string()),
+                away: Some(true),
+                emoji_name: None,
+            })
+            .await
+            .unwrap();
+        mock.assert();
+        let err = client
+            .update_status(UserStatus::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidRequest(_)));
+        mock.assert_hits(1);
+    }
+    #[tokio::test]
+    async fn test_get_user_groups() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/user_groups");
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "user_groups": [
+                    {"id": 1, "name": "hamletcharacters", "description": "Characters of Hamlet",
+                        "members": [10, 12], "direct_subgroup_ids": [],
+                        "is_system_group": false, "can_mention_group": 11},
+                    {"id": 2, "name": "role:members", "description": "Members of this organization",
+                        "members": [8, 9, 10, 12], "direct_subgroup_ids": [1],
+                        "is_system_group": true, "can_mention_group": 11}
+                ]}"#,
+            );
+        });
+        let client = test_client(server.address());
+        let groups = client.get_user_groups().await.unwrap();
+        mock.assert();
+        assert_eq!(
+            groups[0],
+            UserGroup {
+                id: 1,
+                name: "hamletcharacters".to_string(),
+                description: "Characters of Hamlet".to_string(),
+                members: vec![10, 12],
+                is_system_group: false,
+            }
+        );
+        assert_eq!(groups[1].members, [8, 9, 10, 12]);
+        assert!(groups[1].is_system_group);
+    }
+    #[tokio::test]
+    async fn test_get_user_by_email() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/users/iago@zulip.com");
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "user": {
+                    "user_id": 11, "email": "iago@zulip.com",
+                    "delivery_email": "iago@zulip.com", "full_name": "Iago",
+                    "is_active": true, "is_admin": true, "is_owner": false,
+                    "is_guest": false, "is_bot": false, "role": 200,
+                    "timezone": "", "date_joined": "2019-10-20T07:50:53.728864+00:00",
+                    "avatar_url": null, "avatar_version": 1, "profile_data": {}}}"#,
+            );
+        });
+        let client = test_client(server.address());
+        let user = client.get_user_by_email("iago@zulip.com").await.unwrap();
+        mock.assert();
+        assert_eq!(user.user_id, 11);
+        assert_eq!(user.full_name, "Iago");
+        assert!(user.is_admin && !user.is_bot);
+        assert_eq!(user.role, Some(200));
+
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/users/a%2Fb%3Fc%23d%25e%20f@zulip.com");
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "user": {
+                    "user_id": 12, "email": "a/b?c#d%e f@zulip.com",
+                    "delivery_email": null, "full_name": "Odd",
+                    "is_active": true, "is_admin": false, "is_owner": false,
+                    "is_guest": false, "is_bot": false, "role": 400,
+                    "timezone": "", "date_joined": "2019-10-20T07:50:53.728864+00:00",
+                    "avatar_url": null, "avatar_version": 1, "profile_data": {}}}"#,
+            );
+        });
+        let user = client
+            .get_user_by_email("a/b?c#d%e f@zulip.com")
+            .await
+            .unwrap();
+        mock.assert();
+        assert_eq!(user.user_id, 12);
+    }
+    #[tokio::test]
+    async fn test_event_queue() {
+        let server = MockServer::start();
+        let register_mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/register")
+                .body(form_encode(&[
+                    ("event_types", r#"["message"]"#),
+                    ("narrow", r#"[["stream","general"]]"#),
+                    ("apply_markdown", "true"),
+                ]));
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "queue_id": "1375801870:2942",
+                    "last_event_id": -1, "zulip_feature_level": 185,
+                    "unread_msgs": {"count": 3, "pms": [], "streams": [], "huddles": [],
+                        "mentions": [], "old_unreads_missing": false}}"#,
+            );
+        });
+        let events_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/events")
+                .query_param("queue_id", "1375801870:2942")
+                .query_param("last_event_id", "-1");
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "queue_id": "1375801870:2942",
+                    "events": [{"type": "heartbeat", "id": 0}]}"#,
+            );
+        });
+        let expired_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/events")
+                .query_param("last_event_id", "0");
+            then.status(400).body(
+                r#"{"result": "error", "code": "BAD_EVENT_QUEUE_ID",
+                    "msg": "Bad event queue ID: 1375801870:2942",
+                    "queue_id": "1375801870:2942"}"#,
+            );
+        });
+        let client = test_client(server.address());
+        let req = RegisterQueueRequest {
+            event_types: vec!["message".to_string()],
+            narrow: vec![("stream".to_string(), "general".to_string())],
+            apply_markdown: true,
+        };
+        let queue = client.register_queue(&req).await.unwrap();
+        register_mock.assert();
+        assert_eq!(queue.last_event_id, -1);
+        assert_eq!(queue.unread_msgs.unwrap().count, 3);
+        let events = client
+            .get_events(&queue.queue_id, queue.last_event_id)
+            .await
+            .unwrap();
+        events_mock.assert();
+        assert!(matches!(
+            events[..],
+            [Event {
+                id: 0,
+                kind: EventKind::Heartbeat
+            }]
+        ));
+        let err = client.get_events(&queue.queue_id, 0).await.unwrap_err();
+        expired_mock.assert();
+        assert!(err.is_bad_event_queue(), "{}", err);
+    }
+    #[tokio::test]
+    async fn test_deactivate_and_reactivate_user() {
+        let server = MockServer::start();
+        let deactivate_mock = server.mock(|when, then| {
+            when.method(DELETE).path("/api/v1/users/12");
+            then.status(200).body(r#"{"result": "success", "msg": ""}"#);
+        });
+        let reactivate_mock = server.mock(|when, then| {
+            when.method(POST).path("/api/v1/users/12/reactivate");
+            then.status(200).body(r#"{"result": "success", "msg": ""}"#);
+        });
+        let denied_mock = server.mock(|when, then| {
+            when.method(DELETE).path("/api/v1/users/13");
+            then.status(403).body(
+                r#"{"result": "error", "code": "UNAUTHORIZED_PRINCIPAL",
+                    "msg": "Must be an organization administrator"}"#,
+            );
+        });
+        let unauthorized_mock = server.mock(|when, then| {
+            when.method(DELETE).path("/api/v1/users/14");
+            then.status(401).body(
+                r#"{"result": "error", "code": "UNAUTHORIZED",
+                    "msg": "Invalid API key"}"#,
+            );
+        });
+        let client = test_client(server.address());
+        client.deactivate_user(12).await.unwrap();
+        deactivate_mock.assert();
+        client.reactivate_user(12).await.unwrap();
+        reactivate_mock.assert();
+        let err = client.deactivate_user(13).await.unwrap_err();
+        denied_mock.assert();
+        assert!(
+            matches!(&err, Error::Unsuccessful { msg, .. } if msg.starts_with("Must be")),
+            "{}",
+            err
+        );
+        assert!(err.is_permission_denied());
+        // Invalid credentials are not a lack of permission.
+        let err = client.deactivate_user(14).await.unwrap_err();
+        unauthorized_mock.assert();
+        assert!(
+            matches!(&err, Error::Unsuccessful { code, .. } if code == "UNAUTHORIZED"),
+            "{}",
+            err
+        );
+        assert!(!err.is_permission_denied());
+    }
+    #[tokio::test]
+    async fn test_get_message_history() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/messages/42/history");
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "message_history": [
+                    {"topic": "greetings", "content": "Hi", "rendered_content": "<p>Hi</p>",
+                        "user_id": 5, "timestamp": 1691057093},
+                    {"topic": "hello", "prev_topic": "greetings", "content": "Hi",
+                        "rendered_content": "<p>Hi</p>", "user_id": 5, "timestamp": 1691057193},
+                    {"topic": "hello", "content": "Hello", "rendered_content": "<p>Hello</p>",
+                        "prev_content": "Hi", "prev_rendered_content": "<p>Hi</p>",
+                        "content_html_diff": "<div><p>Hello</p></div>",
+                        "user_id": 6, "timestamp": 1691057293}
+                ]}"#,
+            );
+        });
+        let client = test_client(server.address());
+        let history = client.get_message_history(42).await.unwrap();
+        mock.assert();
+        assert_eq!(history.len(), 3);
+        assert!(history[0].prev_topic.is_none() && history[0].prev_content.is_none());
+        assert_eq!(history[1].topic.as_deref(), Some("hello"));
+        assert_eq!(history[1].prev_topic.as_deref(), Some("greetings"));
+        assert!(history[1].prev_content.is_none());
+        assert_eq!(history[2].user_id, Some(6));
+        assert_eq!(history[2].prev_content.as_deref(), Some("Hi"));
+        assert_eq!(
+            history[2].prev_rendered_content.as_deref(),
+            Some("<p>Hi</p>")
+        );
+        assert_eq!(history[2].timestamp.timestamp(), 1691057293);
+    }
+    #[tokio::test]
+    async fn test_create_scheduled_message() {
+        let server = MockServer::start();
+        let expected_body =
+            "type=stream&to=5&topic=test&content=abc&scheduled_delivery_timestamp=1681662420";
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/v1/scheduled_messages")
+                .body(expected_body);
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "scheduled_message_id": 42}"#);
+        });
+        let client = test_client(server.address());
+        let req = SendMessageRequest::Stream {
+            to: Identifier::Id(5),
+            topic: "test".to_string(),
+            content: "abc".to_string(),
+        };
+        let id = client
+            .create_scheduled_message(req, Utc.timestamp_opt(1681662420, 0).unwrap())
+            .await
+            .unwrap();
+        mock.assert();
+        assert_eq!(id, 42);
+    }
+    #[tokio::test]
+    async fn test_delete_scheduled_message() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(DELETE).path("/api/v1/scheduled_messages/42");
+            then.status(200).body(r#"{"result": "success", "msg": ""}"#);
+        });
+        let client = test_client(server.address());
+        let result = client.delete_scheduled_message(42).await;
+        mock.assert();
+        assert!(result.is_ok());
+    }
+    #[tokio::test]
+    async fn test_get_drafts() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/drafts");
+            then.status(200).body(
+                r#"{
+    "count": 1,
+    "drafts": [
+        {
+            "content": "Hello there!",
+            "id": 17,
+            "timestamp": 1595479019,
+            "to": [3],
+            "topic": "sample topic",
+            "type": "stream"
+        }
+    ],
+    "msg": "",
+    "result": "success"
+}"#,
+            );
+        });
+        let client = test_client(server.address());
+        let drafts = client.get_drafts().await.unwrap();
+        mock.assert();
+        assert_eq!(
+            drafts,
+            [Draft {
+                id: Some(17),
+                r#type: DraftType::Stream,
+                to: vec![3],
+                topic: "sample topic".to_string(),
+                content: "Hello there!".to_string(),
+                timestamp: Some(Utc.timestamp_opt(1595479019, 0).unwrap()),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_drafts() {
+        let server = MockServer::start();
+        let drafts = [
+            Draft {
+                id: None,
+                r#type: DraftType::Stream,
+                to: vec![3],
+                topic: "sample topic".to_string(),
+                content: "Hello there!".to_string(),
+                timestamp: None,
+            },
+            Draft {
+                id: None,
+                r#type: DraftType::Private,
+                to: vec![4, 8],
+                topic: String::new(),
+                content: "Hi".to_string(),
+                timestamp: Some(Utc.timestamp_opt(1595479019, 0).unwrap()),
+            },
+        ];
+        let expected_body = form_encode(&[(
+            "drafts",
+            r#"[{"type":"stream","to":[3],"topic":"sample topic","content":"Hello there!"},{"type":"private","to":[4,8],"topic":"","content":"Hi","timestamp":1595479019}]"#,
+        )]);
+        let mock = server.mock(|when, then| {
+            when.method(POST).path("/api/v1/drafts").body(expected_body);
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "ids": [17, 18]}"#);
+        });
+        let client = test_client(server.address());
+        let ids = client.create_drafts(&drafts).await.unwrap();
+        mock.assert();
+        assert_eq!(ids, [17, 18]);
+    }
+
+    /// Encode key-value pairs as an `application/x-www-form-urlencoded` body.
+    fn form_encode(pairs: &[(&str, &str)]) -> String {
+        let mut url = reqwest::Url::parse("http://example.com").unwrap();
+        url.query_pairs_mut().extend_pairs(pairs);
+        url.query().unwrap().to_string()
+    }
+
     fn message_template() -> String {
         r#"{
     "anchor": 21,
@@ -428,4 +2454,155 @@ mod tests {
     "result": "success"
 }"#.to_string()
     }
+
+    #[tokio::test]
+    async fn test_try_get_stream_id() {
+        let server = MockServer::start();
+        let found_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/get_stream_id")
+                .query_param("stream", "general");
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "stream_id": 15}"#);
+        });
+        let not_found_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/get_stream_id")
+                .query_param("stream", "nonexistent");
+            then.status(400).body(
+                r#"{"result": "error", "msg": "Invalid stream name 'nonexistent'", "code": "BAD_REQUEST"}"#,
+            );
+        });
+        let bad_request_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/get_stream_id")
+                .query_param("stream", "");
+            then.status(400).body(
+                r#"{"result": "error", "msg": "Stream name can't be empty", "code": "BAD_REQUEST"}"#,
+            );
+        });
+        let server_error_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/get_stream_id")
+                .query_param("stream", "broken");
+            then.status(429).body(
+                r#"{"result": "error", "msg": "API usage exceeded rate limit", "code": "RATE_LIMIT_HIT"}"#,
+            );
+        });
+        let client = test_client(server.address());
+        assert_eq!(client.try_get_stream_id("general").await.unwrap(), Some(15));
+        assert_eq!(client.try_get_stream_id("nonexistent").await.unwrap(), None);
+        // Other bad requests are not mistaken for a missing stream.
+        let err = client.try_get_stream_id("").await.unwrap_err();
+        assert!(matches!(err, Error::Unsuccessful { code, .. } if code == "BAD_REQUEST"));
+        let err = client.try_get_stream_id("broken").await.unwrap_err();
+        assert!(matches!(err, Error::Unsuccessful { code, .. } if code == "RATE_LIMIT_HIT"));
+        found_mock.assert();
+        not_found_mock.assert();
+        bad_request_mock.assert();
+        server_error_mock.assert();
+    }
+
+    #[tokio::test]
+    async fn test_get_stream_by_name() {
+        let server = MockServer::start();
+        let id_mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/get_stream_id")
+                .query_param("stream", "general");
+            then.status(200)
+                .body(r#"{"result": "success", "msg": "", "stream_id": 15}"#);
+        });
+        let stream_mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/streams/15");
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "stream": {"stream_id": 15,
+                    "name": "general", "description": "", "rendered_description": "",
+                    "date_created": 1691057093, "invite_only": false,
+                    "stream_post_policy": 1, "message_retention_days": null,
+                    "history_public_to_subscribers": true, "first_message_id": null,
+                    "can_remove_subscribers": null}}"#,
+            );
+        });
+        let client = test_client(server.address());
+        let stream = client.get_stream_by_name("general").await.unwrap();
+        id_mock.assert();
+        stream_mock.assert();
+        assert_eq!(stream.stream_id, 15);
+        assert_eq!(stream.name, "general");
+    }
+
+    #[tokio::test]
+    async fn test_search_streams() {
+        let server = MockServer::start();
+        let stream = |id: u64, name: &str| {
+            format!(
+                r#"{{"stream_id": {}, "name": "{}", "description": "",
+                    "rendered_description": "", "date_created": 1691057093,
+                    "invite_only": false, "stream_post_policy": 1,
+                    "message_retention_days": null, "history_public_to_subscribers": true,
+                    "first_message_id": null, "can_remove_subscribers": null}}"#,
+                id, name
+            )
+        };
+        let streams_mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/streams");
+            then.status(200).body(format!(
+                r#"{{"result": "success", "msg": "", "streams": [{}, {}, {}, {}]}}"#,
+                stream(1, "general"),
+                stream(2, "design-review"),
+                stream(3, "Design"),
+                stream(4, "general-chat")
+            ));
+        });
+        let client = test_client(server.address());
+        let names =
+            |streams: Vec<Stream>| -> Vec<String> { streams.into_iter().map(|x| x.name).collect() };
+        assert_eq!(
+            names(client.search_streams("des").await.unwrap()),
+            ["design-review", "Design"]
+        );
+        // An exact match comes first, but other streams containing the query
+        // are still returned.
+        assert_eq!(
+            names(client.search_streams("Design").await.unwrap()),
+            ["Design", "design-review"]
+        );
+        assert_eq!(
+            names(client.search_streams("general").await.unwrap()),
+            ["general", "general-chat"]
+        );
+        streams_mock.assert_hits(3);
+    }
+
+    #[tokio::test]
+    async fn test_get_realm_emoji() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/api/v1/realm/emoji");
+            then.status(200).body(
+                r#"{"result": "success", "msg": "", "emoji": {"1": {"id": "1",
+                    "name": "green_tick", "source_url": "/user_avatars/1/emoji/green_tick.png",
+                    "deactivated": false, "author_id": 5}}}"#,
+            );
+        });
+        let client = test_client(server.address());
+        let emoji = client.get_realm_emoji().await.unwrap();
+        assert_eq!(
+            emoji["1"],
+            RealmEmoji {
+                id: "1".to_string(),
+                name: "green_tick".to_string(),
+                source_url: "/user_avatars/1/emoji/green_tick.png".to_string(),
+                deactivated: false,
+                author_id: Some(5),
+            }
+        );
+        // The second call should use the cache.
+        assert_eq!(client.get_realm_emoji().await.unwrap(), emoji);
+        mock.assert_hits(1);
+        client.clear_realm_emoji_cache();
+        client.get_realm_emoji().await.unwrap();
+        mock.assert_hits(2);
+    }
 }
@@ -0,0 +1,62 @@
+//! Types for requests and responses about drafts.
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A draft of a message, stored on the server.
+///
+/// Can be fetched with `crate::Client::get_drafts`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Draft {
+    /// The unique ID of the draft.
+    ///
+    /// Only present for drafts fetched from the server, it should be `None`
+    /// when creating new drafts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    /// The type of the draft.
+    pub r#type: DraftType,
+    /// A vector with one stream ID if this is a stream draft, or a list of user
+    /// IDs if this is a private message draft.
+    pub to: Vec<u64>,
+    /// The topic of a stream draft. Should be empty for private message
+    /// drafts.
+    pub topic: String,
+    /// The body of the draft.
+    pub content: String,
+    /// The time when the draft was last edited.
+    ///
+    /// When creating a draft, `None` means that the current time will be used.
+    #[serde(
+        default,
+        with = "chrono::serde::ts_seconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+/// The type of a draft.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DraftType {
+    Stream,
+    Private,
+}
+
+/// A wrapper around the response from get_drafts.
+#[derive(Deserialize, Debug)]
+pub(crate) struct GetDraftsResponse {
+    pub drafts: Vec<Draft>,
+}
+
+/// A request to create drafts.
+#[derive(Serialize, Debug)]
+pub(crate) struct CreateDraftsRequest<'a> {
+    #[serde(serialize_with = "crate::message::serialize_as_json_str")]
+    pub drafts: &'a [Draft],
+}
+
+/// A wrapper around the response from create_drafts.
+#[derive(Deserialize, Debug)]
+pub(crate) struct CreateDraftsResponse {
+    pub ids: Vec<u64>,
+}
@@ -0,0 +1,34 @@
+//! Types for requests and responses about custom emoji.
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// A custom emoji uploaded to the realm (organization).
+///
+/// Can be fetched with `crate::Client::get_realm_emoji`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RealmEmoji {
+    /// The ID for this emoji, same as the key in the map returned by
+    /// `crate::Client::get_realm_emoji`.
+    pub id: String,
+    /// The user-friendly name for this emoji. Users in the organization can use
+    /// this emoji by writing this name between colons (`:name:`).
+    pub name: String,
+    /// The path relative to the organization's URL where the emoji's image can
+    /// be found.
+    pub source_url: String,
+    /// Whether the emoji has been deactivated or not.
+    pub deactivated: bool,
+    /// The ID of the user who uploaded the emoji.
+    ///
+    /// `None` if the emoji was uploaded before the server started recording
+    /// the author.
+    #[serde(default)]
+    pub author_id: Option<u64>,
+}
+
+/// A wrapper around the response from get_realm_emoji.
+#[derive(Deserialize, Debug)]
+pub(crate) struct GetRealmEmojiResponse {
+    pub emoji: HashMap<String, RealmEmoji>,
+}
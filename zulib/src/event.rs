@@ -0,0 +1,163 @@
+//! Types for the real-time events API.
+use serde::{Deserialize, Serialize};
+
+use crate::message::{serialize_as_json_str, Flag, ReceivedMessage};
+
+/// Register an event queue, from which events can then be fetched with
+/// `crate::Client::get_events`.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RegisterQueueRequest {
+    /// The types of events to receive, like "message", or all types if empty.
+    #[serde(
+        serialize_with = "serialize_as_json_str",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub event_types: Vec<String>,
+    /// Only receive message events for messages matching all of these
+    /// (operator, operand) filters, like ("stream", "general").
+    #[serde(
+        serialize_with = "serialize_as_json_str",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub narrow: Vec<(String, String)>,
+    /// Whether the content of messages should be rendered to HTML.
+    pub apply_markdown: bool,
+}
+
+/// A registered event queue.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RegisterQueueResponse {
+    /// The id of the queue, used to fetch events from it.
+    pub queue_id: String,
+    /// The id of the last event in the queue before any events were fetched,
+    /// usually -1.
+    pub last_event_id: i64,
+    /// The unread messages of the user, if "message" is in `event_types`.
+    #[serde(default)]
+    pub unread_msgs: Option<UnreadMessages>,
+}
+
+/// A summary of the unread messages of the user, as returned when registering
+/// an event queue.
+#[derive(Deserialize, Debug, Clone)]
+pub struct UnreadMessages {
+    /// The total number of unread messages.
+    pub count: u64,
+}
+
+/// An event from an event queue.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(from = "RawEvent")]
+pub struct Event {
+    /// The id of the event within the queue, which should be passed as
+    /// `last_event_id` to `crate::Client::get_events` once the event is
+    /// handled.
+    pub id: i64,
+    pub kind: EventKind,
+}
+
+/// The type and content of an `Event`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    /// A new message.
+    Message { message: Box<ReceivedMessage> },
+    /// Sent periodically when there are no other events, to keep the
+    /// connection alive.
+    Heartbeat,
+    /// Some other type of event, which is not supported yet.
+    #[serde(other)]
+    Other,
+}
+
+/// An event as received from the server, where the flags of a message are
+/// outside of the message.
+#[derive(Deserialize)]
+struct RawEvent {
+    id: i64,
+    #[serde(flatten)]
+    kind: EventKind,
+    #[serde(default)]
+    flags: Vec<Flag>,
+}
+
+impl From<RawEvent> for Event {
+    fn from(event: RawEvent) -> Self {
+        let RawEvent {
+            id,
+            mut kind,
+            flags,
+        } = event;
+        if let EventKind::Message { message } = &mut kind {
+            message.flags = flags;
+        }
+        Self { id, kind }
+    }
+}
+
+/// A wrapper around the response from get_events.
+#[derive(Deserialize, Debug)]
+pub(crate) struct GetEventsResponse {
+    pub events: Vec<Event>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_register_queue_request() {
+        let form = |req: &RegisterQueueRequest| {
+            let request = reqwest::Client::new()
+                .post("https://example.com/api/v1/register")
+                .form(req)
+                .build()
+                .unwrap();
+            String::from_utf8(request.body().unwrap().as_bytes().unwrap().to_vec()).unwrap()
+        };
+        let mut req = RegisterQueueRequest {
+            event_types: vec!["message".to_string(), "reaction".to_string()],
+            narrow: vec![
+                ("stream".to_string(), "general".to_string()),
+                ("topic".to_string(), "a & b".to_string()),
+            ],
+            apply_markdown: true,
+        };
+        assert_eq!(
+            form(&req),
+            "event_types=%5B%22message%22%2C%22reaction%22%5D&narrow=%5B%5B%22stream%22%2C%\
+             22general%22%5D%2C%5B%22topic%22%2C%22a+%26+b%22%5D%5D&apply_markdown=true"
+        );
+        // Empty lists are left out, so that all events are received.
+        req.event_types.clear();
+        req.narrow.clear();
+        req.apply_markdown = false;
+        assert_eq!(form(&req), "apply_markdown=false");
+    }
+
+    #[test]
+    fn test_deserialize_events() {
+        let events: Vec<Event> = serde_json::from_str(
+            r#"[{"type": "message", "id": 0, "flags": ["read", "mentioned"],
+                "message": {"id": 40, "timestamp": 1691057093, "content": "Hi",
+                    "content_type": "text/x-markdown", "avatar_url": null,
+                    "client": "website", "display_recipient": "general",
+                    "is_me_message": false, "reactions": [], "recipient_id": 10,
+                    "sender_email": "you@example.com", "sender_full_name": "You",
+                    "sender_id": 3, "sender_realm_str": "example", "stream_id": 3,
+                    "subject": "greetings", "type": "stream"}},
+                {"type": "heartbeat", "id": 1},
+                {"type": "typing", "op": "start", "id": 2}]"#,
+        )
+        .unwrap();
+        let ids: Vec<i64> = events.iter().map(|x| x.id).collect();
+        assert_eq!(ids, [0, 1, 2]);
+        let EventKind::Message { message } = &events[0].kind else {
+            panic!("Expected a message event: {:?}", events[0]);
+        };
+        assert_eq!((message.id, message.content.as_str()), (40, "Hi"));
+        assert!(message.is_read() && message.is_mentioned());
+        assert!(matches!(events[1].kind, EventKind::Heartbeat));
+        assert!(matches!(events[2].kind, EventKind::Other));
+    }
+}
@@ -1,8 +1,14 @@
 mod client;
+pub mod draft;
+pub mod emoji;
+pub mod event;
+pub mod linkifier;
 pub mod message;
 mod rc;
 pub mod stream;
+pub mod user;
 
+use std::fmt;
 use std::str::FromStr;
 
 pub use client::{Client, Error, Result};
@@ -18,6 +24,33 @@ pub enum Identifier {
     Name(String),
 }
 
+impl Identifier {
+    /// The id, if this is an `Identifier::Id`.
+    pub fn as_id(&self) -> Option<u64> {
+        match self {
+            Self::Id(id) => Some(*id),
+            Self::Name(_) => None,
+        }
+    }
+
+    /// The name, if this is an `Identifier::Name`.
+    pub fn as_name(&self) -> Option<&str> {
+        match self {
+            Self::Id(_) => None,
+            Self::Name(name) => Some(name),
+        }
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Id(id) => write!(f, "{}", id),
+            Self::Name(name) => f.write_str(name),
+        }
+    }
+}
+
 impl From<String> for Identifier {
     fn from(s: String) -> Self {
         u64::from_str(&s).map(Self::Id).unwrap_or(Self::Name(s))
@@ -31,3 +64,25 @@ impl From<&str> for Identifier {
             .unwrap_or_else(|_| Self::Name(s.to_string()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identifier() {
+        let id = Identifier::from("42");
+        assert_eq!(id.as_id(), Some(42));
+        assert_eq!(id.as_name(), None);
+        assert_eq!(id.to_string(), "42");
+
+        let name = Identifier::from("general");
+        assert_eq!(name.as_id(), None);
+        assert_eq!(name.as_name(), Some("general"));
+        assert_eq!(name.to_string(), "general");
+
+        assert_eq!(Identifier::from(String::from("7")).as_id(), Some(7));
+        assert_eq!(Identifier::from("-7").as_name(), Some("-7"));
+        assert_eq!(Identifier::from("4 2").as_name(), Some("4 2"));
+    }
+}
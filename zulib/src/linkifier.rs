@@ -0,0 +1,29 @@
+//! Types for requests and responses about linkifiers.
+use serde::{Deserialize, Serialize};
+
+/// A linkifier of the realm (organization), which turns text matching a
+/// pattern into a link.
+///
+/// Can be fetched with `crate::Client::get_linkifiers`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Linkifier {
+    /// The ID of the linkifier.
+    pub id: u64,
+    /// The Python regular expression which should be linkified, with named
+    /// groups which may be used in `url_template`.
+    pub pattern: String,
+    /// An RFC 6570 URL template for the link, like
+    /// "https://github.com/zulip/zulip/issues/{id}".
+    ///
+    /// Named `url_format_string` before Zulip 7.0 (feature level 176), in
+    /// which case it is a Python format string like
+    /// "https://github.com/zulip/zulip/issues/%(id)s".
+    #[serde(alias = "url_format_string")]
+    pub url_template: String,
+}
+
+/// A wrapper around the response from get_linkifiers.
+#[derive(Deserialize, Debug)]
+pub(crate) struct GetLinkifiersResponse {
+    pub linkifiers: Vec<Linkifier>,
+}
@@ -3,7 +3,15 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::Identifier;
 mod narrow;
+mod streaming;
 pub use narrow::Narrow;
+pub use streaming::MessageStream;
+
+/// The maximum number of messages which can be fetched in one request, that is
+/// the maximum of `num_before + num_after` in a `MessageRange`.
+///
+/// This is the limit used by the Zulip server.
+pub const MAX_MESSAGES_PER_REQUEST: u64 = 5000;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SendMessageResponse {
@@ -21,6 +29,7 @@ pub enum SendMessageRequest {
         to: Identifier,
         topic: String,
         /// The content in markdown.
+        #[clap(default_value = "")]
         content: String,
     },
     /// Make a private message.
@@ -28,15 +37,102 @@ pub enum SendMessageRequest {
         /// Either a user ID (integer), or a user name.
         to: Identifier,
         /// The content as markdown.
+        #[clap(default_value = "")]
         content: String,
     },
 }
 
+/// Schedule a message to be sent at a later time.
+#[derive(Serialize, Debug)]
+pub(crate) struct CreateScheduledMessageRequest<'a> {
+    #[serde(flatten)]
+    pub message: &'a SendMessageRequest,
+    /// The time when the message should be sent.
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub scheduled_delivery_timestamp: DateTime<Utc>,
+}
+
+/// Send a message with an id for the client's local echo of it.
+#[derive(Serialize, Debug)]
+pub(crate) struct SendMessageWithLocalIdRequest<'a> {
+    #[serde(flatten)]
+    pub message: &'a SendMessageRequest,
+    /// The id of the client's event queue.
+    pub queue_id: &'a str,
+    /// A client-chosen id of the message, unique within the queue.
+    pub local_id: &'a str,
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct CreateScheduledMessageResponse {
+    pub scheduled_message_id: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct GetScheduledMessagesResponse {
+    pub scheduled_messages: Vec<ScheduledMessage>,
+}
+
+/// A message which is scheduled to be sent in the future.
+///
+/// Can be fetched with `crate::Client::get_scheduled_messages`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduledMessage {
+    /// The unique ID of the scheduled message.
+    pub scheduled_message_id: u64,
+    /// The type of the message.
+    pub r#type: MessageType,
+    /// The recipient(s) of the message.
+    pub to: ScheduledMessageRecipient,
+    /// The topic of a stream message. Only present for stream messages.
+    pub topic: Option<String>,
+    /// The content/body of the message in markdown.
+    pub content: String,
+    /// The content of the message rendered as HTML.
+    pub rendered_content: String,
+    /// The time when the message will be sent.
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub scheduled_delivery_timestamp: DateTime<Utc>,
+    /// Whether the server has tried and failed to send the message at the
+    /// scheduled time.
+    pub failed: bool,
+}
+
+/// The recipient(s) of a scheduled message.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ScheduledMessageRecipient {
+    /// The ID of the stream for a stream message.
+    Stream(u64),
+    /// The IDs of the users for a private message.
+    Users(Vec<u64>),
+}
+
+impl SendMessageRequest {
+    /// Check that the request is valid before sending it to the server.
+    ///
+    /// Currently this only checks that stream messages have a non-empty topic,
+    /// since the server will reject those with a rather opaque error.
+    pub fn validate(&self) -> crate::Result<()> {
+        match self {
+            Self::Stream { topic, .. } if topic.trim().is_empty() => {
+                Err(crate::Error::InvalidRequest(
+                    "The topic of a stream message must not be empty".to_string(),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
 /// Type of anchor when retreiving messages.
 ///
 /// `Anchor::Newest`, `Anchor::Oldest` and `Anchor::FirstUnread` are new in
 /// Zulip 3.0 (feature level 1).
-#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+///
+/// Can be parsed from "newest", "oldest", "first_unread" (or "first-unread")
+/// or a message ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Anchor {
     /// The most recent message.
     Newest,
@@ -46,16 +142,50 @@ pub enum Anchor {
     /// most recent message.
     FirstUnread,
     /// Integer message ID to anchor fetching of new messages.
-    #[clap(skip)]
     MessageId(u64),
 }
 
+impl std::str::FromStr for Anchor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "newest" => Ok(Self::Newest),
+            "oldest" => Ok(Self::Oldest),
+            "first_unread" | "first-unread" => Ok(Self::FirstUnread),
+            _ => s.parse().map(Self::MessageId).map_err(|_| {
+                format!(
+                    "invalid anchor '{}': expected newest, oldest, first_unread or a message ID",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for Anchor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Newest => write!(f, "newest"),
+            Self::Oldest => write!(f, "oldest"),
+            Self::FirstUnread => write!(f, "first_unread"),
+            Self::MessageId(x) => write!(f, "{}", x),
+        }
+    }
+}
+
 /// Specify a range of messages.
+///
+/// When used in a `GetMessagesRequest` with `GetMessagesRequest::message_ids`
+/// set, only the narrow is sent to the server, since the anchor and the number
+/// of messages can't be combined with a list of ids.
 #[derive(Serialize, Debug, Clone, clap::Parser)]
 pub struct MessageRange {
     /// Anchor the fetching of new messages.
+    ///
+    /// One of newest, oldest, first_unread or a message ID.
     #[serde(serialize_with = "serialize_as_json_str")]
-    #[clap(short = 'c', long, value_enum, default_value_t = Anchor::Newest)]
+    #[clap(short = 'c', long, default_value_t = Anchor::Newest)]
     pub anchor: Anchor,
     /// Whether a message with the specified ID matching the narrow should be
     /// included.
@@ -93,10 +223,9 @@ pub struct MessageRange {
 }
 
 /// Get one or many messages.
-#[derive(Serialize, Debug, Clone, clap::Parser)]
+#[derive(Debug, Clone, clap::Parser)]
 pub struct GetMessagesRequest {
     #[clap(flatten)]
-    #[serde(flatten)]
     pub range: MessageRange,
     /// Whether the client supports computing gravatars URLs.
     ///
@@ -117,6 +246,16 @@ pub struct GetMessagesRequest {
     /// that user entered.
     #[clap(long)]
     pub apply_markdown: bool,
+    /// Fetch the messages with these IDs instead of a range around an anchor.
+    ///
+    /// If set, the anchor, include_anchor, num_before and num_after fields of
+    /// the range are not sent, but the narrow still filters the messages.
+    /// Messages which don't exist or which the user can't access are left out
+    /// of the response.
+    ///
+    /// New in Zulip 10.0 (feature level 300).
+    #[clap(long, value_delimiter = ',')]
+    pub message_ids: Option<Vec<u64>>,
 }
 
 /// Information about updating a flag.
@@ -208,6 +347,9 @@ pub enum AutoFlag {
 pub enum Flag {
     Editable(EditableFlag),
     Auto(AutoFlag),
+    /// A flag which is not known by this library, for instance a flag added in
+    /// a newer version of Zulip.
+    Unknown(String),
 }
 
 impl MessageRange {
@@ -229,6 +371,34 @@ impl MessageRange {
         self.narrow = Some(narrow);
         self
     }
+
+    /// Reduce `num_before` and `num_after` so that at most
+    /// `MAX_MESSAGES_PER_REQUEST` messages are requested in total.
+    /// `num_before` is kept if possible.
+    ///
+    /// Returns `true` if the range was clamped.
+    pub fn clamp(&mut self) -> bool {
+        if self.num_before.saturating_add(self.num_after) <= MAX_MESSAGES_PER_REQUEST {
+            return false;
+        }
+        self.num_before = self.num_before.min(MAX_MESSAGES_PER_REQUEST);
+        self.num_after = self
+            .num_after
+            .min(MAX_MESSAGES_PER_REQUEST - self.num_before);
+        true
+    }
+}
+
+impl UpdateFlag {
+    pub fn new(operation: FlagOperation, flag: EditableFlag) -> Self {
+        Self { operation, flag }
+    }
+}
+
+impl UpdateMessageFlagsRequest {
+    pub fn new(update: UpdateFlag, messages: Vec<u64>) -> Self {
+        Self { update, messages }
+    }
 }
 
 impl GetMessagesRequest {
@@ -237,6 +407,60 @@ impl GetMessagesRequest {
             range,
             apply_markdown: true,
             client_gravatar: true,
+            message_ids: None,
+        }
+    }
+
+    /// Create a request for the messages with some specific IDs, see
+    /// `Self::message_ids`.
+    pub fn with_ids(message_ids: Vec<u64>) -> Self {
+        Self {
+            message_ids: Some(message_ids),
+            ..Self::new(MessageRange::new(0, 0))
+        }
+    }
+}
+
+impl Serialize for GetMessagesRequest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        /// A request for a range of messages.
+        #[derive(Serialize)]
+        struct ByRange<'a> {
+            #[serde(flatten)]
+            range: &'a MessageRange,
+            client_gravatar: bool,
+            apply_markdown: bool,
+        }
+
+        /// A request for messages by their ids, which must not include the
+        /// anchor or the number of messages.
+        #[derive(Serialize)]
+        struct ByIds<'a> {
+            #[serde(serialize_with = "serialize_as_json_str")]
+            message_ids: &'a [u64],
+            #[serde(
+                serialize_with = "serialize_as_json_str",
+                skip_serializing_if = "Option::is_none"
+            )]
+            narrow: Option<&'a Vec<Narrow>>,
+            client_gravatar: bool,
+            apply_markdown: bool,
+        }
+
+        match &self.message_ids {
+            None => ByRange {
+                range: &self.range,
+                client_gravatar: self.client_gravatar,
+                apply_markdown: self.apply_markdown,
+            }
+            .serialize(serializer),
+            Some(message_ids) => ByIds {
+                message_ids,
+                narrow: self.range.narrow.as_ref(),
+                client_gravatar: self.client_gravatar,
+                apply_markdown: self.apply_markdown,
+            }
+            .serialize(serializer),
         }
     }
 }
@@ -254,15 +478,28 @@ impl Serialize for Anchor {
     }
 }
 
+/// The response of a render_message request.
+#[derive(Deserialize, Debug)]
+pub(crate) struct RenderMessageResponse {
+    /// The rendered HTML.
+    pub rendered: String,
+}
+
 /// The response of a get_messages request.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GetMessagesResponse {
     /// The same anchor specified in the request (or the computed one, if
     /// `GetMessagesRequest::anchor` was set to `Anchor::FirstUnread`).
+    ///
+    /// This and the other "found" fields are not sent by the server if the
+    /// messages were requested by `GetMessagesRequest::message_ids`, in which
+    /// case they are 0 and false.
+    #[serde(default)]
     pub anchor: u64,
     /// Whether the messages list includes the very newest messages matching the
     /// narrow (used by clients that paginate their requests to decide
     /// whether there are more messages to fetch).
+    #[serde(default)]
     pub found_newest: bool,
     /// Whether the messages list includes the very oldest messages matching the
     /// narrow (used by clients that paginate their requests to decide
@@ -272,6 +509,7 @@ pub struct GetMessagesResponse {
     /// with the ID specified in the request does not exist, did not match
     /// the narrow, or was excluded via include_anchor=false, this will be
     /// false.
+    #[serde(default)]
     pub found_anchor: bool,
     /// Whether the message history was limited due to plan restrictions.
     ///
@@ -335,6 +573,10 @@ pub struct ReceivedMessage {
     pub subject: String,
     pub r#type: MessageType,
     /// The user's message flags for the message.
+    ///
+    /// Missing in the message of a message event, where the flags are given
+    /// alongside the message instead, see `crate::event::Event`.
+    #[serde(default)]
     pub flags: Vec<Flag>,
     /// (Only present if keyword search was included among the narrow
     /// parameters.) HTML content of a queried message that matches the
@@ -348,6 +590,47 @@ pub struct ReceivedMessage {
     pub match_subject: Option<String>,
 }
 
+impl ReceivedMessage {
+    /// Whether the user has read the message.
+    pub fn is_read(&self) -> bool {
+        self.flags
+            .iter()
+            .any(|x| matches!(x, Flag::Editable(EditableFlag::Read)))
+    }
+
+    /// Whether the user has starred the message.
+    pub fn is_starred(&self) -> bool {
+        self.flags
+            .iter()
+            .any(|x| matches!(x, Flag::Editable(EditableFlag::Starred)))
+    }
+
+    /// Whether the user was mentioned by the message, either directly or via a
+    /// user group. Wildcard mentions like @**all** are not considered.
+    pub fn is_mentioned(&self) -> bool {
+        self.flags
+            .iter()
+            .any(|x| matches!(x, Flag::Auto(AutoFlag::Mentioned)))
+    }
+
+    /// The time when the message was sent, in the time zone `tz`.
+    pub fn timestamp_in<Tz: TimeZone>(&self, tz: &Tz) -> DateTime<Tz> {
+        self.timestamp.with_timezone(tz)
+    }
+
+    /// The time when the message was sent, in the local time zone.
+    pub fn timestamp_local(&self) -> DateTime<Local> {
+        self.timestamp_in(&Local)
+    }
+
+    /// The time when the message was last edited, in the local time zone, or
+    /// `None` if the message hasn't been edited.
+    pub fn last_edit_timestamp_local(&self) -> Option<DateTime<Local>> {
+        self.last_edit_timestamp
+            .map(|timestamp| timestamp.with_timezone(&Local))
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum MessageType {
@@ -377,7 +660,7 @@ pub struct DisplayRecipientPrivateMessage {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UpdateMessageFlagsResponse {
     /// A vector with the IDs of the modified messages.
-    messages: Vec<u64>,
+    pub messages: Vec<u64>,
 }
 
 /// The response for a update message flags for narrow request.
@@ -455,6 +738,30 @@ pub struct EditHistory {
     pub prev_topic: Option<String>,
 }
 
+/// The response of a get_message request.
+#[derive(Deserialize, Debug)]
+pub(crate) struct GetMessageResponse {
+    pub message: ReceivedMessage,
+    /// The raw Markdown content of the message. Deprecated and not sent by
+    /// newer servers.
+    #[serde(default)]
+    pub raw_content: Option<String>,
+}
+
+/// The response of a get_message_history request.
+#[derive(Deserialize, Debug)]
+pub(crate) struct GetMessageHistoryResponse {
+    /// Snapshots of the message, sorted from the oldest to the newest.
+    pub message_history: Vec<EditHistory>,
+}
+
+/// The response of a get_read_receipts request.
+#[derive(Deserialize, Debug)]
+pub(crate) struct GetReadReceiptsResponse {
+    /// The IDs of the users who have read the message.
+    pub user_ids: Vec<u64>,
+}
+
 /// A reaction to a message.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Reaction {
@@ -468,11 +775,16 @@ pub struct Reaction {
 pub struct EditMessageRequest {
     #[serde(skip_serializing)]
     pub(crate) message_id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
     topic: Option<String>,
     propagate_mode: PropagateMode,
     send_notification_to_old_thread: bool,
     send_notification_to_new_thread: bool,
+    /// The new content of the message. If `None`, the content is left
+    /// unchanged.
+    #[serde(skip_serializing_if = "Option::is_none")]
     content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     stream_id: Option<i64>,
 }
 
@@ -512,6 +824,16 @@ impl EditMessageRequest {
         self.stream_id = Some(stream_id);
         self
     }
+    /// Move the message to another stream and topic, keeping its content.
+    ///
+    /// The propagate mode will be set to `PropagateMode::ChangeAll`, so that
+    /// all messages in the topic are moved. Use `Self::propagate_mode` after
+    /// this to change that.
+    pub fn move_to(&mut self, stream_id: i64, topic: &str) -> &mut Self {
+        self.stream_id(stream_id)
+            .topic(topic)
+            .propagate_mode(PropagateMode::ChangeAll)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -581,11 +903,16 @@ pub enum ReactionType {
     ZulipExtraEmoji,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Which messages should be edited when the topic or stream of a message is
+/// changed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
 #[serde(rename_all = "snake_case")]
 pub enum PropagateMode {
+    /// Only the target message.
     ChangeOne,
+    /// All messages in the topic, both before and after the target message.
     ChangeAll,
+    /// The target message and all following messages in the topic.
     ChangeLater,
 }
 
@@ -595,18 +922,208 @@ fn deserialize_timestamp_to_option<'de, D: Deserializer<'de>>(
     chrono::serde::ts_seconds::deserialize(deserializer).map(Option::Some)
 }
 
-fn serialize_as_json_str<S: Serializer, T: Serialize>(
+/// Serialize a value as a string with the value in JSON, as the API expects
+/// for lists and objects in query parameters and forms.
+///
+/// A value which is itself a string is serialized as is, without the quotes
+/// and escapes of a JSON string.
+pub(crate) fn serialize_as_json_str<S: Serializer, T: Serialize>(
     value: &T,
     serializer: S,
 ) -> Result<S::Ok, S::Error> {
-    let ser_json =
-        serde_json::to_string(value).map_err(|e| <S::Error as serde::ser::Error>::custom(e))?;
-    // This is a real hack: If the serialized json happens to be a string, we don't
-    // want to include the quotes.
-    let relevant_str = if ser_json.starts_with('"') && ser_json.ends_with('"') {
-        &ser_json[1..ser_json.len() - 1]
+    use serde::ser::Error;
+
+    let json = serde_json::to_string(value).map_err(S::Error::custom)?;
+    // Only a JSON string starts with a quote. It is parsed back to undo the
+    // escaping. Other values are not converted to a `serde_json::Value`, since
+    // that would reorder the fields of objects.
+    if json.starts_with('"') {
+        let s: String = serde_json::from_str(&json).map_err(S::Error::custom)?;
+        serializer.serialize_str(&s)
     } else {
-        &ser_json
-    };
-    serializer.serialize_str(&relevant_str)
+        serializer.serialize_str(&json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deserialize a stream message with some flags.
+    fn message_with_flags(flags: &str) -> ReceivedMessage {
+        serde_json::from_str(&format!(
+            r#"{{"id": 1, "timestamp": 1691057093, "content": "Hello",
+                "content_type": "text/x-markdown", "avatar_url": null,
+                "client": "website", "display_recipient": "general",
+                "is_me_message": false, "reactions": [], "recipient_id": 10,
+                "sender_email": "you@example.com", "sender_full_name": "You",
+                "sender_id": 3, "sender_realm_str": "example", "stream_id": 1,
+                "subject": "greetings", "type": "stream", "flags": {flags}}}"#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_flag_predicates() {
+        let message = message_with_flags(r#"["read", "mentioned"]"#);
+        assert!(message.is_read());
+        assert!(!message.is_starred());
+        assert!(message.is_mentioned());
+
+        let message = message_with_flags(r#"["starred", "wildcard_mentioned"]"#);
+        assert!(!message.is_read());
+        assert!(message.is_starred());
+        assert!(!message.is_mentioned());
+    }
+
+    #[test]
+    fn test_unknown_flag() {
+        let message = message_with_flags(r#"["read", "some_future_flag"]"#);
+        assert!(message.is_read());
+        assert!(matches!(&message.flags[1], Flag::Unknown(x) if x == "some_future_flag"));
+        // Unknown flags should be serialized as they were.
+        assert_eq!(
+            serde_json::to_string(&message.flags).unwrap(),
+            r#"["read","some_future_flag"]"#
+        );
+    }
+
+    #[test]
+    fn test_serialize_as_json_str() {
+        #[derive(Serialize)]
+        struct JsonStr<T: Serialize>(#[serde(serialize_with = "serialize_as_json_str")] T);
+        fn json_str<T: Serialize>(value: T) -> serde_json::Value {
+            serde_json::to_value(JsonStr(value)).unwrap()
+        }
+
+        assert_eq!(json_str(Anchor::Newest), "newest");
+        assert_eq!(json_str(Anchor::MessageId(42)), "42");
+        assert_eq!(
+            json_str(vec![Narrow::parse("stream:general")]),
+            r#"[{"operator":"stream","operand":"general","negated":false}]"#
+        );
+        // A string is not escaped, and quotes within it are kept.
+        let quoted = Narrow::parse(r#"search:"a \ b""#).operand;
+        assert_eq!(json_str(quoted.clone()), quoted);
+        // Neither is a list which happens to start and end with quoted strings
+        // unwrapped.
+        assert_eq!(json_str(vec!["a", "b"]), r#"["a","b"]"#);
+        assert_eq!(json_str(vec![r#"say "hi""#]), r#"["say \"hi\""]"#);
+    }
+
+    #[test]
+    fn test_serialize_message_ids() {
+        let query = |req: &GetMessagesRequest| {
+            let url = reqwest::Client::new()
+                .get("https://example.com/api/v1/messages")
+                .query(req)
+                .build()
+                .unwrap()
+                .url()
+                .clone();
+            url.query_pairs()
+                .map(|(k, v)| (k.into_owned(), v.into_owned()))
+                .collect::<Vec<_>>()
+        };
+        let pair = |k: &str, v: &str| (k.to_string(), v.to_string());
+
+        let mut req = GetMessagesRequest::with_ids(vec![40, 41, 45]);
+        assert_eq!(
+            query(&req),
+            [
+                pair("message_ids", "[40,41,45]"),
+                pair("client_gravatar", "true"),
+                pair("apply_markdown", "true"),
+            ]
+        );
+        req.range.narrow = Some(vec![Narrow::is_starred()]);
+        assert!(query(&req).contains(&pair(
+            "narrow",
+            r#"[{"operator":"is","operand":"starred","negated":false}]"#
+        )));
+
+        let req = GetMessagesRequest::new(MessageRange::new(3, 4));
+        let query = query(&req);
+        assert!(query.contains(&pair("anchor", "newest")));
+        assert!(query.contains(&pair("num_before", "3")));
+        assert!(!query.iter().any(|(k, _)| k == "message_ids"));
+    }
+
+    #[test]
+    fn test_parse_anchor() {
+        assert_eq!("newest".parse(), Ok(Anchor::Newest));
+        assert_eq!("oldest".parse(), Ok(Anchor::Oldest));
+        assert_eq!("first_unread".parse(), Ok(Anchor::FirstUnread));
+        assert_eq!("first-unread".parse(), Ok(Anchor::FirstUnread));
+        assert_eq!("12345".parse(), Ok(Anchor::MessageId(12345)));
+        assert!("latest".parse::<Anchor>().is_err());
+        assert!("-1".parse::<Anchor>().is_err());
+
+        use clap::Parser;
+        let range = MessageRange::try_parse_from(["ls", "-c", "12345"]).unwrap();
+        assert_eq!(range.anchor, Anchor::MessageId(12345));
+        let range = MessageRange::try_parse_from(["ls"]).unwrap();
+        assert_eq!(range.anchor, Anchor::Newest);
+        assert!(MessageRange::try_parse_from(["ls", "-c", "nope"]).is_err());
+    }
+
+    #[test]
+    fn test_reactions_and_private_recipients() {
+        let message: ReceivedMessage = serde_json::from_str(
+            r#"{"id": 2, "timestamp": 1691057093, "content": "Hi",
+                "content_type": "text/x-markdown", "avatar_url": null,
+                "client": "website", "display_recipient": [
+                    {"id": 3, "email": "you@example.com", "full_name": "You",
+                        "is_mirror_dummy": false},
+                    {"id": 4, "email": "me@example.com", "full_name": "Me",
+                        "is_mirror_dummy": false}
+                ],
+                "is_me_message": false, "recipient_id": 11,
+                "reactions": [
+                    {"emoji_code": "1f44d", "emoji_name": "+1",
+                        "reaction_type": "unicode_emoji", "user_id": 4},
+                    {"emoji_code": "1", "emoji_name": "green_tick",
+                        "reaction_type": "realm_emoji", "user_id": 3}
+                ],
+                "sender_email": "you@example.com", "sender_full_name": "You",
+                "sender_id": 3, "sender_realm_str": "example", "subject": "",
+                "type": "private", "flags": []}"#,
+        )
+        .unwrap();
+        let reactions: Vec<_> = message
+            .reactions
+            .iter()
+            .map(|x| (x.emoji_name.as_str(), x.reaction_type.as_str(), x.user_id))
+            .collect();
+        assert_eq!(
+            reactions,
+            [("+1", "unicode_emoji", 4), ("green_tick", "realm_emoji", 3)]
+        );
+        assert_eq!(message.reactions[0].emoji_code, "1f44d");
+        let DisplayRecipient::PrivateMessage(recipients) = &message.display_recipient else {
+            panic!("Expected private message recipients");
+        };
+        assert_eq!(recipients[1].id, 4);
+        assert_eq!(recipients[1].full_name, "Me");
+        assert!(!recipients[1].is_mirror_dummy);
+    }
+
+    #[test]
+    fn test_timestamp_in_time_zone() {
+        let message = message_with_flags("[]");
+        // 1691057093 is 2023-08-03 10:04:53 UTC.
+        let offset = FixedOffset::east_opt(2 * 3600).unwrap();
+        assert_eq!(
+            message.timestamp_in(&offset).to_rfc3339(),
+            "2023-08-03T12:04:53+02:00"
+        );
+        let offset = FixedOffset::west_opt(5 * 3600 + 30 * 60).unwrap();
+        assert_eq!(
+            message.timestamp_in(&offset).to_rfc3339(),
+            "2023-08-03T04:34:53-05:30"
+        );
+        // The local time is the same instant regardless of the time zone.
+        assert_eq!(message.timestamp_local(), message.timestamp);
+        assert_eq!(message.last_edit_timestamp_local(), None);
+    }
 }
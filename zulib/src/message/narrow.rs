@@ -1,4 +1,6 @@
 const SEARCH_OPERATOR: &str = "search";
+const IS_OPERATOR: &str = "is";
+const STREAM_OPERATOR: &str = "stream";
 
 /// A filter for Zulip messages.
 ///
@@ -8,7 +10,10 @@ const SEARCH_OPERATOR: &str = "search";
 /// in the API for fetching messages).
 ///
 /// Read more about narrows [here](https://zulip.com/api/construct-narrow).
-#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+///
+/// When serialized, a stream operand which is a number is written as an
+/// integer so that the server interprets it as a stream id rather than a name.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Narrow {
     pub operator: String,
     pub operand: String,
@@ -41,6 +46,54 @@ impl Narrow {
         }
     }
 
+    /// Create an "is" narrow, like "is:starred", filtering messages by some
+    /// property.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use zulib::message::Narrow;
+    ///
+    /// assert_eq!(
+    ///     Narrow::is("private").negated(),
+    ///     Narrow {
+    ///         operator: "is".to_string(),
+    ///         operand: "private".to_string(),
+    ///         negated: true
+    ///     },
+    /// );
+    /// ```
+    pub fn is(value: &str) -> Self {
+        Self {
+            operator: IS_OPERATOR.to_string(),
+            operand: value.to_string(),
+            negated: false,
+        }
+    }
+
+    /// Create a narrow for starred messages, "is:starred".
+    pub fn is_starred() -> Self {
+        Self::is("starred")
+    }
+
+    /// Create a narrow for unread messages, "is:unread".
+    pub fn is_unread() -> Self {
+        Self::is("unread")
+    }
+
+    /// Create a narrow for messages where the user is mentioned,
+    /// "is:mentioned".
+    pub fn is_mentioned() -> Self {
+        Self::is("mentioned")
+    }
+
+    /// Negate the narrow, so that it matches all messages that the original
+    /// narrow doesn't match.
+    pub fn negated(mut self) -> Self {
+        self.negated = !self.negated;
+        self
+    }
+
     /// Parse a filter on the form "[-]<FILTERNAME>:<VALUE>" or a keyword
     /// otherwise.
     ///
@@ -100,3 +153,61 @@ impl Narrow {
         }
     }
 }
+
+impl serde::Serialize for Narrow {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("Narrow", 3)?;
+        state.serialize_field("operator", &self.operator)?;
+        match self.operand.parse::<u64>() {
+            Ok(stream_id) if self.operator == STREAM_OPERATOR => {
+                state.serialize_field("operand", &stream_id)?
+            }
+            _ => state.serialize_field("operand", &self.operand)?,
+        }
+        state.serialize_field("negated", &self.negated)?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_narrows() {
+        for (narrow, operand) in [
+            (Narrow::is_starred(), "starred"),
+            (Narrow::is_unread(), "unread"),
+            (Narrow::is_mentioned(), "mentioned"),
+            (Narrow::is("alerted"), "alerted"),
+        ] {
+            assert_eq!(
+                serde_json::to_string(&narrow).unwrap(),
+                format!(r#"{{"operator":"is","operand":"{operand}","negated":false}}"#)
+            );
+            assert_eq!(narrow, Narrow::parse(&format!("is:{operand}")));
+            let negated = narrow.clone().negated();
+            assert!(negated.negated);
+            assert_eq!(negated, Narrow::parse(&format!("-is:{operand}")));
+            assert_eq!(negated.negated(), narrow);
+        }
+    }
+
+    #[test]
+    fn test_serialize_stream_id() {
+        assert_eq!(
+            serde_json::to_string(&Narrow::parse("stream:5")).unwrap(),
+            r#"{"operator":"stream","operand":5,"negated":false}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Narrow::parse("stream:general")).unwrap(),
+            r#"{"operator":"stream","operand":"general","negated":false}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Narrow::parse("-streams:public")).unwrap(),
+            r#"{"operator":"streams","operand":"public","negated":true}"#
+        );
+    }
+}
@@ -0,0 +1,197 @@
+//! Incremental parsing of the messages in a response to get_messages.
+
+use std::fmt;
+use std::io::{self, Read};
+
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use super::ReceivedMessage;
+use crate::{Error, Result};
+
+/// The number of parsed messages which may be buffered before the parsing
+/// waits for them to be consumed.
+const BUFFERED_MESSAGES: usize = 64;
+
+/// The number of downloaded chunks of the response body which may be buffered
+/// before the download waits for them to be parsed.
+const BUFFERED_CHUNKS: usize = 16;
+
+/// An asynchronous iterator over the messages in a response to get_messages.
+///
+/// The messages are parsed one at a time while the response body is
+/// downloaded, so the whole response is never kept in memory. Created by
+/// `crate::Client::get_messages_stream`.
+#[derive(Debug)]
+pub struct MessageStream {
+    messages: Receiver<Result<ReceivedMessage>>,
+}
+
+impl MessageStream {
+    /// Start downloading and parsing the body of `response`.
+    ///
+    /// Must be called within a Tokio runtime.
+    pub(crate) fn new(response: reqwest::Response) -> Self {
+        let (chunk_tx, chunk_rx) = mpsc::channel(BUFFERED_CHUNKS);
+        let (message_tx, messages) = mpsc::channel(BUFFERED_MESSAGES);
+        tokio::spawn(download_body(response, chunk_tx));
+        tokio::task::spawn_blocking(move || {
+            parse_messages(
+                &mut ChunkReader {
+                    chunks: chunk_rx,
+                    chunk: io::Cursor::default(),
+                    error: None,
+                },
+                &message_tx,
+            )
+        });
+        Self { messages }
+    }
+
+    /// Get the next message, or `None` when all messages have been yielded.
+    ///
+    /// If an error is returned, because the server responded with an error or
+    /// the response could not be downloaded or parsed, no more messages follow.
+    pub async fn next(&mut self) -> Option<Result<ReceivedMessage>> {
+        self.messages.recv().await
+    }
+}
+
+/// Send the chunks of the response body until it ends, an error occurs or the
+/// parsing stops.
+async fn download_body(mut response: reqwest::Response, chunks: Sender<reqwest::Result<Vec<u8>>>) {
+    loop {
+        let chunk = match response.chunk().await {
+            Ok(Some(chunk)) => Ok(chunk.to_vec()),
+            Ok(None) => return,
+            Err(e) => Err(e),
+        };
+        let failed = chunk.is_err();
+        if chunks.send(chunk).await.is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// A blocking reader over the chunks sent by `download_body`.
+struct ChunkReader {
+    chunks: Receiver<reqwest::Result<Vec<u8>>>,
+    chunk: io::Cursor<Vec<u8>>,
+    /// The error if the download failed.
+    error: Option<reqwest::Error>,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.chunk.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            match self.chunks.blocking_recv() {
+                Some(Ok(chunk)) => self.chunk = io::Cursor::new(chunk),
+                Some(Err(e)) => {
+                    self.error = Some(e);
+                    return Err(io::Error::other("Failed to download the response body"));
+                }
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Parse the response body from `reader` and send every message in it, and
+/// finally an error if the request was unsuccessful.
+fn parse_messages(reader: &mut ChunkReader, messages: &Sender<Result<ReceivedMessage>>) {
+    let result = {
+        let mut deserializer = serde_json::Deserializer::from_reader(&mut *reader);
+        deserializer
+            .deserialize_map(ResponseVisitor { messages })
+            .and_then(|result| deserializer.end().map(|()| result))
+    };
+    let error = match result {
+        Ok(Ok(())) => return,
+        Ok(Err(e)) => e,
+        Err(e) if e.is_io() => match reader.error.take() {
+            Some(e) => Error::Network(e),
+            None => Error::BadResponse(e),
+        },
+        Err(e) => Error::BadResponse(e),
+    };
+    // Nothing to do if the stream has been dropped.
+    let _ = messages.blocking_send(Err(error));
+}
+
+/// Visits the top level object of the response, sending the messages as they
+/// are parsed.
+///
+/// Results in the error from the server if the request was unsuccessful.
+struct ResponseVisitor<'a> {
+    messages: &'a Sender<Result<ReceivedMessage>>,
+}
+
+impl<'de, 'a> Visitor<'de> for ResponseVisitor<'a> {
+    type Value = Result<()>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a response object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let (mut result, mut code, mut msg, mut stream) = (None, None, None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "result" => result = Some(map.next_value::<String>()?),
+                "code" => code = Some(map.next_value()?),
+                "msg" => msg = Some(map.next_value()?),
+                "stream" => stream = map.next_value()?,
+                "messages" => map.next_value_seed(MessagesSeed {
+                    messages: self.messages,
+                })?,
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        match result.as_deref() {
+            Some("success") => Ok(Ok(())),
+            Some("error") => Ok(Err(Error::Unsuccessful {
+                code: code.ok_or_else(|| de::Error::missing_field("code"))?,
+                msg: msg.ok_or_else(|| de::Error::missing_field("msg"))?,
+                stream,
+            })),
+            Some(other) => Err(de::Error::unknown_variant(other, &["success", "error"])),
+            None => Err(de::Error::missing_field("result")),
+        }
+    }
+}
+
+/// Parses the array of messages and sends every message.
+struct MessagesSeed<'a> {
+    messages: &'a Sender<Result<ReceivedMessage>>,
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for MessagesSeed<'a> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for MessagesSeed<'a> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an array of messages")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(message) = seq.next_element::<ReceivedMessage>()? {
+            if self.messages.blocking_send(Ok(message)).is_err() {
+                return Err(de::Error::custom("The message stream was dropped"));
+            }
+        }
+        Ok(())
+    }
+}
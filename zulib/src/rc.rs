@@ -1,7 +1,15 @@
+use std::path::Path;
+
+use anyhow::Context;
 use pest::Parser;
 use pest_derive::*;
 use serde::Deserialize;
 
+/// The environment variables read by `ZulipRc::from_env`.
+const EMAIL_VAR: &str = "ZULIP_EMAIL";
+const KEY_VAR: &str = "ZULIP_API_KEY";
+const SITE_VAR: &str = "ZULIP_SITE";
+
 #[derive(Parser)]
 #[grammar = "rc.pest"]
 struct INIParser;
@@ -19,45 +27,131 @@ pub struct ZulipRc {
     pub site: String,
 }
 
+/// Check that a site is an http(s) URL and remove any trailing slashes, so
+/// that API endpoints can be appended to it.
+pub(crate) fn normalize_site(site: &str) -> anyhow::Result<String> {
+    let url = reqwest::Url::parse(site).with_context(|| format!("Invalid site URL: {}", site))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        anyhow::bail!("The site must be an http or https URL, got: {}", site);
+    }
+    Ok(site.trim_end_matches('/').to_string())
+}
+
+/// The response from fetch_api_key.
+#[derive(Deserialize, Debug)]
+pub(crate) struct FetchApiKeyResponse {
+    pub api_key: String,
+}
+
 impl ZulipRc {
+    /// Parse the content of a .zuliprc file.
+    ///
+    /// The email, key and site are read from the `[api]` section. Other
+    /// sections, unknown keys and comment lines starting with ';' or '#' are
+    /// ignored.
     pub fn parse_from_str(rc: &str) -> anyhow::Result<Self> {
         let pairs = INIParser::parse(Rule::file, rc)?;
-        let mut email = "";
-        let mut key = "";
-        let mut site = "";
+        let mut found_api_section = false;
+        let mut in_api_section = false;
+        let mut email = None;
+        let mut key = None;
+        let mut site = None;
         for pair in pairs {
             // A pair is a combination of the rule which matched and a span of input
             for inner_pair in pair.into_inner() {
                 match inner_pair.as_rule() {
                     Rule::section => {
-                        if inner_pair.as_str() != "[api]" {
-                            panic!("not valid section")
-                        }
+                        in_api_section = inner_pair.as_str() == "[api]";
+                        found_api_section |= in_api_section;
                     }
-                    Rule::property => {
+                    Rule::property if in_api_section => {
                         let mut rule = inner_pair.into_inner();
                         let name: &str = rule.next().unwrap().as_str();
-                        if name == "email" {
-                            email = rule.next().unwrap().as_str();
-                        }
-                        if name == "key" {
-                            key = rule.next().unwrap().as_str();
-                        }
-                        if name == "site" {
-                            site = rule.next().unwrap().as_str();
+                        let value = rule.next().unwrap().as_str();
+                        match name {
+                            "email" => email = Some(value),
+                            "key" => key = Some(value),
+                            "site" => site = Some(value),
+                            _ => (),
                         }
                     }
+                    Rule::property => (),
                     Rule::EOI => break,
                     _ => unreachable!(),
                 };
             }
         }
+        if !found_api_section {
+            anyhow::bail!("No [api] section found");
+        }
+        let get = |value: Option<&str>, name| {
+            value
+                .map(str::to_string)
+                .with_context(|| format!("Missing {} in the [api] section", name))
+        };
         Ok(Self {
-            email: email.to_string(),
-            key: key.to_string(),
-            site: site.to_string(),
+            email: get(email, "email")?,
+            key: get(key, "key")?,
+            site: normalize_site(&get(site, "site")?)?,
         })
     }
+
+    /// Serialize the configuration to the content of a .zuliprc file, with an
+    /// `[api]` section containing the email, key and site.
+    ///
+    /// This is the inverse of `ZulipRc::parse_from_str`.
+    pub fn to_rc_string(&self) -> String {
+        format!(
+            "[api]\nemail={}\nkey={}\nsite={}\n",
+            self.email, self.key, self.site
+        )
+    }
+
+    /// Read the configuration from the environment variables `ZULIP_EMAIL`,
+    /// `ZULIP_API_KEY` and `ZULIP_SITE`.
+    ///
+    /// Fails with an error naming the missing variables if any of them is not
+    /// set.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut missing = Vec::new();
+        let mut var = |name| {
+            std::env::var(name).unwrap_or_else(|_| {
+                missing.push(name);
+                String::new()
+            })
+        };
+        let rc = Self {
+            email: var(EMAIL_VAR),
+            key: var(KEY_VAR),
+            site: var(SITE_VAR),
+        };
+        if !missing.is_empty() {
+            anyhow::bail!("Missing environment variable(s): {}", missing.join(", "));
+        }
+        Ok(Self {
+            site: normalize_site(&rc.site)?,
+            ..rc
+        })
+    }
+
+    /// Parse a .zuliprc file at `path`, or read the configuration from the
+    /// environment (see `ZulipRc::from_env`) if there is no such file.
+    pub fn parse_or_env(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(rc) => Self::parse_from_str(&rc)
+                .with_context(|| format!("Failed to parse {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Self::from_env().with_context(|| {
+                    format!(
+                        "No file at {} and no configuration in the environment",
+                        path.display()
+                    )
+                })
+            }
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -84,4 +178,97 @@ mod tests {
             ZulipRc { email, key, site }
         );
     }
+
+    #[test]
+    fn test_to_rc_string() {
+        let rc = ZulipRc {
+            email: "me@example.com".to_string(),
+            key: "1aBC9afGhIjKLmNoPqR45Stuv09WvXyZ".to_string(),
+            site: "https://leanprover.zulipchat.com".to_string(),
+        };
+        assert_eq!(ZulipRc::parse_from_str(&rc.to_rc_string()).unwrap(), rc);
+    }
+
+    #[test]
+    fn test_parse_with_extra_content() {
+        let rc = ZulipRc::parse_from_str(indoc::indoc! {"
+            ; A comment.
+            [zulip]
+            email=wrong@example.com
+            # Another comment.
+            [api]
+            email=me@example.com
+            insecure=false
+            key=abc
+            site=https://example.com
+            [other]
+            key=wrong
+        "})
+        .unwrap();
+        assert_eq!(
+            rc,
+            ZulipRc {
+                email: "me@example.com".to_string(),
+                key: "abc".to_string(),
+                site: "https://example.com".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_normalize_site() {
+        let rc = ZulipRc::parse_from_str("[api]\nemail=a\nkey=b\nsite=https://example.com/\n");
+        assert_eq!(rc.unwrap().site, "https://example.com");
+        assert!(ZulipRc::parse_from_str("[api]\nemail=a\nkey=b\nsite=example.com\n").is_err());
+        assert!(normalize_site("ftp://example.com").is_err());
+        assert_eq!(
+            normalize_site("http://localhost:9991/").unwrap(),
+            "http://localhost:9991"
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = ZulipRc::parse_from_str("[zulip]\nemail=me@example.com\n").unwrap_err();
+        assert_eq!(err.to_string(), "No [api] section found");
+        let err = ZulipRc::parse_from_str("[api]\nemail=me@example.com\nsite=x\n").unwrap_err();
+        assert_eq!(err.to_string(), "Missing key in the [api] section");
+    }
+
+    #[test]
+    fn test_from_env() {
+        // Environment variables are shared between all tests, so all checks
+        // involving them are done in this single test.
+        let rc = ZulipRc {
+            email: "me@example.com".to_string(),
+            key: "1aBC9afGhIjKLmNoPqR45Stuv09WvXyZ".to_string(),
+            site: "https://leanprover.zulipchat.com".to_string(),
+        };
+        std::env::set_var(EMAIL_VAR, &rc.email);
+        std::env::set_var(KEY_VAR, &rc.key);
+        std::env::set_var(SITE_VAR, &rc.site);
+        assert_eq!(ZulipRc::from_env().unwrap(), rc);
+
+        let dir = std::env::temp_dir();
+        let missing_file = dir.join(format!("zuliprc-missing-{}", std::process::id()));
+        assert_eq!(ZulipRc::parse_or_env(&missing_file).unwrap(), rc);
+        // An existing file takes precedence over the environment.
+        let file = dir.join(format!("zuliprc-{}", std::process::id()));
+        std::fs::write(
+            &file,
+            "[api]\nemail=you@example.com\nkey=abc\nsite=https://example.com\n",
+        )
+        .unwrap();
+        let from_file = ZulipRc::parse_or_env(&file);
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(from_file.unwrap().email, "you@example.com");
+
+        std::env::remove_var(KEY_VAR);
+        std::env::remove_var(SITE_VAR);
+        let err = ZulipRc::from_env().unwrap_err().to_string();
+        assert!(err.contains(KEY_VAR) && err.contains(SITE_VAR), "{}", err);
+        assert!(!err.contains(EMAIL_VAR), "{}", err);
+        assert!(ZulipRc::parse_or_env(&missing_file).is_err());
+        std::env::remove_var(EMAIL_VAR);
+    }
 }
@@ -1,4 +1,6 @@
 //! Types for requests and responses about streams.
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::ops::Deref;
 
 use chrono::prelude::*;
@@ -163,7 +165,8 @@ pub struct Subscription {
     /// Whether the stream has been configured to allow unauthenticated access
     /// to its message history from the web.
     pub is_web_public: bool,
-    /// The user's personal color for the stream.
+    /// The user's personal color for the stream, as a hex string like
+    /// "#76ce90". See `Self::color_rgb` for a parsed version.
     pub color: String,
     /// The average number of messages sent to the stream in recent weeks,
     /// rounded to the nearest integer.
@@ -171,6 +174,31 @@ pub struct Subscription {
     /// `None` means the stream was recently created and there is insufficient
     /// data to estimate the average traffic.
     pub stream_weekly_trafic: Option<u64>,
+    /// The ids of all users subscribed to the stream.
+    ///
+    /// Only present if requested with
+    /// `crate::Client::get_subscriptions(true)`, empty otherwise.
+    #[serde(default)]
+    pub subscribers: Vec<u64>,
+}
+
+impl Stream {
+    /// Compare the names of two streams case insensitively, for sorting
+    /// streams alphabetically.
+    ///
+    /// Names which only differ in case are ordered by their exact names.
+    pub fn cmp_by_name(&self, other: &Self) -> Ordering {
+        self.name
+            .to_lowercase()
+            .cmp(&other.name.to_lowercase())
+            .then_with(|| self.name.cmp(&other.name))
+    }
+
+    /// Compare the creation dates of two streams, with the oldest stream
+    /// first.
+    pub fn cmp_by_creation(&self, other: &Self) -> Ordering {
+        self.date_created.cmp(&other.date_created)
+    }
 }
 
 impl Deref for Subscription {
@@ -180,6 +208,201 @@ impl Deref for Subscription {
     }
 }
 
+impl Subscription {
+    /// Compare the weekly traffic of two subscribed streams, with the least
+    /// active stream first.
+    ///
+    /// Streams with unknown traffic, which are recently created, come before
+    /// all other streams.
+    pub fn cmp_by_traffic(&self, other: &Self) -> Ordering {
+        self.stream_weekly_trafic.cmp(&other.stream_weekly_trafic)
+    }
+
+    /// Parse the user's personal color for the stream.
+    ///
+    /// Returns `None` if the color is not a valid hex color.
+    pub fn color_rgb(&self) -> Option<Color> {
+        Color::from_hex(&self.color)
+    }
+}
+
+/// A color in RGB format.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    /// Parse a hex color like "#76ce90", or the short form "#7c9" which is
+    /// equivalent to "#77cc99".
+    ///
+    /// Returns `None` if the string is not a valid hex color.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let digits = hex.strip_prefix('#')?;
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |i: usize, len: usize| {
+            let value = u8::from_str_radix(&digits[i * len..(i + 1) * len], 16).unwrap();
+            // Expand a single digit, like 0xa to 0xaa.
+            if len == 1 {
+                value * 0x11
+            } else {
+                value
+            }
+        };
+        let len = match digits.len() {
+            3 => 1,
+            6 => 2,
+            _ => return None,
+        };
+        Some(Self {
+            r: channel(0, len),
+            g: channel(1, len),
+            b: channel(2, len),
+        })
+    }
+
+    /// Format the color as a hex color like "#76ce90".
+    pub fn to_hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+/// Changes to the settings of a subscription, for
+/// `crate::Client::update_subscription_settings`.
+///
+/// Only the settings which are set are changed.
+#[derive(Debug, Clone, Default)]
+pub struct SubscriptionSettings {
+    color: Option<Color>,
+    is_muted: Option<bool>,
+    pin_to_top: Option<bool>,
+    desktop_notifications: Option<bool>,
+    audible_notifications: Option<bool>,
+    push_notifications: Option<bool>,
+    email_notifications: Option<bool>,
+    wildcard_mentions_notify: Option<bool>,
+}
+
+impl SubscriptionSettings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the user's personal color for the stream.
+    pub fn color(&mut self, color: Color) -> &mut Self {
+        self.color = Some(color);
+        self
+    }
+    /// Mute or unmute the stream.
+    pub fn is_muted(&mut self, is_muted: bool) -> &mut Self {
+        self.is_muted = Some(is_muted);
+        self
+    }
+    /// Pin the stream to the top, or unpin it.
+    pub fn pin_to_top(&mut self, pin_to_top: bool) -> &mut Self {
+        self.pin_to_top = Some(pin_to_top);
+        self
+    }
+    pub fn desktop_notifications(&mut self, enabled: bool) -> &mut Self {
+        self.desktop_notifications = Some(enabled);
+        self
+    }
+    pub fn audible_notifications(&mut self, enabled: bool) -> &mut Self {
+        self.audible_notifications = Some(enabled);
+        self
+    }
+    pub fn push_notifications(&mut self, enabled: bool) -> &mut Self {
+        self.push_notifications = Some(enabled);
+        self
+    }
+    pub fn email_notifications(&mut self, enabled: bool) -> &mut Self {
+        self.email_notifications = Some(enabled);
+        self
+    }
+    pub fn wildcard_mentions_notify(&mut self, enabled: bool) -> &mut Self {
+        self.wildcard_mentions_notify = Some(enabled);
+        self
+    }
+
+    /// Get the changed settings as properties for a stream, in the format of
+    /// the API.
+    pub(crate) fn to_properties(&self, stream_id: u64) -> Vec<SubscriptionProperty> {
+        let color = self.color.map(|x| ("color", x.to_hex().into()));
+        let flags = [
+            ("is_muted", self.is_muted),
+            ("pin_to_top", self.pin_to_top),
+            ("desktop_notifications", self.desktop_notifications),
+            ("audible_notifications", self.audible_notifications),
+            ("push_notifications", self.push_notifications),
+            ("email_notifications", self.email_notifications),
+            ("wildcard_mentions_notify", self.wildcard_mentions_notify),
+        ];
+        let flags = flags
+            .iter()
+            .filter_map(|&(property, value)| Some((property, value?.into())));
+        color
+            .into_iter()
+            .chain(flags)
+            .map(|(property, value)| SubscriptionProperty {
+                stream_id,
+                property,
+                value,
+            })
+            .collect()
+    }
+}
+
+/// A change of one setting of a subscription.
+#[derive(Serialize, Debug)]
+pub(crate) struct SubscriptionProperty {
+    pub stream_id: u64,
+    pub property: &'static str,
+    pub value: serde_json::Value,
+}
+
+/// A request to change settings of subscriptions.
+#[derive(Serialize, Debug)]
+pub(crate) struct UpdateSubscriptionSettingsRequest {
+    #[serde(serialize_with = "crate::message::serialize_as_json_str")]
+    pub subscription_data: Vec<SubscriptionProperty>,
+}
+
+/// A stream to subscribe to, which is created if it doesn't exist.
+#[derive(Serialize, Debug)]
+pub(crate) struct NewSubscription<'a> {
+    pub name: &'a str,
+    pub description: &'a str,
+}
+
+/// A request to subscribe to streams.
+#[derive(Serialize, Debug)]
+pub(crate) struct SubscribeRequest<'a> {
+    #[serde(serialize_with = "crate::message::serialize_as_json_str")]
+    pub subscriptions: &'a [NewSubscription<'a>],
+    /// Whether streams which are created should be private.
+    pub invite_only: bool,
+}
+
+/// The response when subscribing to streams, for instance with
+/// `crate::Client::create_stream`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubscribeResponse {
+    /// A map from the email of each user who was subscribed to the names of
+    /// the streams they were subscribed to.
+    pub subscribed: HashMap<String, Vec<String>>,
+    /// A map from the email of each user who was already subscribed to the
+    /// names of the streams they were already subscribed to.
+    pub already_subscribed: HashMap<String, Vec<String>>,
+    /// The names of the streams which the user is not authorized to subscribe
+    /// to.
+    #[serde(default)]
+    pub unauthorized: Vec<String>,
+}
+
 #[derive(Deserialize, Debug)]
 pub(crate) struct GetSubscribedStreamsResponse {
     pub subscriptions: Vec<Subscription>,
@@ -207,6 +430,27 @@ pub struct Topic {
     pub name: String,
 }
 
+impl Topic {
+    /// Compare the last messages of two topics, with the most recently active
+    /// topic first.
+    ///
+    /// Note that this is the reverse of the derived `Ord` implementation.
+    pub fn cmp_by_recency(&self, other: &Self) -> Ordering {
+        other.max_id.cmp(&self.max_id)
+    }
+
+    /// Compare the names of two topics case insensitively, for sorting topics
+    /// alphabetically.
+    ///
+    /// Names which only differ in case are ordered by their exact names.
+    pub fn cmp_by_name(&self, other: &Self) -> Ordering {
+        self.name
+            .to_lowercase()
+            .cmp(&other.name.to_lowercase())
+            .then_with(|| self.name.cmp(&other.name))
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub(crate) struct TopicsInStreamResponse {
     pub topics: Vec<Topic>,
@@ -216,3 +460,127 @@ pub(crate) struct TopicsInStreamResponse {
 pub(crate) struct StreamId {
     pub stream_id: u64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stream with a name, created at a timestamp.
+    fn stream(id: u64, name: &str, date_created: i64) -> Stream {
+        serde_json::from_value(serde_json::json!({
+            "stream_id": id, "name": name, "description": "",
+            "rendered_description": "", "date_created": date_created,
+            "invite_only": false, "stream_post_policy": 1,
+            "message_retention_days": null,
+            "history_public_to_subscribers": true, "first_message_id": null,
+            "can_remove_subscribers": null,
+        }))
+        .unwrap()
+    }
+
+    /// A subscription to a stream with some weekly traffic.
+    fn subscription(stream: Stream, traffic: Option<u64>) -> Subscription {
+        Subscription {
+            stream,
+            desktop_notifications: None,
+            email_notifications: None,
+            wildcard_mentions_notify: None,
+            push_notifications: None,
+            audible_notifications: None,
+            pin_to_top: false,
+            email_address: String::new(),
+            is_muted: false,
+            is_web_public: false,
+            color: "#76ce90".to_string(),
+            stream_weekly_trafic: traffic,
+            subscribers: Vec::new(),
+        }
+    }
+
+    fn ids<'a>(streams: impl IntoIterator<Item = &'a Stream>) -> Vec<u64> {
+        streams.into_iter().map(|x| x.stream_id).collect()
+    }
+
+    #[test]
+    fn test_sort_streams_by_name() {
+        let mut streams = [
+            stream(1, "general", 30),
+            stream(2, "Announce", 20),
+            stream(3, "design", 10),
+            stream(4, "announce", 40),
+        ];
+        streams.sort_by(Stream::cmp_by_name);
+        assert_eq!(ids(&streams), [2, 4, 3, 1]);
+    }
+
+    #[test]
+    fn test_sort_streams_by_creation() {
+        let mut streams = [
+            stream(1, "general", 30),
+            stream(2, "announce", 20),
+            stream(3, "design", 10),
+        ];
+        streams.sort_by(Stream::cmp_by_creation);
+        assert_eq!(ids(&streams), [3, 2, 1]);
+    }
+
+    #[test]
+    fn test_sort_subscriptions_by_traffic() {
+        let mut subscriptions = [
+            subscription(stream(1, "general", 30), Some(120)),
+            subscription(stream(2, "announce", 20), Some(3)),
+            subscription(stream(3, "new", 10), None),
+        ];
+        subscriptions.sort_by(Subscription::cmp_by_traffic);
+        assert_eq!(ids(subscriptions.iter().map(|x| &x.stream)), [3, 2, 1]);
+    }
+
+    #[test]
+    fn test_sort_topics() {
+        let topic = |max_id: u64, name: &str| Topic {
+            max_id,
+            name: name.to_string(),
+        };
+        let mut topics = [
+            topic(12, "lunch"),
+            topic(40, "Design"),
+            topic(7, "announcements"),
+            topic(25, "design"),
+        ];
+        let names =
+            |topics: &[Topic]| -> Vec<String> { topics.iter().map(|x| x.name.clone()).collect() };
+        topics.sort_by(Topic::cmp_by_recency);
+        assert_eq!(
+            names(&topics),
+            ["Design", "design", "lunch", "announcements"]
+        );
+        topics.sort_by(Topic::cmp_by_name);
+        assert_eq!(
+            names(&topics),
+            ["announcements", "Design", "design", "lunch"]
+        );
+    }
+
+    #[test]
+    fn test_color_from_hex() {
+        assert_eq!(
+            Color::from_hex("#76ce90"),
+            Some(Color {
+                r: 0x76,
+                g: 0xce,
+                b: 0x90
+            })
+        );
+        assert_eq!(
+            Color::from_hex("#7C9"),
+            Some(Color {
+                r: 0x77,
+                g: 0xcc,
+                b: 0x99
+            })
+        );
+        for invalid in ["76ce90", "#76ce9", "#76ce9g", "#+1+2+3", "#", ""] {
+            assert_eq!(Color::from_hex(invalid), None, "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Types for requests and responses about users.
+use serde::{Deserialize, Serialize};
+
+/// A user, or bot, in the realm (organization).
+///
+/// Can be fetched with `crate::Client::get_users` or
+/// `crate::Client::get_user_by_email`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    /// The unique ID of the user, which is used to address private messages.
+    pub user_id: u64,
+    /// The Zulip API email address of the user or bot.
+    pub email: String,
+    /// The real email address of the user, if it is visible to the requesting
+    /// user.
+    #[serde(default)]
+    pub delivery_email: Option<String>,
+    /// The full name of the user or bot.
+    pub full_name: String,
+    /// Whether the user account is active (not deactivated).
+    #[serde(default = "default_true")]
+    pub is_active: bool,
+    /// Whether the user is an organization administrator or owner.
+    #[serde(default)]
+    pub is_admin: bool,
+    /// Whether the user is an organization owner.
+    #[serde(default)]
+    pub is_owner: bool,
+    /// Whether the user is a guest user.
+    #[serde(default)]
+    pub is_guest: bool,
+    /// Whether the user is a bot.
+    #[serde(default)]
+    pub is_bot: bool,
+    /// The role of the user in the organization, like 100 for owners, 200 for
+    /// administrators and 400 for members.
+    #[serde(default)]
+    pub role: Option<u64>,
+    /// The time zone of the user, like "Europe/Stockholm", or the empty string
+    /// if it is unknown.
+    #[serde(default)]
+    pub timezone: String,
+    /// The time when the user joined the organization, in ISO 8601 format.
+    #[serde(default)]
+    pub date_joined: Option<String>,
+    /// The URL of the avatar of the user, if it was requested.
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A wrapper around the response from get_users.
+#[derive(Deserialize, Debug)]
+pub(crate) struct GetUsersResponse {
+    pub members: Vec<User>,
+}
+
+/// A wrapper around the response from get_user_by_email.
+#[derive(Deserialize, Debug)]
+pub(crate) struct GetUserResponse {
+    pub user: User,
+}
+
+/// A group of users in the realm, which can be mentioned like `@*group*`.
+///
+/// Can be fetched with `crate::Client::get_user_groups`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UserGroup {
+    /// The unique ID of the group.
+    pub id: u64,
+    /// The name of the group.
+    pub name: String,
+    /// The description of the group.
+    pub description: String,
+    /// The IDs of the users in the group, not including members of subgroups.
+    pub members: Vec<u64>,
+    /// Whether this is a group managed by the server, like "role:members",
+    /// rather than one created by a user.
+    #[serde(default)]
+    pub is_system_group: bool,
+}
+
+/// A wrapper around the response from get_user_groups.
+#[derive(Deserialize, Debug)]
+pub(crate) struct GetUserGroupsResponse {
+    pub user_groups: Vec<UserGroup>,
+}
+
+/// A change of the status of the current user, sent with
+/// `crate::Client::update_status`.
+///
+/// Fields which are `None` are left unchanged.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserStatus {
+    /// The status text, like "lunch", or the empty string to clear it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_text: Option<String>,
+    /// Whether the user is away.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub away: Option<bool>,
+    /// The name of the emoji shown with the status, like "car", or the empty
+    /// string to clear it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emoji_name: Option<String>,
+}